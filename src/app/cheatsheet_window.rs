@@ -0,0 +1,81 @@
+use crate::cheatsheet::matches_query;
+use crate::commandlist::CommandEntry;
+use crossterm::event::KeyCode;
+
+pub struct CheatsheetState {
+    entries: Vec<CommandEntry>,
+    pub query: String,
+    /// index into the entries matching the query
+    pub selected_idx: Option<usize>,
+}
+
+impl CheatsheetState {
+    pub fn new(entries: Vec<CommandEntry>) -> CheatsheetState {
+        let selected_idx = if entries.is_empty() { None } else { Some(0) };
+        CheatsheetState {
+            entries,
+            query: String::new(),
+            selected_idx,
+        }
+    }
+
+    pub fn matching_entries(&self) -> Vec<&CommandEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| matches_query(entry, &self.query))
+            .collect()
+    }
+
+    pub fn selected_entry(&self) -> Option<&CommandEntry> {
+        self.selected_idx.and_then(|idx| self.matching_entries().get(idx).copied())
+    }
+
+    /// Typed characters refine the search, the arrow and page keys move the selection.
+    pub fn apply_event(&mut self, code: KeyCode) {
+        let matching_count = self.matching_entries().len();
+        match code {
+            KeyCode::Char(c) => self.set_query(format!("{}{}", self.query, c)),
+            KeyCode::Backspace => {
+                let mut query = self.query.clone();
+                query.pop();
+                self.set_query(query);
+            }
+            KeyCode::Up => self.selected_idx = self.selected_idx.map(|idx| idx.saturating_sub(1)),
+            KeyCode::PageUp => self.selected_idx = self.selected_idx.map(|idx| idx.saturating_sub(5)),
+            KeyCode::Down => self.selected_idx = self.selected_idx.map(|idx| (idx + 1).min(matching_count - 1)),
+            KeyCode::PageDown => self.selected_idx = self.selected_idx.map(|idx| (idx + 5).min(matching_count - 1)),
+            _ => {}
+        }
+    }
+
+    fn set_query(&mut self, query: String) {
+        self.query = query;
+        self.selected_idx = if self.matching_entries().is_empty() { None } else { Some(0) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_search_and_select() {
+        let entry = |line: &str| CommandEntry::new(vec![line.to_string()]);
+        let mut state = CheatsheetState::new(vec![entry("sort"), entry("sort -u"), entry("wc -l")]);
+        state.apply_event(KeyCode::Down);
+        state.apply_event(KeyCode::Down);
+        state.apply_event(KeyCode::Down);
+        assert_eq!(state.selected_entry(), Some(&entry("wc -l")));
+
+        for c in "sort".chars() {
+            state.apply_event(KeyCode::Char(c));
+        }
+        assert_eq!(state.matching_entries().len(), 2);
+        assert_eq!(state.selected_entry(), Some(&entry("sort")));
+        state.apply_event(KeyCode::Char('x'));
+        assert_eq!(state.selected_entry(), None);
+        state.apply_event(KeyCode::Down);
+        state.apply_event(KeyCode::Backspace);
+        assert_eq!(state.selected_entry(), Some(&entry("sort")));
+    }
+}
@@ -1,9 +1,41 @@
+use crate::command_evaluation::ExecutionMode;
 use crate::commandlist::CommandEntry;
 use crossterm::event::KeyCode;
 
+/// Order the entries of a list are shown in. Only the view is sorted, the stored order stays the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListSortOrder {
+    #[default]
+    Stored,
+    Alphabetical,
+    Length,
+}
+
+impl ListSortOrder {
+    fn next(self) -> ListSortOrder {
+        match self {
+            ListSortOrder::Stored => ListSortOrder::Alphabetical,
+            ListSortOrder::Alphabetical => ListSortOrder::Length,
+            ListSortOrder::Length => ListSortOrder::Stored,
+        }
+    }
+
+    /// Shown in the title of the list while it is sorted
+    pub fn label(self) -> Option<&'static str> {
+        match self {
+            ListSortOrder::Stored => None,
+            ListSortOrder::Alphabetical => Some("sorted alphabetically"),
+            ListSortOrder::Length => Some("sorted by length"),
+        }
+    }
+}
+
 pub struct CommandListState {
+    /// the entries in their stored order
     pub list: Vec<CommandEntry>,
+    /// position of the selected entry in the displayed order
     pub selected_idx: Option<usize>,
+    pub sort_order: ListSortOrder,
     recently_deleted: Vec<CommandEntry>,
 }
 
@@ -12,11 +44,88 @@ impl CommandListState {
         CommandListState {
             selected_idx: selected_idx.or(if list.is_empty() { None } else { Some(list.len() - 1) }),
             list,
+            sort_order: ListSortOrder::Stored,
             recently_deleted: Vec::new(),
         }
     }
+
+    /// Indices into the stored list, in the order the entries are displayed.
+    fn display_order(&self) -> Vec<usize> {
+        let mut order = (0..self.list.len()).collect::<Vec<_>>();
+        match self.sort_order {
+            ListSortOrder::Stored => {}
+            ListSortOrder::Alphabetical => order.sort_by_cached_key(|&idx| self.list[idx].as_string().to_lowercase()),
+            ListSortOrder::Length => order.sort_by_cached_key(|&idx| self.list[idx].as_string().chars().count()),
+        }
+        order
+    }
+
+    pub fn displayed_entries(&self) -> Vec<&CommandEntry> {
+        self.display_order().into_iter().map(|idx| &self.list[idx]).collect()
+    }
+
+    /// Index of the selected entry in the stored list.
+    pub fn selected_list_idx(&self) -> Option<usize> {
+        self.selected_idx.and_then(|idx| self.display_order().get(idx).copied())
+    }
+
     pub fn selected_entry(&self) -> Option<&CommandEntry> {
-        self.selected_idx.and_then(|idx| self.list.get(idx))
+        self.selected_list_idx().and_then(|idx| self.list.get(idx))
+    }
+
+    fn select_list_idx(&mut self, list_idx: usize) {
+        self.selected_idx = self.display_order().iter().position(|&idx| idx == list_idx);
+    }
+
+    /// Show the entries in the next sort order, keeping the selected entry selected.
+    pub fn cycle_sort_order(&mut self) {
+        let selected = self.selected_list_idx();
+        self.sort_order = self.sort_order.next();
+        if let Some(selected) = selected {
+            self.select_list_idx(selected);
+        }
+    }
+
+    /// Store the entries in the order they are displayed in.
+    pub fn apply_sort_order(&mut self) {
+        self.list = self.displayed_entries().into_iter().cloned().collect();
+        self.sort_order = ListSortOrder::Stored;
+    }
+
+    /// Move the selected entry one position up or down in the stored order, returning whether it moved.
+    /// Entries can only be moved while the list is shown in its stored order.
+    pub fn move_selected(&mut self, up: bool) -> bool {
+        let Some(selected_idx) = self.selected_idx else {
+            return false;
+        };
+        if self.sort_order != ListSortOrder::Stored {
+            return false;
+        }
+        let target_idx = if up {
+            selected_idx.checked_sub(1)
+        } else {
+            Some(selected_idx + 1)
+        };
+        match target_idx.filter(|&idx| idx < self.list.len()) {
+            Some(target_idx) => {
+                self.list.swap(selected_idx, target_idx);
+                self.selected_idx = Some(target_idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// cycle the preferred execution mode of the selected entry between none, isolated and unsafe
+    pub fn cycle_selected_execution_mode(&mut self) {
+        if let Some(entry) = self.selected_list_idx().and_then(|idx| self.list.get_mut(idx)) {
+            let next_mode = match entry.execution_mode() {
+                None => Some(ExecutionMode::Isolated),
+                Some(ExecutionMode::Isolated) => Some(ExecutionMode::Unsafe),
+                Some(ExecutionMode::Unsafe) => None,
+            };
+            entry.set_execution_mode(next_mode);
+        }
     }
 
     pub fn apply_event(&mut self, code: KeyCode) {
@@ -41,10 +150,10 @@ impl CommandListState {
                     if let Some(entry) = self.recently_deleted.pop() {
                         self.list.push(entry);
                     }
-                    self.selected_idx = Some(self.list.len() - 1);
+                    self.select_list_idx(self.list.len() - 1);
                 }
                 KeyCode::Delete | KeyCode::Backspace => {
-                    let deleted_entry = self.list.remove(selected_idx);
+                    let deleted_entry = self.list.remove(self.display_order()[selected_idx]);
                     self.recently_deleted.push(deleted_entry);
                     if self.list.is_empty() {
                         self.selected_idx = None;
@@ -58,3 +167,69 @@ impl CommandListState {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sort_order() {
+        let entries = ["ls -la", "cat", "Grep x"]
+            .iter()
+            .map(|x| CommandEntry::new(vec![x.to_string()]))
+            .collect::<Vec<_>>();
+        let mut state = CommandListState::new(entries, Some(0));
+        let displayed = |state: &CommandListState| state.displayed_entries().iter().map(|x| x.as_string()).collect::<Vec<_>>();
+
+        state.cycle_sort_order();
+        assert_eq!(displayed(&state), vec!["cat", "Grep x", "ls -la"]);
+        assert_eq!(
+            state.selected_entry().unwrap().as_string(),
+            "ls -la",
+            "the selection follows the entry"
+        );
+        state.cycle_sort_order();
+        assert_eq!(displayed(&state), vec!["cat", "ls -la", "Grep x"]);
+        assert_eq!(state.list[0].as_string(), "ls -la", "the stored order is kept");
+
+        state.apply_event(KeyCode::Delete);
+        assert_eq!(displayed(&state), vec!["cat", "Grep x"]);
+        state.apply_event(KeyCode::Char('u'));
+        assert_eq!(state.selected_entry().unwrap().as_string(), "ls -la");
+
+        state.apply_sort_order();
+        assert_eq!(state.sort_order, ListSortOrder::Stored);
+        assert_eq!(
+            state.list.iter().map(|x| x.as_string()).collect::<Vec<_>>(),
+            vec!["cat", "Grep x", "ls -la"]
+        );
+    }
+
+    #[test]
+    fn test_move_selected() {
+        let entries = ["a", "b", "c"]
+            .iter()
+            .map(|x| CommandEntry::new(vec![x.to_string()]))
+            .collect::<Vec<_>>();
+        let mut state = CommandListState::new(entries, Some(0));
+        let stored = |state: &CommandListState| state.list.iter().map(|x| x.as_string()).collect::<Vec<_>>();
+
+        assert!(!state.move_selected(true), "the first entry can't move up");
+        assert_eq!(stored(&state), vec!["a", "b", "c"]);
+        assert!(state.move_selected(false));
+        assert!(state.move_selected(false));
+        assert_eq!(stored(&state), vec!["b", "c", "a"]);
+        assert_eq!(state.selected_idx, Some(2), "the selection follows the entry");
+        assert!(!state.move_selected(false), "the last entry can't move down");
+        assert_eq!(stored(&state), vec!["b", "c", "a"]);
+        assert!(state.move_selected(true));
+        assert_eq!(stored(&state), vec!["b", "a", "c"]);
+
+        state.cycle_sort_order();
+        assert!(!state.move_selected(true), "entries can't be moved while the list is sorted");
+        assert_eq!(stored(&state), vec!["b", "a", "c"]);
+
+        let mut empty_state = CommandListState::new(Vec::new(), None);
+        assert!(!empty_state.move_selected(false));
+    }
+}
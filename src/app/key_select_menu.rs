@@ -1,14 +1,70 @@
 pub struct KeySelectMenu<T> {
     options: Vec<(char, String)>,
     pub menu_type: T,
+    /// title of the menu box
+    pub title: &'static str,
+    /// lines explaining the choice, shown above the options
+    pub heading: Vec<String>,
+    /// option highlighted with the arrow keys, whose full text is shown below the options
+    pub selected_idx: Option<usize>,
 }
 
 impl<T> KeySelectMenu<T> {
     pub fn new(options: Vec<(char, String)>, menu_type: T) -> Self {
-        Self { options, menu_type }
+        Self {
+            options,
+            menu_type,
+            title: "Open in",
+            heading: Vec::new(),
+            selected_idx: None,
+        }
+    }
+
+    pub fn with_heading(mut self, title: &'static str, heading: Vec<String>) -> Self {
+        self.title = title;
+        self.heading = heading;
+        self
     }
 
     pub fn option_list_strings(&self) -> impl Iterator<Item = String> + '_ {
         self.options.iter().map(|(c, s)| format!("{}: {}", c, s))
     }
+
+    /// Highlight the next option, or the previous one if `backwards` is set, wrapping around at the ends.
+    pub fn select_next(&mut self, backwards: bool) {
+        let count = self.options.len();
+        if count == 0 {
+            return;
+        }
+        self.selected_idx = Some(match (self.selected_idx, backwards) {
+            (None, false) => 0,
+            (None, true) => count - 1,
+            (Some(idx), false) => (idx + 1) % count,
+            (Some(idx), true) => (idx + count - 1) % count,
+        });
+    }
+
+    /// The key and text of the highlighted option
+    pub fn selected_option(&self) -> Option<&(char, String)> {
+        self.selected_idx.and_then(|idx| self.options.get(idx))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_select_next() {
+        let mut menu = KeySelectMenu::new(vec![('a', "less".into()), ('b', "jq . | less".into())], ());
+        assert_eq!(menu.selected_option(), None);
+        menu.select_next(true);
+        assert_eq!(menu.selected_option(), Some(&('b', "jq . | less".to_string())));
+        menu.select_next(false);
+        assert_eq!(menu.selected_option(), Some(&('a', "less".to_string())));
+
+        let mut empty = KeySelectMenu::<()>::new(Vec::new(), ());
+        empty.select_next(false);
+        assert_eq!(empty.selected_option(), None);
+    }
 }
@@ -1,15 +1,37 @@
-use crate::app::key_select_menu::KeySelectMenu;
-use crate::app::{App, CachedCommandPart, KeySelectMenuType};
-use crate::command_evaluation;
-use crate::lineeditor::{convert_keyevent_to_editorevent, EditorEvent};
-use crate::util::{StringExt, VecStringExt};
 use crate::CmdOutput;
 use crate::Stdio;
+use crate::app::key_select_menu::KeySelectMenu;
+use crate::app::{
+    App, BookmarkSidebar, CachedCommandPart, KeySelectMenuType, LayoutRects, OutputFilter, OutputSelection, StdinStream,
+    WATCH_INTERVALS, WindowState,
+};
+use crate::cheatsheet;
+use crate::command_evaluation;
+use crate::command_evaluation::{ExecutionMode, StdinSource, decode_output, merge_stderr_lines};
+use crate::commandlist::CommandEntry;
+use crate::env_expansion::describe_expansions;
+use crate::explain::{explain_command, pipeline_stage_prefixes};
+use crate::hooks::HookEvent;
+use crate::lineeditor::{EditorEvent, EditorState, convert_keyevent_to_editorevent};
+use crate::pipr_config::ReplaceNearDuplicates;
+use crate::recent_dirs::{quote_dir, referenced_dirs};
+use crate::snippets::Snippet;
+use crate::substitution::substitution_at;
+use crate::util::{StringExt, VecStringExt, filter_lines, shell_quote, shell_script, strip_ansi};
 
-use crossterm::event::{KeyCode, KeyModifiers};
+use crossbeam_channel::bounded;
+use crossterm::event::{KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
+use std::time::Duration;
+
+/// number of lines the output is scrolled by on PageUp / PageDown
+const OUTPUT_SCROLL_STEP: u16 = 10;
+/// number of lines the output is scrolled by per mouse wheel step
+const OUTPUT_MOUSE_SCROLL_STEP: u16 = 3;
 
 /// a state holder for the autocomplete menu
 #[derive(Debug)]
@@ -50,9 +72,8 @@ impl App {
     pub fn handle_key_select_menu_event(&mut self, key_select_menu: KeySelectMenu<KeySelectMenuType>, c: char) {
         match key_select_menu.menu_type {
             KeySelectMenuType::Snippets => {
-                if let Some(snippet) = self.config.snippets.get(&c) {
-                    self.input_state.insert_at_cursor(&snippet.text, false);
-                    self.input_state.cursor_col += snippet.cursor_offset;
+                if let Some(snippet) = self.config.snippets.get(&c).cloned() {
+                    self.insert_snippet(&snippet);
                 }
             }
             KeySelectMenuType::OpenWordIn(word) => {
@@ -67,6 +88,185 @@ impl App {
                     self.should_jump_to_other_cmd = Some((Some(output), command));
                 }
             }
+            KeySelectMenuType::OpenIn(command, output) => {
+                if let Some(target) = self.config.open_in_targets.get(&c) {
+                    let mut command = target.command.resolve_to_command(&shell_quote(&command));
+                    command.stdin(Stdio::piped());
+                    self.should_jump_to_other_cmd = Some((Some(output), command));
+                }
+            }
+            KeySelectMenuType::WatchInterval => {
+                if let Some((_, secs)) = WATCH_INTERVALS.iter().find(|(key, _)| *key == c) {
+                    self.watch_interval = Some(Duration::from_secs(*secs));
+                    self.execute_content();
+                }
+            }
+            KeySelectMenuType::ReplaceBookmark(idx, entry) => match c {
+                'r' => {
+                    self.bookmarks.replace_entry(idx, entry);
+                    self.run_bookmark_hook();
+                }
+                'a' => {
+                    self.bookmarks.push(entry);
+                    self.run_bookmark_hook();
+                }
+                _ => {}
+            },
+            KeySelectMenuType::OverwriteScript(path) => {
+                if c == 'y' {
+                    self.export_script(path);
+                }
+            }
+            KeySelectMenuType::ConfirmUnsafe => {
+                if c == 'y' {
+                    self.unsafe_confirmed = true;
+                    self.execute_entered_command();
+                } else {
+                    self.pending_past_output = None;
+                }
+            }
+            KeySelectMenuType::ConfirmUnsafeRerun => {
+                if c == 'y' {
+                    self.unsafe_confirmed = true;
+                    self.rerun_last_command();
+                }
+            }
+            KeySelectMenuType::ConfirmUnsafeMode => {
+                if c == 'y' {
+                    self.set_execution_mode(ExecutionMode::Unsafe);
+                }
+            }
+            KeySelectMenuType::PipelineStage(stages) => {
+                let stage_idx = c.to_digit(10).and_then(|digit| (digit as usize).checked_sub(1));
+                if let Some(stage_command) = stage_idx.and_then(|idx| stages.get(idx)) {
+                    self.run_pipeline_stage(stage_command, stage_idx.unwrap() + 1, stages.len());
+                }
+            }
+        }
+    }
+
+    /// Run the input, first asking for confirmation if it is flagged as unsafe, listing why it was flagged.
+    fn confirm_unsafe_or_execute(&mut self) {
+        let unsafe_matches = self.unsafe_input_matches();
+        if unsafe_matches.is_empty() {
+            self.execute_entered_command();
+            return;
+        }
+        self.open_unsafe_confirmation(&unsafe_matches, KeySelectMenuType::ConfirmUnsafe);
+    }
+
+    /// Run the input the user entered with Enter, once it was confirmed if needed.
+    fn execute_entered_command(&mut self) {
+        self.record_recent_dirs();
+        self.run_hook(HookEvent::Execute, &[]);
+        self.execute_content();
+    }
+
+    /// bookmark the current command, or remove it if it is bookmarked already.
+    /// Depending on the config, a similar existing bookmark gets replaced, or the user is asked whether to replace it.
+    fn toggle_bookmark(&mut self) {
+        let entry = self.input_state.content_to_commandentry();
+        let near_duplicate = match self.config.bookmark_replace_near_duplicates {
+            ReplaceNearDuplicates::Never => None,
+            _ if self.bookmarks.entries().contains(&entry) => None,
+            _ => self
+                .bookmarks
+                .find_near_duplicate(&entry, self.config.bookmark_near_duplicate_match),
+        };
+        match near_duplicate {
+            Some(idx) if self.config.bookmark_replace_near_duplicates == ReplaceNearDuplicates::Always => {
+                self.bookmarks.replace_entry(idx, entry);
+                self.run_bookmark_hook();
+            }
+            Some(idx) => {
+                let existing = self.bookmarks.entries()[idx].lines().first().cloned().unwrap_or_default();
+                self.opened_key_select_menu = Some(KeySelectMenu::new(
+                    vec![
+                        ('r', format!("Replace the similar bookmark `{}`", existing)),
+                        ('a', "Add as a new bookmark".to_string()),
+                    ],
+                    KeySelectMenuType::ReplaceBookmark(idx, entry),
+                ));
+            }
+            None => {
+                let is_new_bookmark = !self.bookmarks.entries().contains(&entry);
+                self.bookmarks.toggle_entry(entry);
+                if is_new_bookmark {
+                    self.run_bookmark_hook();
+                }
+            }
+        }
+    }
+
+    /// Bookmark the command together with its current output, updating the saved output if it is bookmarked already.
+    fn bookmark_with_output(&mut self) {
+        if self.is_empty_command(self.input_state.content_lines()) {
+            return;
+        }
+        let output_is_current = self.input_state.content_str() == self.last_executed_cmd
+            && self.command_error.is_empty()
+            && self.is_processing_state.is_none()
+            && self.partial_run.is_none();
+        if !output_is_current {
+            self.notification = Some("Run the command first, to bookmark it with its output".to_string());
+            return;
+        }
+        let entry = self.input_state.content_to_commandentry().with_output(&self.command_output);
+        match self.bookmarks.entries().iter().position(|existing| *existing == entry) {
+            Some(idx) => self.bookmarks.replace_entry(idx, entry),
+            None => {
+                self.bookmarks.push(entry);
+            }
+        }
+        self.run_bookmark_hook();
+        self.notification = Some("Bookmarked the command with its output".to_string());
+    }
+
+    /// open the menu to choose the stage of the pipeline to run the command up to
+    fn open_pipeline_stage_menu(&mut self) {
+        let command = self.command_from_lines(self.input_state.content_lines());
+        let stages = pipeline_stage_prefixes(&command);
+        if stages.is_empty() {
+            self.notification = Some("The command has no piped stages".into());
+            return;
+        }
+        self.opened_key_select_menu = Some(KeySelectMenu::new(
+            // only single digit keys are available
+            stages
+                .iter()
+                .take(9)
+                .enumerate()
+                .map(|(idx, stage)| (char::from(b'1' + idx as u8), stage.clone()))
+                .collect(),
+            KeySelectMenuType::PipelineStage(stages),
+        ));
+    }
+
+    /// stop watching, or open the menu to choose the interval to start watching in
+    fn toggle_watch_mode(&mut self) {
+        if self.watch_interval.take().is_none() {
+            self.opened_key_select_menu = Some(KeySelectMenu::new(
+                WATCH_INTERVALS
+                    .iter()
+                    .map(|(key, secs)| (*key, format!("Every {}s", secs)))
+                    .collect(),
+                KeySelectMenuType::WatchInterval,
+            ));
+        }
+    }
+
+    fn insert_snippet(&mut self, snippet: &Snippet) {
+        self.input_state.insert_at_cursor(&snippet.text, false);
+        self.input_state.cursor_col += snippet.cursor_offset;
+    }
+
+    /// Insert the command of a cheatsheet entry like a snippet. Commands spanning multiple lines are inserted as they are.
+    pub fn insert_cheatsheet_entry(&mut self, entry: &CommandEntry) {
+        let snippet = Snippet::parse(&cheatsheet::command(entry));
+        if snippet.text.contains('\n') {
+            self.input_state.insert_text(&snippet.text);
+        } else {
+            self.insert_snippet(&snippet);
         }
     }
 
@@ -86,9 +286,9 @@ impl App {
         }
 
         let command_result = command_evaluation::execute_command_blocking(
-            &self.execution_handler.shell_command, 
-            &command_to_cache.join(" "),
-            self.execution_handler.execution_mode
+            &self.execution_handler.shell_command,
+            &self.wrapped_command(command_to_cache.join(" ")),
+            self.execution_handler.execution_mode,
         );
 
         if self.input_state.cursor_col < self.input_state.current_line().len() {
@@ -108,23 +308,242 @@ impl App {
         }
     }
 
-    fn open_autocomplete_menu(&mut self) {
+    /// Show what the variables in the current line expand to, in the environment commands are run with.
+    /// Variables set in the persistent session aren't known to pipr, and are thus shown as not set.
+    fn preview_variables(&mut self) {
+        let mut env = std::env::vars().collect::<HashMap<_, _>>();
+        env.extend(self.command_env());
+        let expansions = describe_expansions(self.input_state.current_line(), &env);
+        if expansions.is_empty() {
+            self.notification = Some("The current line refers to no variables".to_string());
+            return;
+        }
+        self.window_state = WindowState::TextView("Variables".to_string(), expansions.join("\n"));
+    }
+
+    /// The output lines as they are shown, without color codes, to select one to insert into the input from.
+    pub fn selectable_output_lines(&self) -> Vec<String> {
+        let output = match self.shown_past_output() {
+            Some(past_output) => past_output.output.as_str(),
+            None => self
+                .pretty_json_output
+                .as_deref()
+                .filter(|_| self.pretty_json)
+                .unwrap_or(&self.command_output),
+        };
+        let filtered_output = self
+            .output_filter
+            .as_ref()
+            .filter(|filter| !filter.pattern.is_empty())
+            .and_then(|filter| filter_lines(output, &filter.pattern, self.config.output_filter_regex).ok());
+        strip_ansi(filtered_output.as_deref().unwrap_or(output))
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The selected output line, or the selected word of it.
+    pub fn selected_output_text(&self) -> Option<String> {
+        let selection = self.output_selection.as_ref()?;
+        let line = self.selectable_output_lines().into_iter().nth(selection.line)?;
+        match selection.word {
+            Some(word_idx) => line.split_whitespace().nth(word_idx).map(str::to_string),
+            None => Some(line.trim().to_string()),
+        }
+    }
+
+    fn start_output_selection(&mut self) {
+        if self.output_hex_view || self.selectable_output_lines().is_empty() {
+            self.notification = Some("There is no output to insert from".to_string());
+            return;
+        }
+        // hidden lines couldn't be selected
+        self.output_expanded = true;
+        let line = (self.output_scroll as usize).min(self.selectable_output_lines().len() - 1);
+        self.output_selection = Some(OutputSelection { line, word: None });
+        self.output_selection_changed();
+    }
+
+    fn handle_output_selection_event(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        let lines = self.selectable_output_lines();
+        let Some(selection) = self.output_selection.as_mut() else {
+            return;
+        };
+        let word_count = lines.get(selection.line).map_or(0, |line| line.split_whitespace().count());
+        match code {
+            KeyCode::Esc => self.output_selection = None,
+            KeyCode::Char('y') if modifiers.contains(KeyModifiers::ALT) => self.output_selection = None,
+            KeyCode::Enter => {
+                if let Some(text) = self.selected_output_text().filter(|text| !text.is_empty()) {
+                    self.input_state.insert_at_cursor(&text, true);
+                }
+                self.output_selection = None;
+            }
+            KeyCode::Up => {
+                selection.line = selection.line.saturating_sub(1);
+                selection.word = None;
+            }
+            KeyCode::Down => {
+                selection.line = (selection.line + 1).min(lines.len().saturating_sub(1));
+                selection.word = None;
+            }
+            KeyCode::Right => {
+                selection.word = match selection.word {
+                    None if word_count > 0 => Some(0),
+                    Some(word_idx) if word_idx + 1 < word_count => Some(word_idx + 1),
+                    _ => None,
+                }
+            }
+            KeyCode::Left => {
+                selection.word = match selection.word {
+                    None => word_count.checked_sub(1),
+                    Some(word_idx) => word_idx.checked_sub(1),
+                }
+            }
+            _ => {}
+        }
+        self.output_selection_changed();
+    }
+
+    /// Scroll the output so the selected line stays in view.
+    fn output_selection_changed(&mut self) {
+        let Some(line) = self.output_selection.as_ref().map(|selection| selection.line as u16) else {
+            return;
+        };
+        let visible_lines = self.layout.output.height.saturating_sub(2).max(1);
+        if line < self.output_scroll {
+            self.output_scroll = line;
+        } else if line >= self.output_scroll + visible_lines {
+            self.output_scroll = line + 1 - visible_lines;
+        }
+    }
+
+    /// Run the innermost `$(...)` at the cursor on its own in the background, to show what it expands to once it finished.
+    fn preview_substitution(&mut self) {
+        let Some(substitution) = substitution_at(self.input_state.current_line(), self.input_state.cursor_col) else {
+            self.notification = Some("The cursor is not within a $(...)".to_string());
+            return;
+        };
+        let substitution = substitution.to_string();
+        let shell = command_evaluation::shell_with_startup(&self.execution_handler.shell_command, self.config.shell_startup);
+        let command = self.wrapped_command(substitution.clone());
+        let (execution_mode, timeout) = (self.execution_mode(), self.config.cmd_timeout);
+        let (sender, receiver) = bounded(1);
+        std::thread::spawn(move || {
+            let result = command_evaluation::execute_command_to_completion(&shell, &command, execution_mode, timeout);
+            let expansion = match result {
+                // like the shell, drop the trailing newlines
+                Ok(finished) if finished.exit_code == Some(0) => {
+                    decode_output(&finished.stdout).trim_end_matches('\n').to_string()
+                }
+                Ok(finished) => format!(
+                    "Exited with code {}\n\n{}",
+                    finished.exit_code.map_or("none".to_string(), |code| code.to_string()),
+                    decode_output(&finished.stderr)
+                ),
+                Err(err) => err.to_string(),
+            };
+            sender.send(format!("$({})\n\nexpands to:\n\n{}", substitution, expansion))
+        });
+        self.substitution_preview_receiver = Some(receiver);
+    }
+
+    /// store the current output to be used as stdin for the next commands, and start with a fresh input.
+    fn prime_stdin_with_output(&mut self) {
+        // a failed command only leaves its stderr behind, which is shown instead of the stderr of the last output,
+        // so that is all there is to merge as well
+        let failed = self.last_exit_code.is_some_and(|code| code != 0);
+        let lines = match self.stdin_stream {
+            StdinStream::Stdout => self.command_output.lines().map(String::from).collect(),
+            StdinStream::Stderr | StdinStream::Both if failed => self.command_error.lines().map(String::from).collect(),
+            StdinStream::Stderr => self.command_stderr_lines.iter().map(|line| line.text.clone()).collect(),
+            StdinStream::Both => merge_stderr_lines(&self.command_output_bytes, &self.command_stderr_lines, "")
+                .lines()
+                .map(String::from)
+                .collect(),
+        };
+        self.prime_stdin(lines, Some(self.stdin_stream));
+    }
+
+    /// store the lines to be used as stdin for the next commands, and start with a fresh input.
+    pub fn prime_stdin(&mut self, lines: Vec<String>, stream: Option<StdinStream>) {
+        self.primed_stdin = Some(StdinSource::Lines(lines));
+        self.primed_stdin_stream = stream;
+        self.push_input_to_history();
+        self.history_idx = None;
+        self.cached_command_part = None;
+        self.input_state.apply_event(EditorEvent::Clear);
+    }
+
+    /// Offer path completions for the word at the cursor.
+    /// When triggered explicitly, a single completion is inserted right away instead of being shown.
+    fn open_autocomplete_menu(&mut self, explicit: bool) {
         let current_line = self.input_state.current_line().to_string();
         let hovered_word = current_line.word_at_idx(self.input_state.cursor_col);
         let hovered_char = self.input_state.hovered_char();
         if hovered_char.is_none() || hovered_char == Some(" ") || hovered_char == Some("") {
             let hovered_word = hovered_word.unwrap_or_default();
-            if let Some(completions) = provide_path_autocomplete(hovered_word) {
-                if completions.len() == 1 {
+            // automatic completion never triggers on an empty word, as that would open the menu after every space
+            let min_prefix_length = if explicit {
+                self.config.autocomplete_min_prefix_length
+            } else {
+                self.config.autocomplete_min_prefix_length.max(1)
+            };
+            if hovered_word.chars().count() < min_prefix_length {
+                return;
+            }
+            if let Some(mut completions) = provide_path_autocomplete(hovered_word) {
+                if self.config.autocomplete_max_options > 0 {
+                    completions.truncate(self.config.autocomplete_max_options);
+                }
+                if explicit && completions.len() == 1 {
                     let completed_value = completions.first().unwrap().trim_start_matches(hovered_word);
                     self.input_state.insert_at_cursor(completed_value, true);
-                } else if completions.len() > 1 {
+                } else if explicit || completions.iter().any(|completion| completion != hovered_word) {
                     self.autocomplete_state = AutocompleteState::from_options(hovered_word.to_string(), completions);
                 }
             }
         }
     }
 
+    /// Remember the directories the input refers to, moving ones that are remembered already to the end.
+    fn record_recent_dirs(&mut self) {
+        if self.config.recent_dirs_size == 0 {
+            return;
+        }
+        let Ok(cwd) = std::env::current_dir() else {
+            return;
+        };
+        let command = self.command_from_lines(self.input_state.content_lines());
+        for dir in referenced_dirs(&command, &cwd) {
+            let entry = CommandEntry::new(vec![dir]);
+            self.recent_dirs.remove_entry(&entry);
+            self.recent_dirs.push(entry);
+        }
+    }
+
+    /// Offer the recent directories containing the word before the cursor, the most recent first.
+    /// The chosen directory replaces that word, quoted if needed.
+    fn open_recent_dirs_menu(&mut self) {
+        let current_line = self.input_state.current_line().to_string();
+        let hovered_char = self.input_state.hovered_char();
+        let word = match hovered_char {
+            None | Some(" ") | Some("") => current_line.word_at_idx(self.input_state.cursor_col).unwrap_or_default(),
+            _ => "",
+        };
+        let dirs = self
+            .recent_dirs
+            .as_strings()
+            .into_iter()
+            .rev()
+            .filter(|dir| dir.contains(word));
+        let dirs = dirs.map(|dir| quote_dir(&dir)).collect::<Vec<_>>();
+        if dirs.is_empty() {
+            self.notification = Some("No recent directories".to_string());
+        }
+        self.autocomplete_state = AutocompleteState::from_options(word.to_string(), dirs);
+    }
+
     fn open_helpviewer(&mut self) {
         let current_line = self.input_state.current_line();
         let hovered_word = current_line.word_at_idx(self.input_state.cursor_col);
@@ -136,36 +555,220 @@ impl App {
         }
     }
 
+    /// the current output, in the form it should be passed on to other programs
+    fn exported_output(&self) -> String {
+        if self.config.keep_ansi_on_export {
+            self.command_output.to_owned()
+        } else {
+            strip_ansi(&self.command_output)
+        }
+    }
+
     fn open_outputviewer(&mut self) {
-        let current_output = self.command_output.to_owned();
+        let current_output = self.exported_output();
         let output_viewers = &self.config.output_viewers;
         let options = output_viewers.iter().map(|(&k, v)| (k, v.to_owned())).collect();
         let key_select_menu = KeySelectMenu::new(options, KeySelectMenuType::OpenOutputIn(current_output));
         self.opened_key_select_menu = Some(key_select_menu);
     }
 
+    fn open_open_in_menu(&mut self) {
+        let targets = &self.config.open_in_targets;
+        if targets.is_empty() {
+            return;
+        }
+        let options = targets.iter().map(|(&k, v)| (k, v.label.to_owned())).collect();
+        let menu_type = KeySelectMenuType::OpenIn(self.input_state.content_str(), self.exported_output());
+        self.opened_key_select_menu = Some(KeySelectMenu::new(options, menu_type));
+    }
+
+    /// export the command to the typed in path, asking before overwriting an existing file.
+    fn confirm_script_export(&mut self, path: &str) {
+        let path = match path.strip_prefix("~/") {
+            Some(relative) => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(relative),
+            None => PathBuf::from(path),
+        };
+        if path.exists() {
+            self.opened_key_select_menu = Some(KeySelectMenu::new(
+                vec![('y', format!("Overwrite {}", path.display())), ('n', "Cancel".to_string())],
+                KeySelectMenuType::OverwriteScript(path),
+            ));
+        } else {
+            self.export_script(path);
+        }
+    }
+
+    /// write the command into an executable shell script.
+    fn export_script(&mut self, path: PathBuf) {
+        let command = self.command_from_lines(self.input_state.content_lines());
+        let script = shell_script(&command, &self.config.script_shebang, &self.config.script_header);
+        let result = std::fs::write(&path, script).and_then(|_| {
+            let mut permissions = std::fs::metadata(&path)?.permissions();
+            permissions.set_mode(permissions.mode() | 0o111);
+            std::fs::set_permissions(&path, permissions)
+        });
+        self.notification = Some(match result {
+            Ok(()) => format!("Exported the command to {}", path.display()),
+            Err(err) => format!("Could not export the command to {}: {}", path.display(), err),
+        });
+    }
+
+    /// toggle running commands elevated. When turning it on, the user is asked for their password on the terminal first,
+    /// so the following commands can run without asking.
+    fn toggle_elevation(&mut self) {
+        if self.elevated {
+            self.elevated = false;
+            return;
+        }
+        if self.execution_mode() == ExecutionMode::Isolated {
+            self.notification = Some("Elevated commands need isolation to be disabled (--no-isolation)".into());
+            return;
+        }
+        let mut authentication = Command::new(&self.config.elevation_command);
+        authentication.arg("true");
+        self.should_jump_to_other_cmd = Some((None, authentication));
+        self.elevation_pending = true;
+    }
+
+    /// called once a program pipr handed the terminal to exited. Pending elevation is only enabled if it authenticated.
+    pub fn on_other_cmd_exit(&mut self, success: bool) {
+        if !std::mem::take(&mut self.elevation_pending) {
+            return;
+        }
+        self.elevated = success;
+        if !success {
+            self.notification = Some(format!(
+                "Authentication with {} failed, commands don't run elevated",
+                self.config.elevation_command
+            ));
+        }
+    }
+
+    /// type the current command into another tmux pane, without submitting it.
+    fn send_to_tmux(&mut self) {
+        if std::env::var_os("TMUX").is_none() {
+            self.notification = Some("Not running inside tmux".into());
+            return;
+        }
+        let pane = &self.config.tmux_target_pane;
+        let result = Command::new("tmux")
+            .args(["send-keys", "-t", pane, "-l", &self.input_state.content_str()])
+            .output();
+        self.notification = Some(match result {
+            Ok(output) if output.status.success() => format!("Sent command to tmux pane {}", pane),
+            Ok(output) => format!("tmux: {}", String::from_utf8_lossy(&output.stderr).trim()),
+            Err(err) => format!("Could not run tmux: {}", err),
+        });
+    }
+
+    /// Lines to scroll the output by on PageUp / PageDown, keeping `scrolloff` lines of the previous page in view
+    /// if the output area is too small for a full step.
+    fn output_page_step(&self) -> u16 {
+        let visible_lines = self.layout.output.height.saturating_sub(2);
+        let max_step = visible_lines.saturating_sub(self.config.scrolloff.try_into().unwrap_or(u16::MAX));
+        OUTPUT_SCROLL_STEP.min(max_step).max(1)
+    }
+
     pub fn handle_main_window_tui_event(&mut self, code: KeyCode, modifiers: KeyModifiers) {
         let control_pressed = modifiers.contains(KeyModifiers::CONTROL);
 
+        if let Some(output_filter) = self.output_filter.as_mut().filter(|filter| filter.editing) {
+            match code {
+                KeyCode::Esc => self.output_filter = None,
+                KeyCode::Enter if output_filter.pattern.is_empty() => self.output_filter = None,
+                KeyCode::Enter => output_filter.editing = false,
+                KeyCode::Char('f') if control_pressed => self.output_filter = None,
+                KeyCode::Backspace => {
+                    output_filter.pattern.pop();
+                }
+                KeyCode::Char(c) if !control_pressed => output_filter.pattern.push(c),
+                _ => {}
+            }
+            self.output_scroll = 0;
+            return;
+        }
+
+        if self.output_selection.is_some() {
+            self.handle_output_selection_event(code, modifiers);
+            return;
+        }
+
+        if let Some(path) = self.script_export_path.as_mut() {
+            match code {
+                KeyCode::Esc => self.script_export_path = None,
+                KeyCode::Enter => {
+                    let path = std::mem::take(path);
+                    self.script_export_path = None;
+                    if !path.is_empty() {
+                        self.confirm_script_export(&path);
+                    }
+                }
+                KeyCode::Backspace => {
+                    path.pop();
+                }
+                KeyCode::Char(c) if !control_pressed => path.push(c),
+                _ => {}
+            }
+            return;
+        }
+
         if let Some(autocomplete_state) = self.autocomplete_state.as_mut() {
             match code {
                 KeyCode::Tab | KeyCode::Down => autocomplete_state.cycle_selected(),
                 KeyCode::BackTab | KeyCode::Up => autocomplete_state.cycle_selected_backwards(),
-                KeyCode::Enter => {
-                    let completed_value = autocomplete_state
-                        .selected()
-                        .trim_start_matches(&autocomplete_state.original_prompt);
-                    self.input_state.insert_at_cursor(completed_value, true);
+                KeyCode::Enter => self.apply_autocomplete(),
+                KeyCode::Esc => self.autocomplete_state = None,
+                // any other key closes the menu and is handled as usual, so typing can just continue
+                _ => {
                     self.autocomplete_state = None;
+                    self.handle_main_window_tui_event(code, modifiers);
                 }
-                _ => self.autocomplete_state = None,
             }
             return;
         }
 
-        if let Some(key_select_menu) = self.opened_key_select_menu.take() {
-            if let KeyCode::Char(c) = code {
-                self.handle_key_select_menu_event(key_select_menu, c);
+        if let Some(mut key_select_menu) = self.opened_key_select_menu.take() {
+            match code {
+                KeyCode::Char(c) => self.handle_key_select_menu_event(key_select_menu, c),
+                KeyCode::Up | KeyCode::Down => {
+                    key_select_menu.select_next(code == KeyCode::Up);
+                    self.opened_key_select_menu = Some(key_select_menu);
+                }
+                KeyCode::Enter => {
+                    if let Some(&(c, _)) = key_select_menu.selected_option() {
+                        self.handle_key_select_menu_event(key_select_menu, c);
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        if let Some(sidebar) = self.bookmark_sidebar.as_mut().filter(|sidebar| sidebar.focused) {
+            let mut state = sidebar.list_state(&self.bookmarks);
+            match code {
+                KeyCode::Char('b') if modifiers.contains(KeyModifiers::ALT) => self.bookmark_sidebar = None,
+                KeyCode::Esc => sidebar.focused = false,
+                KeyCode::Enter => {
+                    if let Some(entry) = state.selected_entry() {
+                        self.input_state.load_commandentry(entry);
+                        self.entry_execution_mode = entry.execution_mode();
+                        self.cached_command_part = None;
+                    }
+                    sidebar.focused = false;
+                }
+                KeyCode::Up
+                | KeyCode::Down
+                | KeyCode::PageUp
+                | KeyCode::PageDown
+                | KeyCode::Char('k')
+                | KeyCode::Char('j')
+                | KeyCode::Char('g')
+                | KeyCode::Char('G') => {
+                    state.apply_event(code);
+                    sidebar.selected_idx = state.selected_idx;
+                }
+                _ => {}
             }
             return;
         }
@@ -175,17 +778,106 @@ impl App {
             KeyCode::Char('q') | KeyCode::Char('c') if control_pressed => self.set_should_quit(),
             KeyCode::F(2) => self.autoeval_mode = !self.autoeval_mode,
             KeyCode::F(3) => self.paranoid_history_mode = !self.paranoid_history_mode,
-            KeyCode::Tab => self.open_autocomplete_menu(),
+            KeyCode::Char('t') if modifiers.contains(KeyModifiers::ALT) => self.pty_mode = !self.pty_mode,
+            KeyCode::Char('s') if modifiers.contains(KeyModifiers::ALT) => self.persistent_session = !self.persistent_session,
+            KeyCode::Char('v') if modifiers.contains(KeyModifiers::ALT) => self.show_whitespace = !self.show_whitespace,
+            KeyCode::Left if modifiers.contains(KeyModifiers::ALT) => self.page_past_outputs(true),
+            KeyCode::Right if modifiers.contains(KeyModifiers::ALT) => self.page_past_outputs(false),
+            KeyCode::Char('h') if modifiers.contains(KeyModifiers::ALT) => {
+                self.config.highlighting_enabled = !self.config.highlighting_enabled
+            }
+            KeyCode::Char('w') if modifiers.contains(KeyModifiers::ALT) => self.toggle_watch_mode(),
+            KeyCode::Char('x') if modifiers.contains(KeyModifiers::ALT) => {
+                self.output_hex_view = !self.output_hex_view;
+                self.output_scroll = 0;
+            }
+            KeyCode::Char('j') if modifiers.contains(KeyModifiers::ALT) => {
+                self.pretty_json = !self.pretty_json;
+                self.output_scroll = 0;
+                self.format_json_output();
+            }
+            KeyCode::Char('e') if modifiers.contains(KeyModifiers::ALT) => {
+                let command = self.command_from_lines(self.input_state.content_lines());
+                self.window_state = WindowState::TextView("Explanation".to_string(), explain_command(&command));
+            }
+            KeyCode::Char('p') if modifiers.contains(KeyModifiers::ALT) => self.open_pipeline_stage_menu(),
+            KeyCode::Char('m') if modifiers.contains(KeyModifiers::ALT) => self.toggle_execution_mode(),
+            KeyCode::Char('g') if modifiers.contains(KeyModifiers::ALT) => self.open_recent_dirs_menu(),
+            KeyCode::Char('d') if modifiers.contains(KeyModifiers::ALT) => self.preview_substitution(),
+            KeyCode::Char('n') if modifiers.contains(KeyModifiers::ALT) => self.preview_variables(),
+            KeyCode::Char('y') if modifiers.contains(KeyModifiers::ALT) => self.start_output_selection(),
+            KeyCode::Char('a') if modifiers.contains(KeyModifiers::ALT) => self.toggle_elevation(),
+            KeyCode::Char('r') if modifiers.contains(KeyModifiers::ALT) => {
+                self.skip_output_cache = true;
+                self.execute_content();
+            }
+            KeyCode::Char('l') if modifiers.contains(KeyModifiers::ALT) => self.rerun_last_command(),
+            KeyCode::Char('b') if modifiers.contains(KeyModifiers::ALT) => {
+                self.bookmark_sidebar.get_or_insert_with(BookmarkSidebar::default).focused = true;
+            }
+            KeyCode::Char('i') if modifiers.contains(KeyModifiers::ALT) => {
+                let report = command_evaluation::sandbox_report(&self.execution_handler.shell_command, self.execution_mode());
+                self.window_state = WindowState::TextView("Sandbox".to_string(), report);
+            }
+            KeyCode::Char(c @ '1'..='9') if modifiers.contains(KeyModifiers::ALT) => {
+                let favorite_idx = c.to_digit(10).unwrap() as usize - 1;
+                if let Some(favorite) = self.config.favorites.get(favorite_idx).cloned() {
+                    self.insert_snippet(&favorite);
+                }
+            }
+            KeyCode::Tab => self.open_autocomplete_menu(true),
             KeyCode::F(5) => self.open_helpviewer(),
             KeyCode::F(6) => self.open_outputviewer(),
+            KeyCode::Char('f') if control_pressed => {
+                self.output_filter = match self.output_filter {
+                    Some(_) => None,
+                    None => Some(OutputFilter {
+                        pattern: String::new(),
+                        editing: true,
+                    }),
+                };
+                self.output_scroll = 0;
+            }
+            KeyCode::Char('o') if control_pressed => self.open_open_in_menu(),
+            KeyCode::Char('t') if control_pressed => self.send_to_tmux(),
+            KeyCode::Char('g') if control_pressed => self.script_export_path = Some(String::new()),
             KeyCode::F(7) => self.do_cache_command_part(),
+            KeyCode::F(8) => {
+                self.output_expanded = !self.output_expanded;
+                self.output_scroll = 0;
+            }
+            KeyCode::F(9) if modifiers.contains(KeyModifiers::SHIFT) => self.primed_stdin = None,
+            KeyCode::F(9) if modifiers.contains(KeyModifiers::ALT) => {
+                self.stdin_stream = self.stdin_stream.next();
+                self.notification = Some(format!("F9 uses {} as stdin", self.stdin_stream.name()));
+            }
+            KeyCode::F(9) => self.prime_stdin_with_output(),
+            KeyCode::Char('z') if modifiers.contains(KeyModifiers::ALT) => {
+                let mut scratch = EditorState::new().with_tab_width(self.config.tab_width);
+                scratch.set_content(self.command_output.lines().map(String::from).collect());
+                self.window_state = WindowState::Scratch(scratch);
+            }
+            KeyCode::Char('f') if modifiers.contains(KeyModifiers::ALT) => {
+                self.output_follow = !self.output_follow;
+                if self.output_follow {
+                    self.output_expanded = true;
+                    self.fit_output_scroll();
+                }
+            }
+            KeyCode::PageUp => self.output_scroll = self.output_scroll.saturating_sub(self.output_page_step()),
+            KeyCode::PageDown => {
+                self.output_scroll = self.output_scroll.saturating_add(self.output_page_step());
+                self.fit_output_scroll();
+            }
 
-            KeyCode::Char('s') if control_pressed => self.bookmarks.toggle_entry(self.input_state.content_to_commandentry()),
+            KeyCode::Char('s') if control_pressed => self.toggle_bookmark(),
+            KeyCode::Char('o') if modifiers.contains(KeyModifiers::ALT) => self.bookmark_with_output(),
             KeyCode::Char('p') if control_pressed => self.apply_history_prev(),
             KeyCode::Char('n') if control_pressed => self.apply_history_next(),
             KeyCode::Char('x') if control_pressed => {
-                self.history.push(self.input_state.content_to_commandentry());
+                self.push_input_to_history();
                 self.history_idx = None;
+                self.entry_execution_mode = None;
                 self.input_state.apply_event(EditorEvent::Clear);
             }
 
@@ -195,47 +887,154 @@ impl App {
                     KeySelectMenuType::Snippets,
                 ));
             }
-            KeyCode::Enter if !modifiers.contains(KeyModifiers::ALT) => {
-                self.history.push(self.input_state.content_to_commandentry());
-                self.execute_content();
-            }
+            KeyCode::Enter if self.enter_executes(modifiers) => match self.input_state.selected_text() {
+                Some(selection) => self.run_selection(&selection),
+                None if self.is_empty_command(self.input_state.content_lines()) => {
+                    if self.config.notify_on_empty_command {
+                        self.notification = Some("Nothing to run".to_string());
+                    }
+                }
+                None => {
+                    self.push_input_to_history();
+                    self.keep_next_output();
+                    self.confirm_unsafe_or_execute();
+                }
+            },
 
             _ => {
-                if let Some(editor_event) = convert_keyevent_to_editorevent(code, modifiers) {
+                // every enter that doesn't run the command inserts a newline
+                let editor_event = match code {
+                    KeyCode::Enter => Some(EditorEvent::NewLine),
+                    _ => convert_keyevent_to_editorevent(code, modifiers),
+                };
+                if let Some(editor_event) = editor_event {
+                    let is_typing = matches!(editor_event, EditorEvent::NewCharacter(_) | EditorEvent::Backspace);
                     let previous_content = self.input_state.content_lines().clone();
                     self.history_idx = None;
-                    self.input_state.apply_event(editor_event);
+                    if let EditorEvent::NewCharacter(' ') = editor_event {
+                        self.input_state.expand_abbreviation(&self.config.abbreviations);
+                    }
+                    if modifiers.contains(KeyModifiers::SHIFT) && editor_event.is_movement() {
+                        self.input_state.apply_selecting_event(editor_event);
+                    } else {
+                        self.input_state.apply_event(editor_event);
+                    }
+                    self.invalidate_outdated_cache(&previous_content);
+                    if self.input_state.content_str().is_empty() {
+                        self.entry_execution_mode = None;
+                    }
 
-                    let new_content = self.input_state.content_lines();
+                    // in a persistent session, running half-typed commands like `cd /u` would change its state
+                    if self.autoeval_mode && !self.persistent_session && previous_content != *self.input_state.content_lines() {
+                        self.execute_content();
+                    }
+                    if self.config.autocomplete_automatic && is_typing {
+                        self.open_autocomplete_menu(false);
+                    }
+                }
+            }
+        }
+    }
+
+    /// whether Enter with the given modifiers runs the command, instead of inserting a newline.
+    fn enter_executes(&self, modifiers: KeyModifiers) -> bool {
+        let autoeval_active = self.autoeval_mode && !self.persistent_session;
+        if autoeval_active && self.config.autoeval_enter_inserts_newline {
+            modifiers.intersects(KeyModifiers::ALT | KeyModifiers::CONTROL)
+        } else {
+            !modifiers.contains(KeyModifiers::ALT)
+        }
+    }
 
-                    if let Some(CachedCommandPart { end_line, end_col, .. }) = self.cached_command_part {
-                        if previous_content.split_strings_at_offset(end_line, end_col).0
-                            != new_content.split_strings_at_offset(end_line, end_col).0
-                        {
-                            self.cached_command_part = None;
+    pub fn handle_main_window_mouse_event(&mut self, event: MouseEvent, double_click: bool) {
+        let layout = self.layout;
+        let is_in = |rect| LayoutRects::inner_position(rect, event.column, event.row);
+        match event.kind {
+            MouseEventKind::ScrollUp if is_in(layout.output).is_some() => {
+                self.output_scroll = self.output_scroll.saturating_sub(OUTPUT_MOUSE_SCROLL_STEP)
+            }
+            MouseEventKind::ScrollDown if is_in(layout.output).is_some() => {
+                self.output_scroll = self.output_scroll.saturating_add(OUTPUT_MOUSE_SCROLL_STEP);
+                self.fit_output_scroll();
+            }
+            MouseEventKind::Down(MouseButton::Left) => {
+                if let Some(autocomplete_state) = self.autocomplete_state.as_mut() {
+                    if let Some((_, row)) = is_in(layout.autocomplete) {
+                        let idx = layout.autocomplete_offset + row as usize;
+                        if idx < autocomplete_state.options.len() {
+                            autocomplete_state.current_idx = idx;
+                            if double_click {
+                                self.apply_autocomplete();
+                            }
                         }
+                        return;
                     }
+                    self.autocomplete_state = None;
+                }
+                // the text area has no border, so it is checked directly
+                if layout.input_text.contains((event.column, event.row).into()) && self.opened_key_select_menu.is_none() {
+                    let row = (event.row - layout.input_text.y) as usize;
+                    let (scrolled_line, scroll) = layout.input_scroll;
+                    let column = (event.column - layout.input_text.x) as usize + if row == scrolled_line { scroll } else { 0 };
+                    self.input_state.set_cursor_position(row, column);
+                }
+            }
+            _ => {}
+        }
+    }
 
-                    if self.autoeval_mode && previous_content != *new_content {
-                        self.execute_content();
+    fn apply_autocomplete(&mut self) {
+        if let Some(autocomplete_state) = self.autocomplete_state.take() {
+            let selected = autocomplete_state.selected();
+            match selected.strip_prefix(&autocomplete_state.original_prompt) {
+                Some(completed_value) => self.input_state.insert_at_cursor(completed_value, true),
+                // options that don't start with the typed word replace it
+                None => {
+                    for _ in autocomplete_state.original_prompt.chars() {
+                        self.input_state.apply_event(EditorEvent::Backspace);
                     }
+                    self.input_state.insert_at_cursor(selected, true);
                 }
             }
         }
     }
 
+    /// Paste text into the input as literal content, without executing it.
+    pub fn handle_main_window_paste(&mut self, text: &str) {
+        if self.autocomplete_state.is_some() || self.opened_key_select_menu.is_some() {
+            return;
+        }
+        let previous_content = self.input_state.content_lines().clone();
+        self.history_idx = None;
+        self.input_state.insert_text(text);
+        self.invalidate_outdated_cache(&previous_content);
+    }
+
+    /// drop the cached command part if the part of the input it was created from changed.
+    fn invalidate_outdated_cache(&mut self, previous_content: &Vec<String>) {
+        let new_content = self.input_state.content_lines();
+        if let Some(CachedCommandPart { end_line, end_col, .. }) = self.cached_command_part
+            && previous_content.split_strings_at_offset(end_line, end_col).0
+                != new_content.split_strings_at_offset(end_line, end_col).0
+        {
+            self.cached_command_part = None;
+        }
+    }
+
     fn apply_history_prev(&mut self) {
         self.cached_command_part = None;
         if let Some(idx) = self.history_idx {
             if idx > 0 {
                 self.history_idx = Some(idx - 1);
                 self.input_state.load_commandentry(self.history.get_at(idx - 1).unwrap());
+                self.entry_execution_mode = None;
             }
         } else if self.history.len() > 0 {
             let new_idx = self.history.len() - 1;
             self.history_idx = Some(new_idx);
-            self.history.push(self.input_state.content_to_commandentry());
+            self.push_input_to_history();
             self.input_state.load_commandentry(self.history.get_at(new_idx).unwrap());
+            self.entry_execution_mode = None;
         }
     }
 
@@ -250,6 +1049,7 @@ impl App {
                 self.history_idx = None;
                 self.input_state.set_content(vec![String::new()]);
             }
+            self.entry_execution_mode = None;
         }
     }
 }
@@ -273,7 +1073,11 @@ fn provide_path_autocomplete(word: &str) -> Option<Vec<String>> {
         entries.filter_map(|entry| entry.ok()).collect()
     } else {
         let started_subfile_name = path.file_name().unwrap().to_string_lossy().to_string();
-        let parent_path = path.parent().unwrap_or_else(|| Path::new("./"));
+        // a plain file name has an empty parent, which is the current directory
+        let parent_path = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("./"));
         if let Ok(parent_entries) = parent_path.read_dir() {
             parent_entries
                 .filter_map(|entry| entry.ok())
@@ -287,10 +1091,11 @@ fn provide_path_autocomplete(word: &str) -> Option<Vec<String>> {
     let completions = possible_children
         .iter()
         .map(|entry| entry.path().display().to_string().replace(home_dir.to_str().unwrap(), "~"))
+        // completions for a plain file name are looked up in ./, but should still start like the word
+        .map(|completion| match completion.strip_prefix("./") {
+            Some(stripped) if !word.trim().is_empty() && !word.starts_with("./") => stripped.to_string(),
+            _ => completion,
+        })
         .collect::<Vec<_>>();
-    if completions.is_empty() {
-        None
-    } else {
-        Some(completions)
-    }
+    if completions.is_empty() { None } else { Some(completions) }
 }
@@ -1,52 +1,229 @@
 extern crate crossterm;
 
-use crate::app::command_list_window::CommandListState;
+use crate::app::cheatsheet_window::CheatsheetState;
+use crate::app::command_list_window::{CommandListState, ListSortOrder};
 use crate::app::key_select_menu::KeySelectMenu;
 use crate::app::main_window::AutocompleteState;
-use crate::commandlist::CommandList;
-use crate::lineeditor::EditorState;
-use crate::util::VecStringExt;
+use crate::cheatsheet::DEFAULT_CHEATSHEET;
+use crate::command_evaluation::{
+    ExecutionMode, StderrLine, StdinSource, UnsafeMatch, decode_output, elevate_command, unsafe_command_matches, wrap_command,
+};
+use crate::commandlist::{CommandEntry, CommandList, PushResult};
+use crate::context_line::ContextLine;
+use crate::hooks::HookEvent;
+use crate::lineeditor::{EditorEvent, EditorState, convert_keyevent_to_editorevent};
+use crate::output_cache::{CacheKey, OutputCache};
+use crate::pipr_config::CompletionNotify;
+use crate::trailing_whitespace::trim_trailing_whitespace;
+use crate::util::{VecStringExt, changed_lines, pretty_json};
 use crate::{CmdOutput, CommandExecutionHandler, CommandExecutionRequest, PiprConfig};
 
-use crossterm::event::{KeyCode, KeyModifiers};
+use crossbeam_channel::{Receiver, TryRecvError, bounded};
+use crossterm::event::{KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::layout::{Margin, Position, Rect};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 
+pub mod cheatsheet_window;
 pub mod command_list_window;
 pub mod key_select_menu;
 pub mod main_window;
 
+/// maximum time between two clicks to count as a double click
+const DOUBLE_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+
+/// intervals in seconds that can be chosen for watch mode, together with their key in the selection menu
+pub const WATCH_INTERVALS: [(char, u64); 5] = [('1', 1), ('2', 2), ('5', 5), ('0', 10), ('3', 30)];
+
 pub const HELP_TEXT: &str = "\
 F1         Show/hide help
 F2         Toggle autoeval
+F12        Turn autoeval off and kill the running command right away (see autoeval_off_key)
 F3         Toggle Paranoid history (fills up history in autoeval)
+Alt+T      Toggle running commands in a pseudo-terminal
+Alt+S      Toggle running commands in a persistent shell session (disables autoeval)
+Alt+W      Start (choose an interval) / stop re-running the command periodically
 F4         Show/hide history
 Ctrl+B     Show/hide bookmarks
+Alt+B      Show and focus the bookmark sidebar, again to hide it. Return loads the selected bookmark, Esc leaves it
 F5         Open helpviewer
 F6         Open outputviewer
+Ctrl+O     Open the command or output in one of the configured targets
+↑/↓        In a menu: highlight an option to see all of it, Return chooses it
+Ctrl+T     Send the command to a tmux pane
+Alt+R      Run the command, even if its output is cached (see output_cache_ttl_secs)
+Alt+L      Run the last executed command again, even if the input changed since
+Alt+A      Toggle running commands with elevated privileges, through sudo or the configured elevation_command
+Ctrl+G     Export the command as an executable shell script (type the path, then Return)
+Alt+E      Explain the command, showing its piped stages and their flags and arguments
+Alt+P      Run the pipeline only up to a chosen stage, to inspect its output there
+Alt+D      Run the $(...) at the cursor on its own and show what it expands to
+Alt+N      Show what the variables in the current line expand to, like an empty $VAR
+Alt+I      Show what commands can access in the sandbox they are run in
+Alt+G      Insert one of the directories recent commands referred to, filtered by the word before the cursor
+Alt+M      Switch between running commands isolated and unsafe (asks before disabling isolation)
 F7         When the cursor is on a `|` symbol, cache the output of everything before that |
+F8         Expand/collapse long output
+Ctrl+F     Filter the output by a pattern (Return to keep the filter, Esc to remove it), again to remove it
+Alt+X      Toggle between showing the output as text and as a hex dump
+Alt+J      Toggle pretty-printing output that is JSON
+Alt+Y      Select an output line with ↑/↓ (←/→ for a single word of it) and insert it at the cursor with Return
+Alt+←/→    Page through the outputs of the last commands run with Return
+F9         Use the current output as stdin for the next commands (clears the input)
+Shift+F9   Stop using the stored output as stdin
+Alt+F9     Switch between using stdout, stderr or both as stdin with F9
+Alt+Z      Edit a copy of the output in a scratch window, Ctrl+S there uses it as stdin for the next commands like F9
+PgUp/PgDn  Scroll the output
+Alt+F      Toggle following the output, keeping it scrolled to its end when new output arrives
 Ctrl+S     Save bookmark
-Alt+Return Newline
+Alt+O      Save bookmark together with the current output, shown in the bookmark preview
+m          In the bookmark list: cycle the execution mode of the selected bookmark
+p          In the bookmark list: append the selected bookmark to the command as another pipeline stage
+e          In the bookmark / history list: edit a copy of the selected entry
+s          In the bookmark / history list: sort by text or length. S in the bookmark list keeps the bookmarks in that order
+Alt+↑/↓    In the bookmark list: move the selected bookmark up/down
+Alt+Return Newline (runs the command instead in autoeval with autoeval_enter_inserts_newline)
 Ctrl+U     Clear Command
+Alt+U      Restore the last cleared command
+Ctrl+W     Delete the previous word (also Ctrl+Backspace)
+Ctrl+←/→   Move the cursor by word
+Shift+←/→  Select text (also with ↑/↓, Home/End and Ctrl). Return then runs only the selection
+Alt+Q      Shell-quote the selection, or the word under the cursor
+Alt+V      Show/hide spaces (·) and tabs (→) in the input
+Alt+H      Toggle syntax highlighting
 Ctrl+P     Previous in history
 Ctrl+N     Next in history
 Ctrl+V     Insert snippet (press corresponding key to choose)
+Alt+C      Show/hide the cheatsheet. Type to search, Return inserts the selected command
+Alt+1-9    Insert the corresponding favorite
+
+With mouse_support enabled, click to move the cursor or select entries,
+double-click to choose an entry and scroll the output with the mouse wheel.
 
 disable a line by starting it with a #
 this will simply exclude the line from the executed command.
 
 Config file is in
-~/.config/pipr/pipr.toml";
+~/.config/pipr/pipr.toml (or the file passed via --config)";
 
 pub enum WindowState {
     Main,
     TextView(String, String),
     BookmarkList(CommandListState),
     HistoryList(CommandListState),
+    Cheatsheet(CheatsheetState),
+    /// an editable copy of the output, to tweak it by hand before using it as stdin
+    Scratch(EditorState),
+}
+
+impl WindowState {
+    /// The most relevant keys of the window, as (key, action), shown in the bottom line. All keys are listed in [`HELP_TEXT`].
+    pub fn key_hints(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            WindowState::Main => &[
+                ("Enter", "run"),
+                ("F2", "autoeval"),
+                ("F4", "history"),
+                ("Ctrl+B", "bookmarks"),
+            ],
+            WindowState::TextView(..) => &[("any key", "close")],
+            WindowState::BookmarkList(_) => &[("Enter", "use"), ("p", "append"), ("e", "edit copy"), ("Del", "delete")],
+            WindowState::HistoryList(_) => &[("Enter", "use"), ("e", "edit copy"), ("s", "sort"), ("Del", "delete")],
+            WindowState::Cheatsheet(_) => &[("type", "search"), ("Enter", "insert"), ("Esc", "close")],
+            WindowState::Scratch(_) => &[("Ctrl+S", "use as stdin"), ("Esc", "discard")],
+        }
+    }
 }
 
 pub enum KeySelectMenuType {
     Snippets,
     OpenWordIn(String), // stores the word that should be opened in the selected help
     OpenOutputIn(String),
+    OpenIn(String, String), // stores the current command and output, which are passed to the selected target
+    WatchInterval,
+    PipelineStage(Vec<String>),           // stores the commands running the pipeline up to each stage
+    ReplaceBookmark(usize, CommandEntry), // stores the index of the similar bookmark and the new entry
+    OverwriteScript(PathBuf),             // stores the existing file the script should be exported to
+    ConfirmUnsafe,
+    ConfirmUnsafeRerun,
+    ConfirmUnsafeMode,
+}
+
+/// A pattern the shown output is filtered by
+#[derive(Debug, Default)]
+pub struct OutputFilter {
+    pub pattern: String,
+    /// whether keys are currently typed into the filter instead of the input
+    pub editing: bool,
+}
+
+/// A line of the output, or a single word of it, chosen to be inserted into the input
+#[derive(Debug, Default)]
+pub struct OutputSelection {
+    pub line: usize,
+    /// index of the selected word within the line, `None` selects the whole line
+    pub word: Option<usize>,
+}
+
+/// The bookmark list shown next to the input
+#[derive(Debug, Default)]
+pub struct BookmarkSidebar {
+    pub selected_idx: Option<usize>,
+    /// whether keys go to the sidebar instead of the input
+    pub focused: bool,
+}
+
+impl BookmarkSidebar {
+    /// the current bookmarks, with the selection kept within them
+    pub fn list_state(&self, bookmarks: &CommandList) -> CommandListState {
+        let entries = bookmarks.entries().clone();
+        let selected_idx = self.selected_idx.filter(|&idx| idx < entries.len());
+        CommandListState::new(entries, selected_idx)
+    }
+}
+
+/// The part of the input that was run, if it wasn't all of it
+#[derive(Debug, Clone, Copy)]
+pub enum PartialRun {
+    /// the pipeline up to the given stage, of that many stages
+    Stage(usize, usize),
+    Selection,
+}
+
+/// Which output of the last command F9 feeds into the stdin of the next commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdinStream {
+    Stdout,
+    Stderr,
+    /// both, with the stderr lines in between the stdout lines where they were written
+    Both,
+}
+
+impl StdinStream {
+    pub fn name(self) -> &'static str {
+        match self {
+            StdinStream::Stdout => "stdout",
+            StdinStream::Stderr => "stderr",
+            StdinStream::Both => "stdout and stderr",
+        }
+    }
+
+    fn next(self) -> StdinStream {
+        match self {
+            StdinStream::Stdout => StdinStream::Stderr,
+            StdinStream::Stderr => StdinStream::Both,
+            StdinStream::Both => StdinStream::Stdout,
+        }
+    }
+}
+
+/// The output of an earlier command run with Enter, kept to compare later outputs to
+#[derive(Debug, Clone)]
+pub struct PastOutput {
+    pub command: String,
+    pub output: String,
 }
 
 #[derive(Debug)]
@@ -69,16 +246,112 @@ impl CachedCommandPart {
     }
 }
 
+/// Screen areas of the interactive ui elements, updated on every draw.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LayoutRects {
+    /// the area the input text is drawn in, excluding border and line number gutter
+    pub input_text: Rect,
+    /// the input line with the cursor, and the number of columns it is scrolled by to keep the cursor visible
+    pub input_scroll: (usize, usize),
+    pub autocomplete: Rect,
+    /// index of the first visible autocomplete option
+    pub autocomplete_offset: usize,
+    pub output: Rect,
+    pub command_list: Rect,
+    /// index of the first visible command list entry
+    pub command_list_offset: usize,
+}
+
+impl LayoutRects {
+    /// position of a mouse event relative to the inside (excluding borders) of a bordered area
+    pub fn inner_position(rect: Rect, column: u16, row: u16) -> Option<(u16, u16)> {
+        let inner = rect.inner(Margin::new(1, 1));
+        inner
+            .contains(Position::new(column, row))
+            .then(|| (column - inner.x, row - inner.y))
+    }
+}
+
 pub struct App {
     pub input_state: EditorState,
     pub command_output: String,
+    /// the output exactly as the command printed it
+    pub command_output_bytes: Vec<u8>,
+    /// what a successful command wrote to stderr, shown within the output depending on `stderr_display`
+    pub command_stderr_lines: Vec<StderrLine>,
+    /// number of lines in the output, `None` until a command succeeded
+    pub command_output_line_count: Option<usize>,
     pub command_error: String,
+    /// show the output as a hex dump instead of text
+    pub output_hex_view: bool,
+    /// pretty-print output that is JSON
+    pub pretty_json: bool,
+    /// the output pretty-printed, once it was parsed as JSON
+    pub pretty_json_output: Option<String>,
+    /// receives the pretty-printed output while it is parsed in the background
+    pretty_json_receiver: Option<Receiver<Option<String>>>,
+    /// receives the text showing what a `$(...)` expands to, once it ran in the background
+    substitution_preview_receiver: Option<Receiver<String>>,
+    /// whether the whole output is shown, ignoring the configured output_line_limit
+    pub output_expanded: bool,
+    pub output_scroll: u16,
+    /// keep the output scrolled to its end when new output arrives, like `tail -f`
+    pub output_follow: bool,
+    /// lines of the output that weren't in the previous one, and when that output arrived
+    pub changed_output_lines: Option<(Vec<usize>, Instant)>,
+    /// only the output lines matching this filter are shown
+    pub output_filter: Option<OutputFilter>,
+    /// the output line that is inserted into the input, while one is being selected
+    pub output_selection: Option<OutputSelection>,
+    /// outputs of recent commands, if enabled
+    output_cache: Option<OutputCache>,
+    /// the output of the running command is stored in the cache under this key
+    pending_cache_key: Option<CacheKey>,
+    /// the next command is run even if its output is cached
+    pub skip_output_cache: bool,
+    /// the next command is run even if it is flagged as unsafe, as the user confirmed it
+    pub unsafe_confirmed: bool,
+    /// whether the shown output was taken from the cache
+    pub output_from_cache: bool,
+    /// a cached output was shown while a command was still running, whose output is thus outdated
+    discard_running_output: bool,
+    /// outputs of the last commands run with Enter, the most recent first
+    pub past_outputs: VecDeque<PastOutput>,
+    /// index into `past_outputs` of the output shown instead of the current one
+    pub shown_past_output: Option<usize>,
+    /// the command run with Enter whose output is kept in `past_outputs` once it arrives
+    pending_past_output: Option<String>,
+    /// whether the current output is the most recent of the `past_outputs`
+    current_output_is_past: bool,
+    pub bookmark_sidebar: Option<BookmarkSidebar>,
+    /// path the command is exported to as a script, while it is typed in
+    pub script_export_path: Option<String>,
+    /// width of the area the output is displayed in, updated on every draw
+    pub output_width: u16,
     pub autoeval_mode: bool,
+    /// run commands inside a pseudo-terminal
+    pub pty_mode: bool,
+    /// run commands in a shell session that is kept alive between commands
+    pub persistent_session: bool,
+    /// run commands through the configured elevation command, like sudo
+    pub elevated: bool,
+    /// the elevation command is asking for the password, commands run elevated once it succeeded
+    pub elevation_pending: bool,
+    /// render spaces and tabs in the input visibly
+    pub show_whitespace: bool,
     pub last_executed_cmd: String,
+    /// the command that was last run with Enter and its stdin, without the cached part of the input
+    last_run: Option<(String, Option<StdinSource>)>,
+    /// set if the output is the result of running only a part of the input
+    pub partial_run: Option<PartialRun>,
     pub paranoid_history_mode: bool,
     pub window_state: WindowState,
     pub bookmarks: CommandList,
     pub history: CommandList,
+    /// directories referenced by the commands that were run, the most recent last
+    pub recent_dirs: CommandList,
+    /// entries of the cheatsheet shown with Alt+C
+    pub cheatsheet: Vec<CommandEntry>,
     pub history_idx: Option<usize>,
     pub execution_handler: CommandExecutionHandler,
     pub config: PiprConfig,
@@ -90,8 +363,40 @@ pub struct App {
     /// Part of a command can be cached, so it will not be reevluated on every execution.
     pub cached_command_part: Option<CachedCommandPart>,
 
+    /// Output of a previous command, or a file given on the command line, that is fed into the stdin of the following executions.
+    pub primed_stdin: Option<StdinSource>,
+    /// The output F9 takes, and the one the primed stdin was taken from, `None` if it was edited in the scratch window
+    pub stdin_stream: StdinStream,
+    pub primed_stdin_stream: Option<StdinStream>,
+
+    /// Execution mode preferred by the loaded bookmark, used until another entry is loaded or the input is cleared.
+    pub entry_execution_mode: Option<ExecutionMode>,
+
+    /// when set, the command is re-run in this interval
+    pub watch_interval: Option<Duration>,
+    last_watch_run: Instant,
+
+    /// short message shown at the bottom of the screen until the next key press
+    pub notification: Option<String>,
+
+    /// prompt-like line shown above the input, if configured
+    pub context_line: Option<ContextLine>,
+
+    /// areas of the last drawn frame, used to map mouse events to ui elements
+    pub layout: LayoutRects,
+    /// time and position of the last left click, to detect double clicks
+    last_click: Option<(Instant, u16, u16)>,
+
     /// number from 0-4 showing an animation that shows some process being executed
     pub is_processing_state: Option<u8>,
+    /// when the currently running command was started by the executor
+    pub command_started: Option<Instant>,
+    /// the terminal bell should be rung, as a long running command finished
+    pub ring_bell: bool,
+    /// exit code of the last finished command, `None` if it couldn't run or didn't exit by itself
+    pub last_exit_code: Option<i32>,
+    /// the terminal was resized, so the output scroll position has to be fitted to the new layout once it is drawn
+    pub resized: bool,
 
     /// A (stdin, command) that should be executed in the main screen.
     /// this will be taken ( and thus reset ) and handled by the ui module.
@@ -108,17 +413,68 @@ impl App {
     ) -> App {
         App {
             autocomplete_state: None,
+            recent_dirs: CommandList::new(None, Some(config.recent_dirs_size)),
             window_state: WindowState::Main,
-            input_state: EditorState::new(),
+            input_state: EditorState::new().with_tab_width(config.tab_width),
             command_output: "".into(),
+            command_output_bytes: Vec::new(),
+            command_stderr_lines: Vec::new(),
+            command_output_line_count: None,
             command_error: "".into(),
+            output_hex_view: false,
+            pretty_json: config.pretty_json_default,
+            pretty_json_output: None,
+            pretty_json_receiver: None,
+            substitution_preview_receiver: None,
+            output_expanded: false,
+            output_filter: None,
+            output_selection: None,
+            changed_output_lines: None,
+            output_cache: config
+                .output_cache_ttl
+                .map(|ttl| OutputCache::new(ttl, config.output_cache_size)),
+            pending_cache_key: None,
+            skip_output_cache: false,
+            unsafe_confirmed: false,
+            output_from_cache: false,
+            discard_running_output: false,
+            past_outputs: VecDeque::new(),
+            shown_past_output: None,
+            pending_past_output: None,
+            current_output_is_past: false,
+            script_export_path: None,
+            bookmark_sidebar: config.bookmark_sidebar_default.then(BookmarkSidebar::default),
+            output_scroll: 0,
+            output_follow: false,
+            output_width: 0,
             last_executed_cmd: "".into(),
+            last_run: None,
+            partial_run: None,
             autoeval_mode: config.autoeval_mode_default,
+            pty_mode: config.pty_mode_default,
+            persistent_session: config.persistent_session_default,
+            elevated: false,
+            elevation_pending: false,
+            show_whitespace: config.show_whitespace,
             paranoid_history_mode: config.paranoid_history_mode_default,
             should_quit: false,
             is_processing_state: None,
+            command_started: None,
+            ring_bell: false,
+            last_exit_code: None,
+            resized: false,
+            notification: None,
+            context_line: config.context_line.clone().map(ContextLine::new),
+            watch_interval: None,
+            last_watch_run: Instant::now(),
+            layout: LayoutRects::default(),
+            last_click: None,
             history_idx: None,
             cached_command_part: None,
+            primed_stdin: None,
+            stdin_stream: StdinStream::Stdout,
+            primed_stdin_stream: Some(StdinStream::Stdout),
+            entry_execution_mode: None,
             opened_key_select_menu: None,
             should_jump_to_other_cmd: None,
             execution_handler,
@@ -126,58 +482,459 @@ impl App {
             config,
             bookmarks,
             history,
+            cheatsheet: CommandList::deserialize(None, None, DEFAULT_CHEATSHEET).entries().clone(),
         }
     }
 
     pub fn on_cmd_output(&mut self, process_result: CmdOutput) {
+        if let CmdOutput::Started(time) = process_result {
+            self.command_started = Some(time);
+            return;
+        }
+        if std::mem::take(&mut self.discard_running_output) {
+            return;
+        }
         self.is_processing_state = None;
+        if let Some(started) = self.command_started.take() {
+            let failed = matches!(process_result, CmdOutput::Failed(..) | CmdOutput::NotOk(_));
+            self.announce_completion(failed, started.elapsed());
+        }
+        self.output_from_cache = false;
+        self.shown_past_output = None;
+        let cache_key = self.pending_cache_key.take();
+        let past_output_command = self.pending_past_output.take();
         match process_result {
-            CmdOutput::Ok(stdout) => {
+            CmdOutput::Started(_) => {}
+            CmdOutput::Ok(stdout, stderr_lines) => {
+                self.last_exit_code = Some(0);
+                if let (Some(cache), Some(cache_key)) = (self.output_cache.as_mut(), cache_key) {
+                    cache.insert(cache_key, stdout.clone(), stderr_lines.clone());
+                }
                 if self.paranoid_history_mode {
-                    self.history.push(self.input_state.content_to_commandentry());
+                    self.push_input_to_history();
                 }
-                self.command_output = stdout;
+                let new_output = decode_output(&stdout);
+                self.changed_output_lines = (self.autoeval_mode
+                    && self.config.output_change_highlight.is_some()
+                    && !self.command_output.is_empty()
+                    && new_output != self.command_output)
+                    .then(|| (changed_lines(&self.command_output, &new_output), Instant::now()));
+                self.command_output = new_output;
+                // decoded output always ends with a newline, so empty output would count as one line
+                self.command_output_line_count = Some(if stdout.is_empty() {
+                    0
+                } else {
+                    self.command_output.lines().count()
+                });
+                self.command_output_bytes = stdout;
+                self.command_stderr_lines = stderr_lines;
                 self.command_error = String::new();
+                self.keep_past_output(past_output_command);
+                self.update_output_scroll();
+                self.format_json_output();
+            }
+            CmdOutput::Failed(code, stderr) => {
+                self.last_exit_code = Some(code);
+                self.command_error = stderr;
+            }
+            CmdOutput::NotOk(error) => {
+                self.last_exit_code = None;
+                self.command_error = error;
+            }
+        }
+    }
+
+    /// Ring the bell or send a desktop notification, as configured, if the command ran for long enough.
+    fn announce_completion(&mut self, failed: bool, running_time: Duration) {
+        let notify = self.config.completion_notify;
+        if notify == CompletionNotify::Off || running_time < self.config.completion_notify_after {
+            return;
+        }
+        self.ring_bell = notify.bell();
+        if notify.desktop() {
+            let summary = if failed {
+                "pipr: command failed"
+            } else {
+                "pipr: command finished"
+            };
+            let command = self.last_executed_cmd.lines().next().unwrap_or_default();
+            let body = format!("{} ({}s)", command, running_time.as_secs());
+            match Command::new("notify-send")
+                .args([summary, &body])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+            {
+                // reap it once it is done, without waiting for it
+                Ok(mut child) => drop(std::thread::spawn(move || child.wait())),
+                Err(err) => self.notification = Some(format!("Could not run notify-send: {}", err)),
             }
-            CmdOutput::NotOk(stderr) => self.command_error = stderr,
         }
     }
 
+    /// Keep the current output in `past_outputs` if it is the output of a command run with Enter.
+    fn keep_past_output(&mut self, command: Option<String>) {
+        self.current_output_is_past = command.is_some() && self.config.output_history_size > 0;
+        if let Some(command) = command.filter(|_| self.current_output_is_past) {
+            self.past_outputs.push_front(PastOutput {
+                command,
+                output: self.command_output.clone(),
+            });
+            self.past_outputs.truncate(self.config.output_history_size);
+        }
+    }
+
+    /// Keep the output of the next command in `past_outputs`, as it is run with Enter.
+    pub fn keep_next_output(&mut self) {
+        self.pending_past_output = Some(self.input_state.content_str());
+    }
+
+    /// Show the next older or newer past output. Paging past the newest one shows the current output again.
+    pub fn page_past_outputs(&mut self, older: bool) {
+        // the current output is shown already, no need to page to its copy
+        let newest = usize::from(self.current_output_is_past);
+        self.shown_past_output = match (self.shown_past_output, older) {
+            (None, true) if newest < self.past_outputs.len() => Some(newest),
+            (None, true) => {
+                self.notification = Some("No earlier outputs to show".to_string());
+                None
+            }
+            (Some(idx), true) => Some((idx + 1).min(self.past_outputs.len() - 1)),
+            (Some(idx), false) if idx > newest => Some(idx - 1),
+            (_, false) => None,
+        };
+        self.output_scroll = 0;
+    }
+
+    /// The past output shown instead of the current one, if any.
+    pub fn shown_past_output(&self) -> Option<&PastOutput> {
+        self.past_outputs.get(self.shown_past_output?)
+    }
+
+    /// Scroll a new output back to the top, unless the scroll position should be kept.
+    /// A kept position is moved up if the new output is too short for it.
+    fn update_output_scroll(&mut self) {
+        if self.output_follow {
+            self.output_expanded = true;
+            self.fit_output_scroll();
+            return;
+        }
+        // keep the scroll position while watching, so the refreshed output stays in view
+        if self.watch_interval.is_none() && !self.config.keep_output_scroll {
+            self.output_expanded = false;
+            self.output_scroll = 0;
+        }
+        let last_line = self.command_output.lines().count().saturating_sub(1);
+        self.output_scroll = self.output_scroll.min(last_line.try_into().unwrap_or(u16::MAX));
+    }
+
+    /// Pretty-print the output in the background if it is JSON, as parsing large outputs takes a while.
+    pub fn format_json_output(&mut self) {
+        self.pretty_json_output = None;
+        self.pretty_json_receiver = None;
+        let trimmed_output = self.command_output.trim_start();
+        if !self.pretty_json || !(trimmed_output.starts_with('{') || trimmed_output.starts_with('[')) {
+            return;
+        }
+        let (sender, receiver) = bounded(1);
+        let output = self.command_output.clone();
+        std::thread::spawn(move || sender.send(pretty_json(&output)));
+        self.pretty_json_receiver = Some(receiver);
+    }
+
     pub fn set_should_quit(&mut self) {
         self.should_quit = true;
-        self.history.push(self.input_state.content_to_commandentry());
+        self.push_input_to_history();
     }
 
+    /// Run the input, unless there is nothing to run.
     pub fn execute_content(&mut self) {
         let lines = self.input_state.content_lines().clone();
         let lines = match self.cached_command_part {
             Some(CachedCommandPart { end_line, end_col, .. }) => lines.split_strings_at_offset(end_line, end_col).1,
             _ => lines,
         };
+        if self.is_empty_command(&lines) {
+            return;
+        }
+
+        let command = self.command_from_lines(&lines);
 
+        let stdin = match &self.cached_command_part {
+            Some(cached_command_part) => Some(StdinSource::Lines(cached_command_part.cached_output.to_owned())),
+            None => self.primed_stdin.clone(),
+        };
+        if self.send_to_executor(self.wrapped_command(command.clone()), stdin.clone()) {
+            self.last_executed_cmd = self.input_state.content_str();
+            self.last_run = Some((command, stdin));
+            self.partial_run = None;
+        }
+    }
+
+    /// Run the pipeline only up to the given stage, to inspect the output at that point.
+    pub fn run_pipeline_stage(&mut self, stage_command: &str, stage: usize, stage_count: usize) {
+        self.run_partially(stage_command.to_string(), PartialRun::Stage(stage, stage_count));
+    }
+
+    /// Run only the selected part of the input, like a single `$(...)`.
+    pub fn run_selection(&mut self, selection: &str) {
+        let lines = selection.split('\n').map(String::from).collect::<Vec<_>>();
+        if self.is_empty_command(&lines) {
+            return;
+        }
+        self.run_partially(self.command_from_lines(&lines), PartialRun::Selection);
+    }
+
+    fn run_partially(&mut self, command: String, partial_run: PartialRun) {
+        let command = self.wrapped_command(command);
+        if self.send_to_executor(command, self.primed_stdin.clone()) {
+            self.partial_run = Some(partial_run);
+        }
+    }
+
+    /// Run the configured startup command, showing its output without changing the input.
+    pub fn run_startup_command(&mut self, command: &str) {
+        let command = self.wrapped_command(command.to_string());
+        self.send_to_executor(command, None);
+    }
+
+    /// Run the last executed command again with the same stdin, even if the input changed or was cleared since.
+    /// Like a command run with Enter, it needs to be confirmed if it is flagged as unsafe.
+    pub fn rerun_last_command(&mut self) {
+        let Some((command, stdin)) = self.last_run.clone() else {
+            self.notification = Some("No command was run yet".to_string());
+            return;
+        };
+        let unsafe_matches = self.unsafe_matches(&command);
+        if !unsafe_matches.is_empty() && !self.unsafe_confirmed {
+            self.open_unsafe_confirmation(&unsafe_matches, KeySelectMenuType::ConfirmUnsafeRerun);
+            return;
+        }
+        let command = self.wrapped_command(command);
+        self.skip_output_cache = true;
+        self.pending_past_output = Some(self.last_executed_cmd.clone());
+        if self.send_to_executor(command, stdin) {
+            self.partial_run = None;
+        }
+    }
+
+    /// Hand a command over to the executor, returning whether it was accepted.
+    fn send_to_executor(&mut self, command: String, stdin: Option<StdinSource>) -> bool {
+        let allow_unsafe = std::mem::take(&mut self.unsafe_confirmed);
+        let cache_key = self.output_cache_key(&command, &stdin);
+        if let Some(cached) = cache_key
+            .as_ref()
+            .and_then(|key| self.output_cache.as_ref()?.get(key))
+            .cloned()
+        {
+            let still_running = self.is_processing_state.is_some();
+            self.discard_running_output = false;
+            self.pending_cache_key = None;
+            self.on_cmd_output(CmdOutput::Ok(cached.stdout, cached.stderr_lines));
+            self.output_from_cache = true;
+            self.discard_running_output = still_running;
+            return true;
+        }
+        self.pending_cache_key = cache_key;
+        self.discard_running_output = false;
+        let mut execution_request = CommandExecutionRequest::new(
+            command,
+            stdin,
+            self.pty_mode,
+            self.command_env(),
+            self.entry_execution_mode,
+            self.persistent_session,
+        );
+        execution_request.stdin_format = self.config.stdin_format;
+        execution_request.detach_terminal = self.config.detach_terminal;
+        execution_request.shell_startup = self.config.shell_startup;
+        execution_request.allow_unsafe = allow_unsafe;
+        if self.execution_handler.ensure_running() {
+            self.notification = Some("The command executor stopped unexpectedly and was restarted".to_string());
+        }
+        if let Err(err) = self.execution_handler.execute(execution_request) {
+            self.on_cmd_output(CmdOutput::NotOk(err.to_string()));
+            return false;
+        }
+        self.is_processing_state = Some(0);
+        true
+    }
+
+    /// The key the output of the command is cached under, if the output cache applies to it.
+    /// Commands in the persistent session or in watch mode aren't cached, as their output is expected to change,
+    /// and neither are commands reading stdin from a file.
+    fn output_cache_key(&mut self, command: &str, stdin: &Option<StdinSource>) -> Option<CacheKey> {
+        let skip = std::mem::take(&mut self.skip_output_cache);
+        if skip || self.output_cache.is_none() || self.persistent_session || self.watch_interval.is_some() {
+            return None;
+        }
+        let stdin = match stdin {
+            Some(StdinSource::Lines(lines)) => Some(lines.clone()),
+            Some(StdinSource::File(_) | StdinSource::Fifo(_)) => return None,
+            None => None,
+        };
+        Some(CacheKey {
+            command: command.to_string(),
+            stdin,
+            use_pty: self.pty_mode,
+            execution_mode: self.execution_mode(),
+        })
+    }
+
+    /// the mode the current command is executed in
+    pub fn execution_mode(&self) -> ExecutionMode {
+        self.entry_execution_mode.unwrap_or(self.execution_handler.execution_mode)
+    }
+
+    /// join the input lines into the command that is executed, leaving out disabled lines
+    fn command_from_lines(&self, lines: &[String]) -> String {
+        let lines = if self.config.trim_trailing_whitespace {
+            trim_trailing_whitespace(lines)
+        } else {
+            lines.to_vec()
+        };
         let command = lines
-            .iter()
+            .into_iter()
             .filter(|line| !line.starts_with('#'))
-            .cloned()
             .collect::<Vec<String>>();
-        let command = if self.raw_mode {
+        if self.raw_mode {
             command.join("\n")
         } else {
             command.join(" ")
+        }
+    }
+
+    /// Add the input to the history, trimmed if configured.
+    pub fn push_input_to_history(&mut self) {
+        let mut entry = self.input_state.content_to_commandentry();
+        if self.config.trim_trailing_whitespace {
+            entry = CommandEntry::new(trim_trailing_whitespace(entry.lines()));
+        }
+        if self.history.push(entry) == PushResult::Refused {
+            self.notification = Some("The history is full, raise history_size or delete entries to add new commands".into());
+        }
+    }
+
+    /// Run the configured hook of the event for the current command, unless in incognito mode.
+    pub fn run_hook(&mut self, event: HookEvent, env: &[(&str, String)]) {
+        if self.config.incognito {
+            return;
+        }
+        if let Err(err) = self.config.hooks.run(event, &self.input_state.content_str(), env) {
+            self.notification = Some(format!("Failed to run hook: {}", err));
+        }
+    }
+
+    /// Run the bookmark hook, passing the file the bookmarks are stored in.
+    pub fn run_bookmark_hook(&mut self) {
+        let bookmarks_file = self
+            .bookmarks
+            .file()
+            .map(|file| file.display().to_string())
+            .unwrap_or_default();
+        self.run_hook(HookEvent::Bookmark, &[("PIPR_BOOKMARKS_FILE", bookmarks_file)]);
+    }
+
+    /// Switch between running commands isolated and unsafe. Disabling the sandbox has to be confirmed first.
+    pub fn toggle_execution_mode(&mut self) {
+        match self.execution_handler.execution_mode {
+            ExecutionMode::Isolated => {
+                let heading = vec![
+                    "Commands will run directly on your system,".to_string(),
+                    "without the sandbox protecting your files.".to_string(),
+                ];
+                self.opened_key_select_menu = Some(
+                    KeySelectMenu::new(
+                        vec![('y', "Disable isolation".to_string()), ('n', "Cancel".to_string())],
+                        KeySelectMenuType::ConfirmUnsafeMode,
+                    )
+                    .with_heading("Run commands unsafe?", heading),
+                );
+            }
+            ExecutionMode::Unsafe if which::which("bwrap").is_err() => {
+                self.notification = Some("Isolation needs bubblewrap (bwrap) on your PATH".to_string());
+            }
+            ExecutionMode::Unsafe => self.set_execution_mode(ExecutionMode::Isolated),
+        }
+    }
+
+    pub fn set_execution_mode(&mut self, execution_mode: ExecutionMode) {
+        self.execution_handler.set_execution_mode(execution_mode);
+        // elevated commands aren't possible in the sandbox
+        if execution_mode == ExecutionMode::Isolated {
+            self.elevated = false;
+        }
+        // the output of a command that was still running is lost with the old executor
+        self.is_processing_state = None;
+        self.notification = Some(match execution_mode {
+            ExecutionMode::Isolated => "Commands now run isolated".to_string(),
+            ExecutionMode::Unsafe => "Commands now run unsafe, without isolation".to_string(),
+        });
+    }
+
+    /// The reasons the input would be refused as unsafe in the current execution mode, if any.
+    pub fn unsafe_input_matches(&self) -> Vec<UnsafeMatch> {
+        self.unsafe_matches(&self.command_from_lines(self.input_state.content_lines()))
+    }
+
+    /// The reasons the command would be refused as unsafe in the current execution mode, if any.
+    fn unsafe_matches(&self, command: &str) -> Vec<UnsafeMatch> {
+        if self.execution_mode() != ExecutionMode::Unsafe {
+            return Vec::new();
+        }
+        unsafe_command_matches(command)
+    }
+
+    /// Ask whether to run a command flagged as unsafe, listing why it was flagged.
+    fn open_unsafe_confirmation(&mut self, unsafe_matches: &[UnsafeMatch], menu_type: KeySelectMenuType) {
+        let mut heading = vec![format!("{} unsafe pattern(s) matched:", unsafe_matches.len())];
+        heading.extend(unsafe_matches.iter().map(|m| format!("  {}", m)));
+        self.opened_key_select_menu = Some(
+            KeySelectMenu::new(vec![('y', "Run anyway".to_string()), ('n', "Cancel".to_string())], menu_type)
+                .with_heading("Run unsafe command?", heading),
+        );
+    }
+
+    /// Whether the lines contain nothing to run, as they are blank or disabled.
+    fn is_empty_command(&self, lines: &[String]) -> bool {
+        self.command_from_lines(lines).trim().is_empty()
+    }
+
+    /// apply the configured command wrapper, if any, and run it elevated if enabled
+    fn wrapped_command(&self, command: String) -> String {
+        let command = match &self.config.command_wrapper {
+            Some(wrapper) => wrap_command(wrapper, &command),
+            None => command,
         };
+        if self.elevated {
+            elevate_command(
+                &self.config.elevation_command,
+                &self.execution_handler.shell_command,
+                &command,
+            )
+        } else {
+            command
+        }
+    }
 
-        let execution_request =
-            CommandExecutionRequest::new(command, self.cached_command_part.as_ref().map(|x| x.cached_output.to_owned()));
-        self.execution_handler.execute(execution_request);
-        self.is_processing_state = Some(0);
-        self.last_executed_cmd = self.input_state.content_str();
+    /// environment variables that are set for executed commands
+    fn command_env(&self) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+        if self.config.force_color {
+            env.push(("FORCE_COLOR".to_string(), "1".to_string()));
+            env.push(("CLICOLOR_FORCE".to_string(), "1".to_string()));
+        }
+        if (self.config.force_color || self.pty_mode) && self.output_width > 0 {
+            env.push(("COLUMNS".to_string(), self.output_width.to_string()));
+        }
+        env
     }
 
     fn toggle_history_list(&mut self) {
         match self.window_state {
             WindowState::HistoryList(_) => self.window_state = WindowState::Main,
             _ => {
-                self.history.push(self.input_state.content_to_commandentry());
+                self.push_input_to_history();
                 let entries = self.history.entries().clone();
                 self.window_state = WindowState::HistoryList(CommandListState::new(entries, self.history_idx));
             }
@@ -188,13 +945,36 @@ impl App {
         match self.window_state {
             WindowState::BookmarkList(_) => self.window_state = WindowState::Main,
             _ => {
-                self.history.push(self.input_state.content_to_commandentry());
+                self.push_input_to_history();
                 let entries = self.bookmarks.entries().clone();
                 self.window_state = WindowState::BookmarkList(CommandListState::new(entries, None));
             }
         }
     }
 
+    /// Turn autoeval and watch mode off and kill the running command, before anything else can run.
+    fn stop_autoeval(&mut self) {
+        let was_running = self.is_processing_state.is_some();
+        self.autoeval_mode = false;
+        self.watch_interval = None;
+        self.execution_handler.kill_running_command();
+        self.is_processing_state = None;
+        self.command_started = None;
+        self.pending_past_output = None;
+        self.notification = Some(if was_running {
+            "Autoeval is off, the running command was killed".to_string()
+        } else {
+            "Autoeval is off".to_string()
+        });
+    }
+
+    fn toggle_cheatsheet(&mut self) {
+        match self.window_state {
+            WindowState::Cheatsheet(_) => self.window_state = WindowState::Main,
+            _ => self.window_state = WindowState::Cheatsheet(CheatsheetState::new(self.cheatsheet.clone())),
+        }
+    }
+
     fn toggle_help_window(&mut self) {
         match self.window_state {
             WindowState::TextView(_, _) => self.window_state = WindowState::Main,
@@ -203,15 +983,102 @@ impl App {
     }
 
     pub fn on_tui_event(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        self.notification = None;
         let control_pressed = modifiers.contains(KeyModifiers::CONTROL);
+        if self.config.autoeval_off_key.matches(code, modifiers) {
+            self.stop_autoeval();
+            return;
+        }
         match code {
             KeyCode::F(1) => self.toggle_help_window(),
             KeyCode::Char('b') if control_pressed => self.toggle_bookmark_list(),
             KeyCode::F(4) => self.toggle_history_list(),
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::ALT) => self.toggle_cheatsheet(),
             _ => self.handle_window_specific_event(code, modifiers),
         }
     }
 
+    /// Drop what depends on the old terminal size, the layout is recomputed with the next draw.
+    pub fn on_resize(&mut self) {
+        self.resized = true;
+        // a click at the same position now hits something else
+        self.last_click = None;
+    }
+
+    /// Scroll the output up as far as the output pane got taller than what is left of the output,
+    /// so a scrolled output doesn't leave empty space below it. In follow mode, scroll to the end of the output.
+    pub fn fit_output_scroll(&mut self) {
+        if self.output_follow {
+            self.output_scroll = u16::MAX;
+        }
+        let visible_lines = self.layout.output.height.saturating_sub(2) as usize;
+        let max_scroll = self.command_output.lines().count().saturating_sub(visible_lines);
+        self.output_scroll = self.output_scroll.min(max_scroll.try_into().unwrap_or(u16::MAX));
+    }
+
+    pub fn on_paste(&mut self, text: String) {
+        match &mut self.window_state {
+            WindowState::Main => self.handle_main_window_paste(&text),
+            WindowState::Scratch(scratch) => scratch.insert_text(&text),
+            _ => {}
+        }
+    }
+
+    pub fn on_mouse_event(&mut self, event: MouseEvent) {
+        let double_click = if let MouseEventKind::Down(MouseButton::Left) = event.kind {
+            let now = Instant::now();
+            let is_double_click = self.last_click.is_some_and(|(time, column, row)| {
+                now.duration_since(time) < DOUBLE_CLICK_INTERVAL && (column, row) == (event.column, event.row)
+            });
+            self.last_click = if is_double_click {
+                None
+            } else {
+                Some((now, event.column, event.row))
+            };
+            is_double_click
+        } else {
+            false
+        };
+
+        match &mut self.window_state {
+            WindowState::Main => self.handle_main_window_mouse_event(event, double_click),
+            WindowState::TextView(_, _) => {}
+            WindowState::BookmarkList(state) | WindowState::HistoryList(state) => match event.kind {
+                MouseEventKind::ScrollUp => state.apply_event(KeyCode::Up),
+                MouseEventKind::ScrollDown => state.apply_event(KeyCode::Down),
+                MouseEventKind::Down(MouseButton::Left) => {
+                    if let Some((_, row)) = LayoutRects::inner_position(self.layout.command_list, event.column, event.row) {
+                        let idx = self.layout.command_list_offset + row as usize;
+                        if idx < state.list.len() {
+                            state.selected_idx = Some(idx);
+                            if double_click {
+                                self.handle_window_specific_event(KeyCode::Enter, KeyModifiers::NONE);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            },
+            WindowState::Cheatsheet(state) => match event.kind {
+                MouseEventKind::ScrollUp => state.apply_event(KeyCode::Up),
+                MouseEventKind::ScrollDown => state.apply_event(KeyCode::Down),
+                MouseEventKind::Down(MouseButton::Left) => {
+                    if let Some((_, row)) = LayoutRects::inner_position(self.layout.command_list, event.column, event.row) {
+                        let idx = self.layout.command_list_offset + row as usize;
+                        if idx < state.matching_entries().len() {
+                            state.selected_idx = Some(idx);
+                            if double_click {
+                                self.handle_window_specific_event(KeyCode::Enter, KeyModifiers::NONE);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            },
+            WindowState::Scratch(_) => {}
+        }
+    }
+
     pub fn handle_window_specific_event(&mut self, code: KeyCode, modifiers: KeyModifiers) {
         let window_state = &mut self.window_state;
         match window_state {
@@ -226,11 +1093,33 @@ impl App {
                 KeyCode::Enter => {
                     if let Some(entry) = state.selected_entry() {
                         self.input_state.load_commandentry(entry);
+                        self.entry_execution_mode = entry.execution_mode();
                         self.cached_command_part = None;
                     }
                     self.bookmarks.set_entries(state.list.clone());
                     self.window_state = WindowState::Main;
                 }
+                KeyCode::Char('p') => {
+                    if let Some(entry) = state.selected_entry() {
+                        self.input_state.append_pipe_stage(entry.lines());
+                    }
+                    self.bookmarks.set_entries(state.list.clone());
+                    self.window_state = WindowState::Main;
+                }
+                KeyCode::Char('m') => state.cycle_selected_execution_mode(),
+                KeyCode::Char('s') => state.cycle_sort_order(),
+                KeyCode::Char('S') => state.apply_sort_order(),
+                KeyCode::Up | KeyCode::Down if modifiers.contains(KeyModifiers::ALT) => {
+                    if state.move_selected(code == KeyCode::Up) {
+                        self.bookmarks.set_entries(state.list.clone());
+                    } else if state.sort_order != ListSortOrder::Stored {
+                        self.notification = Some("Bookmarks can only be moved while the list isn't sorted".to_string());
+                    }
+                }
+                KeyCode::Char('e') => {
+                    self.bookmarks.set_entries(state.list.clone());
+                    self.load_copy_of_selected_entry();
+                }
                 _ => state.apply_event(code),
             },
             WindowState::HistoryList(state) => match code {
@@ -239,12 +1128,42 @@ impl App {
                     self.window_state = WindowState::Main;
                 }
                 KeyCode::Enter => {
-                    if let Some(entry) = state.selected_idx.and_then(|idx| state.list.get(idx)) {
+                    if let Some(entry) = state.selected_entry() {
                         self.input_state.load_commandentry(entry);
+                        self.entry_execution_mode = None;
                         self.cached_command_part = None;
                     }
                     self.history.set_entries(state.list.clone());
-                    self.history_idx = state.selected_idx;
+                    self.history_idx = state.selected_list_idx();
+                    self.window_state = WindowState::Main;
+                }
+                KeyCode::Char('e') => {
+                    self.history.set_entries(state.list.clone());
+                    self.load_copy_of_selected_entry();
+                }
+                KeyCode::Char('s') => state.cycle_sort_order(),
+                _ => state.apply_event(code),
+            },
+            WindowState::Scratch(scratch) => match code {
+                KeyCode::Esc => self.window_state = WindowState::Main,
+                KeyCode::Char('s') if modifiers.contains(KeyModifiers::CONTROL) => {
+                    let lines = scratch.content_lines().clone();
+                    self.window_state = WindowState::Main;
+                    self.prime_stdin(lines, None);
+                }
+                KeyCode::Enter => scratch.apply_event(EditorEvent::NewLine),
+                _ => {
+                    if let Some(event) = convert_keyevent_to_editorevent(code, modifiers) {
+                        scratch.apply_event(event);
+                    }
+                }
+            },
+            WindowState::Cheatsheet(state) => match code {
+                KeyCode::Esc => self.window_state = WindowState::Main,
+                KeyCode::Enter => {
+                    if let Some(entry) = state.selected_entry().cloned() {
+                        self.insert_cheatsheet_entry(&entry);
+                    }
                     self.window_state = WindowState::Main;
                 }
                 _ => state.apply_event(code),
@@ -252,7 +1171,601 @@ impl App {
         }
     }
 
+    /// Load the entry selected in the open command list as a new command, detached from the stored entry,
+    /// and go back to the main window to edit it.
+    fn load_copy_of_selected_entry(&mut self) {
+        if let WindowState::BookmarkList(state) | WindowState::HistoryList(state) = &self.window_state
+            && let Some(entry) = state.selected_entry()
+        {
+            self.input_state.set_content(entry.lines().clone());
+            self.history_idx = None;
+            self.entry_execution_mode = None;
+            self.cached_command_part = None;
+        }
+        self.window_state = WindowState::Main;
+    }
+
     pub fn on_tick(&mut self) {
-        self.is_processing_state = self.is_processing_state.map(|x| (x + 1) % 6)
+        self.is_processing_state = self.is_processing_state.map(|x| (x + 1) % 6);
+        self.history.flush_if_due();
+
+        if let Some(receiver) = &self.pretty_json_receiver {
+            match receiver.try_recv() {
+                Ok(pretty_output) => {
+                    self.pretty_json_output = pretty_output;
+                    self.pretty_json_receiver = None;
+                }
+                Err(TryRecvError::Disconnected) => self.pretty_json_receiver = None,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+
+        if let Some(receiver) = &self.substitution_preview_receiver {
+            match receiver.try_recv() {
+                Ok(text) => {
+                    self.window_state = WindowState::TextView("Substitution".to_string(), text);
+                    self.substitution_preview_receiver = None;
+                }
+                Err(TryRecvError::Disconnected) => self.substitution_preview_receiver = None,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+
+        if let Some(watch_interval) = self.watch_interval
+            && self.last_watch_run.elapsed() >= watch_interval
+            && let WindowState::Main = self.window_state
+        {
+            self.last_watch_run = Instant::now();
+            self.execute_content();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command_runner::mock::MockRunner;
+    use std::sync::Arc;
+
+    fn test_app(mode: ExecutionMode) -> App {
+        let shell = vec!["sh".to_string(), "-c".to_string()];
+        test_app_with_handler(CommandExecutionHandler::start(Duration::from_secs(5), mode, shell))
+    }
+
+    fn test_app_with_handler(execution_handler: CommandExecutionHandler) -> App {
+        App::new(
+            execution_handler,
+            false,
+            PiprConfig::default_config(),
+            CommandList::new(None, None),
+            CommandList::new(None, None),
+        )
+    }
+
+    #[test]
+    fn test_executing_empty_command() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        app.config.notify_on_empty_command = true;
+
+        for content in [vec!["  ".to_string()], vec!["# ls".to_string(), "".to_string()]] {
+            app.input_state.set_content(content);
+            app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+            assert_eq!(app.history.len(), 0);
+            assert_eq!(app.is_processing_state, None, "the executor was called");
+            assert_eq!(app.notification.as_deref(), Some("Nothing to run"));
+        }
+
+        app.input_state.set_content(vec!["echo hi".to_string()]);
+        app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(app.history.len(), 1);
+        assert_eq!(app.is_processing_state, Some(0));
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_confirm_unsafe_command() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+
+        app.input_state.set_content(vec!["echo rm x; echo dd y".to_string()]);
+        app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(app.is_processing_state, None, "the command ran without confirmation");
+        let menu = app.opened_key_select_menu.as_ref().expect("no confirmation was asked for");
+        assert_eq!(
+            menu.heading,
+            vec![
+                "2 unsafe pattern(s) matched:",
+                "  `rm x` (matches \"rm\")",
+                "  `dd y` (matches \"dd\")"
+            ]
+        );
+
+        app.on_tui_event(KeyCode::Char('n'), KeyModifiers::NONE);
+        assert!(app.opened_key_select_menu.is_none());
+        assert_eq!(app.is_processing_state, None);
+
+        app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+        app.on_tui_event(KeyCode::Char('y'), KeyModifiers::NONE);
+        assert_eq!(app.is_processing_state, Some(0));
+        assert!(!app.unsafe_confirmed, "the confirmation only applies to a single run");
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_confirm_unsafe_cached_command() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        app.output_cache = Some(OutputCache::new(Duration::from_secs(60), 10));
+
+        app.input_state.set_content(vec!["echo rm x".to_string()]);
+        app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+        app.on_tui_event(KeyCode::Char('y'), KeyModifiers::NONE);
+        wait_for_output(&mut app);
+        app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+        app.on_tui_event(KeyCode::Char('y'), KeyModifiers::NONE);
+        assert!(app.output_from_cache, "the output wasn't taken from the cache");
+        assert!(!app.unsafe_confirmed, "the confirmation only applies to a single run");
+
+        app.input_state.set_content(vec!["echo rm y".to_string()]);
+        app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+        assert!(
+            app.opened_key_select_menu.is_some(),
+            "the next unsafe command ran without confirmation"
+        );
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_toggle_execution_mode() {
+        let mut app = test_app(ExecutionMode::Isolated);
+
+        app.on_tui_event(KeyCode::Char('m'), KeyModifiers::ALT);
+        app.on_tui_event(KeyCode::Char('n'), KeyModifiers::NONE);
+        assert_eq!(
+            app.execution_mode(),
+            ExecutionMode::Isolated,
+            "switched to unsafe without confirmation"
+        );
+
+        app.on_tui_event(KeyCode::Char('m'), KeyModifiers::ALT);
+        app.on_tui_event(KeyCode::Char('y'), KeyModifiers::NONE);
+        assert_eq!(app.execution_mode(), ExecutionMode::Unsafe);
+        app.input_state.set_content(vec!["echo hi".to_string()]);
+        app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+        match app.execution_handler.cmd_out_receive.recv_timeout(Duration::from_secs(5)) {
+            Ok(CmdOutput::Started(_)) => {}
+            _ => panic!("the restarted executor doesn't run commands"),
+        }
+
+        app.on_tui_event(KeyCode::Char('m'), KeyModifiers::ALT);
+        assert!(
+            app.opened_key_select_menu.is_none(),
+            "switching back to isolated needs no confirmation"
+        );
+        let expected_mode = if which::which("bwrap").is_ok() {
+            ExecutionMode::Isolated
+        } else {
+            ExecutionMode::Unsafe
+        };
+        assert_eq!(app.execution_mode(), expected_mode);
+        app.execution_handler.stop();
+    }
+
+    /// Handle the outputs of the executor until the running command finished.
+    fn wait_for_output(app: &mut App) {
+        loop {
+            match app.execution_handler.cmd_out_receive.recv_timeout(Duration::from_secs(5)) {
+                Ok(CmdOutput::Started(_)) => {}
+                Ok(output) => {
+                    app.on_cmd_output(output);
+                    break;
+                }
+                Err(_) => panic!("the command didn't finish"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rerun_last_command() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        app.on_tui_event(KeyCode::Char('l'), KeyModifiers::ALT);
+        assert_eq!(app.notification.as_deref(), Some("No command was run yet"));
+
+        app.input_state
+            .set_content(vec!["echo cached | sed s/cached/again/".to_string()]);
+        app.cached_command_part = Some(CachedCommandPart::new(0, 13, vec!["cached".to_string()]));
+        app.execute_content();
+        wait_for_output(&mut app);
+        app.command_output.clear();
+        app.input_state.set_content(Vec::new());
+        app.cached_command_part = None;
+        app.on_tui_event(KeyCode::Char('l'), KeyModifiers::ALT);
+        assert!(app.input_state.content_str().is_empty(), "the input is left as it is");
+        wait_for_output(&mut app);
+        assert_eq!(
+            app.command_output, "again\n",
+            "the part after the cached output wasn't run with it as stdin"
+        );
+        assert_eq!(
+            app.past_outputs.front().map(|past| past.command.as_str()),
+            Some("echo cached | sed s/cached/again/")
+        );
+
+        app.input_state.set_content(vec!["echo rm x".to_string()]);
+        app.unsafe_confirmed = true;
+        app.execute_content();
+        app.on_tui_event(KeyCode::Char('l'), KeyModifiers::ALT);
+        assert!(
+            app.opened_key_select_menu.is_some(),
+            "the unsafe command was run again without confirmation"
+        );
+        app.on_tui_event(KeyCode::Char('y'), KeyModifiers::NONE);
+        assert!(app.opened_key_select_menu.is_none());
+        assert!(!app.unsafe_confirmed, "the confirmation only applies to a single run");
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_announce_completion() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        app.config.completion_notify = CompletionNotify::Bell;
+        app.config.completion_notify_after = Duration::from_secs(10);
+
+        app.on_cmd_output(CmdOutput::Started(Instant::now()));
+        app.on_cmd_output(CmdOutput::Ok(b"quick\n".to_vec(), Vec::new()));
+        assert!(!app.ring_bell, "commands finishing quickly are not announced");
+
+        app.on_cmd_output(CmdOutput::Started(Instant::now() - Duration::from_secs(11)));
+        app.on_cmd_output(CmdOutput::NotOk("slow and failed".to_string()));
+        assert!(app.ring_bell);
+        assert_eq!(app.last_exit_code, None);
+
+        app.on_cmd_output(CmdOutput::Failed(2, "no such file\n".to_string()));
+        assert_eq!(app.last_exit_code, Some(2));
+        app.on_cmd_output(CmdOutput::Ok(Vec::new(), Vec::new()));
+        assert_eq!(app.last_exit_code, Some(0));
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_prime_stdin_from_stderr() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        let stderr_lines = vec![StderrLine {
+            stdout_offset: 4,
+            text: "warn".to_string(),
+        }];
+        app.on_cmd_output(CmdOutput::Ok(b"one\ntwo\n".to_vec(), stderr_lines));
+
+        app.on_tui_event(KeyCode::F(9), KeyModifiers::ALT);
+        app.on_tui_event(KeyCode::F(9), KeyModifiers::NONE);
+        assert_eq!(app.primed_stdin, Some(StdinSource::Lines(vec!["warn".to_string()])));
+        assert_eq!(app.primed_stdin_stream, Some(StdinStream::Stderr));
+
+        app.on_tui_event(KeyCode::F(9), KeyModifiers::ALT);
+        app.on_tui_event(KeyCode::F(9), KeyModifiers::NONE);
+        assert_eq!(
+            app.primed_stdin,
+            Some(StdinSource::Lines(vec!["one".into(), "warn".into(), "two".into()]))
+        );
+
+        app.on_cmd_output(CmdOutput::Failed(1, "bad\n".to_string()));
+        app.on_tui_event(KeyCode::F(9), KeyModifiers::ALT);
+        app.on_tui_event(KeyCode::F(9), KeyModifiers::ALT);
+        app.on_tui_event(KeyCode::F(9), KeyModifiers::NONE);
+        assert_eq!(app.primed_stdin, Some(StdinSource::Lines(vec!["bad".to_string()])));
+        app.on_tui_event(KeyCode::F(9), KeyModifiers::ALT);
+        app.on_tui_event(KeyCode::F(9), KeyModifiers::NONE);
+        assert_eq!(app.primed_stdin_stream, Some(StdinStream::Both));
+        assert_eq!(
+            app.primed_stdin,
+            Some(StdinSource::Lines(vec!["bad".to_string()])),
+            "the earlier output was used"
+        );
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_scratch_as_stdin() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        app.on_cmd_output(CmdOutput::Ok(b"id,value\n1,40\n".to_vec(), Vec::new()));
+        app.input_state.set_content(vec!["cat data.csv".to_string()]);
+
+        app.on_tui_event(KeyCode::Char('z'), KeyModifiers::ALT);
+        assert!(matches!(app.window_state, WindowState::Scratch(_)));
+        app.on_tui_event(KeyCode::Backspace, KeyModifiers::NONE);
+        app.on_tui_event(KeyCode::Char('2'), KeyModifiers::NONE);
+        app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+        app.on_paste("2,7".to_string());
+        app.on_tui_event(KeyCode::Char('s'), KeyModifiers::CONTROL);
+
+        assert!(matches!(app.window_state, WindowState::Main));
+        assert_eq!(
+            app.primed_stdin,
+            Some(StdinSource::Lines(vec!["id,value".into(), "1,42".into(), "2,7".into()]))
+        );
+        assert_eq!(app.primed_stdin_stream, None);
+        assert!(app.input_state.content_str().is_empty());
+        assert_eq!(app.history.as_strings(), vec!["cat data.csv"]);
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_page_past_outputs() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        let run = |app: &mut App, command: &str, output: &str, with_enter: bool| {
+            app.input_state.set_content(vec![command.to_string()]);
+            if with_enter {
+                app.keep_next_output();
+            }
+            app.on_cmd_output(CmdOutput::Ok(output.as_bytes().to_vec(), Vec::new()));
+        };
+        let shown_command = |app: &App| app.shown_past_output().map(|past| past.command.clone());
+
+        run(&mut app, "ls a", "a\n", true);
+        run(&mut app, "ls b", "b\n", true);
+        // the current output is the most recent past output, so paging starts at the one before it
+        app.page_past_outputs(true);
+        assert_eq!(shown_command(&app).as_deref(), Some("ls a"));
+        app.page_past_outputs(true);
+        assert_eq!(shown_command(&app).as_deref(), Some("ls a"));
+        app.page_past_outputs(false);
+        assert_eq!(shown_command(&app), None);
+
+        run(&mut app, "ls c", "c\n", false);
+        assert_eq!(
+            app.past_outputs.len(),
+            2,
+            "outputs of commands not run with Enter are not kept"
+        );
+        app.page_past_outputs(true);
+        assert_eq!(shown_command(&app).as_deref(), Some("ls b"));
+        assert_eq!(app.shown_past_output().unwrap().output, "b\n");
+        run(&mut app, "ls d", "d\n", false);
+        assert_eq!(shown_command(&app), None, "a new output is shown right away");
+
+        for idx in 0..10 {
+            run(&mut app, &format!("ls {}", idx), "", true);
+        }
+        assert_eq!(app.past_outputs.len(), app.config.output_history_size);
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_recent_dirs() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        let dir = std::env::temp_dir().join(format!("pipr-recent-dir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_name = crate::context_line::display_path(&dir.canonicalize().unwrap());
+
+        for command in [format!("ls {}", dir.display()), format!("ls {} /", dir.display())] {
+            app.input_state.set_content(vec![command]);
+            app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+        }
+        assert_eq!(app.recent_dirs.as_strings(), vec![dir_name.clone(), "/".to_string()]);
+        app.input_state.set_content(vec!["echo rm x; ls /usr".to_string()]);
+        app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+        app.on_tui_event(KeyCode::Char('n'), KeyModifiers::NONE);
+        assert_eq!(
+            app.recent_dirs.len(),
+            2,
+            "the directories of a cancelled command were remembered"
+        );
+
+        app.input_state.set_content(vec!["cd recent-dir".to_string()]);
+        app.input_state.apply_event(crate::lineeditor::EditorEvent::End);
+        app.on_tui_event(KeyCode::Char('g'), KeyModifiers::ALT);
+        app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(app.input_state.content_str(), format!("cd {}", dir_name));
+        app.execution_handler.stop();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_keep_output_scroll() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        let output = |lines: usize| {
+            let stdout = (0..lines).map(|idx| format!("{}\n", idx)).collect::<String>();
+            CmdOutput::Ok(stdout.into_bytes(), Vec::new())
+        };
+
+        app.on_cmd_output(output(100));
+        app.output_scroll = 50;
+        app.on_cmd_output(output(100));
+        assert_eq!(app.output_scroll, 0);
+
+        app.config.keep_output_scroll = true;
+        app.output_scroll = 50;
+        app.on_cmd_output(output(100));
+        assert_eq!(app.output_scroll, 50);
+        app.on_cmd_output(output(20));
+        assert_eq!(app.output_scroll, 19, "the scroll position is clamped to the shorter output");
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_output_selection_stays_in_view() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        let lines: String = (0..30).map(|i| format!("line{}\n", i)).collect();
+        app.on_cmd_output(CmdOutput::Ok(lines.into_bytes(), Vec::new()));
+        app.layout.output = Rect::new(0, 0, 80, 6);
+
+        app.on_tui_event(KeyCode::Char('y'), KeyModifiers::ALT);
+        for _ in 0..5 {
+            app.on_tui_event(KeyCode::Down, KeyModifiers::NONE);
+        }
+        assert_eq!(app.selected_output_text().as_deref(), Some("line5"));
+        assert_eq!(app.output_scroll, 2, "the selected line is below the 4 visible lines");
+
+        app.layout.output = Rect::new(0, 0, 80, 22);
+        app.output_scroll = 0;
+        for _ in 0..10 {
+            app.on_tui_event(KeyCode::Down, KeyModifiers::NONE);
+        }
+        assert_eq!(app.selected_output_text().as_deref(), Some("line15"));
+        assert_eq!(app.output_scroll, 0, "the view scrolled while the selected line was visible");
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_preview_substitution() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        app.input_state.set_content(vec!["echo $(sleep 0.2; echo hi)".to_string()]);
+        app.input_state.cursor_col = 9;
+
+        app.on_tui_event(KeyCode::Char('d'), KeyModifiers::ALT);
+        assert!(
+            matches!(app.window_state, WindowState::Main),
+            "the UI waited for the substitution to finish"
+        );
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while matches!(app.window_state, WindowState::Main) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            app.on_tick();
+        }
+        match &app.window_state {
+            WindowState::TextView(_, text) => assert_eq!(text, "$(sleep 0.2; echo hi)\n\nexpands to:\n\nhi"),
+            _ => panic!("the expansion wasn't shown"),
+        }
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_insert_output_into_input() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        app.on_cmd_output(CmdOutput::Ok(
+            b"  report.txt\n\x1b[1mid\x1b[0m 4242 done\n".to_vec(),
+            Vec::new(),
+        ));
+        app.input_state.set_content(vec!["cat ".to_string()]);
+        app.input_state.apply_event(crate::lineeditor::EditorEvent::End);
+
+        app.on_tui_event(KeyCode::Char('y'), KeyModifiers::ALT);
+        assert_eq!(app.selected_output_text().as_deref(), Some("report.txt"));
+        app.on_tui_event(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(app.input_state.content_str(), "cat report.txt");
+        assert!(app.output_selection.is_none());
+
+        app.on_tui_event(KeyCode::Char('y'), KeyModifiers::ALT);
+        app.on_tui_event(KeyCode::Down, KeyModifiers::NONE);
+        app.on_tui_event(KeyCode::Down, KeyModifiers::NONE);
+        assert_eq!(app.selected_output_text().as_deref(), Some("id 4242 done"));
+        app.on_tui_event(KeyCode::Right, KeyModifiers::NONE);
+        app.on_tui_event(KeyCode::Right, KeyModifiers::NONE);
+        assert_eq!(app.selected_output_text().as_deref(), Some("4242"));
+        app.on_tui_event(KeyCode::Left, KeyModifiers::NONE);
+        app.on_tui_event(KeyCode::Left, KeyModifiers::NONE);
+        app.on_tui_event(KeyCode::Left, KeyModifiers::NONE);
+        assert_eq!(
+            app.selected_output_text().as_deref(),
+            Some("done"),
+            "moving left from the whole line picks the last word"
+        );
+        app.on_tui_event(KeyCode::Esc, KeyModifiers::NONE);
+        assert_eq!(app.input_state.content_str(), "cat report.txt");
+        assert!(!app.should_quit, "Esc only ends the selection");
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_stop_autoeval() {
+        let runner = Arc::new(MockRunner::default());
+        let shell = vec!["sh".to_string(), "-c".to_string()];
+        let handler =
+            CommandExecutionHandler::start_with_runner(Duration::from_secs(5), ExecutionMode::Unsafe, shell, runner.clone());
+        let mut app = test_app_with_handler(handler);
+        let running = runner.prepare("", "");
+        app.autoeval_mode = true;
+        app.input_state.set_content(vec!["sleep 9".to_string()]);
+        app.execute_content();
+        assert!(app.is_processing_state.is_some());
+        let started = app.execution_handler.cmd_out_receive.recv_timeout(Duration::from_secs(5));
+        assert!(matches!(started, Ok(CmdOutput::Started(_))));
+
+        app.on_tui_event(KeyCode::F(12), KeyModifiers::NONE);
+        assert!(!app.autoeval_mode);
+        assert_eq!(app.is_processing_state, None);
+        assert_eq!(
+            app.notification.as_deref(),
+            Some("Autoeval is off, the running command was killed")
+        );
+        assert!(running.was_killed(), "the command kept running");
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_output_follow() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        app.layout.output = Rect::new(0, 0, 80, 12);
+        let output = |lines: usize| (0..lines).map(|idx| format!("{}\n", idx)).collect::<String>().into_bytes();
+        app.on_cmd_output(CmdOutput::Ok(output(30), Vec::new()));
+
+        app.on_tui_event(KeyCode::Char('f'), KeyModifiers::ALT);
+        assert_eq!(app.output_scroll, 20);
+        app.on_cmd_output(CmdOutput::Ok(output(40), Vec::new()));
+        assert_eq!(app.output_scroll, 30, "new output is followed");
+
+        app.on_tui_event(KeyCode::Char('f'), KeyModifiers::ALT);
+        app.on_tui_event(KeyCode::PageUp, KeyModifiers::NONE);
+        let frozen_scroll = app.output_scroll;
+        app.watch_interval = Some(Duration::from_secs(1));
+        app.on_cmd_output(CmdOutput::Ok(output(50), Vec::new()));
+        assert_eq!(app.output_scroll, frozen_scroll, "the scroll position is held without follow");
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_fit_output_scroll() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        let stdout = (0..30).map(|idx| format!("{}\n", idx)).collect::<String>();
+        app.on_cmd_output(CmdOutput::Ok(stdout.into_bytes(), Vec::new()));
+        app.output_scroll = 25;
+        app.layout.output = Rect::new(0, 0, 80, 4);
+        app.fit_output_scroll();
+        assert_eq!(app.output_scroll, 25, "the output still fills the small pane");
+        app.layout.output = Rect::new(0, 0, 80, 12);
+        app.fit_output_scroll();
+        assert_eq!(app.output_scroll, 20);
+        app.layout.output = Rect::new(0, 0, 80, 40);
+        app.fit_output_scroll();
+        assert_eq!(app.output_scroll, 0);
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_page_down_stops_at_output_end() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        let stdout = (0..30).map(|idx| format!("{}\n", idx)).collect::<String>();
+        app.on_cmd_output(CmdOutput::Ok(stdout.into_bytes(), Vec::new()));
+        app.output_expanded = true;
+        app.layout.output = Rect::new(0, 0, 80, 12);
+        for _ in 0..5 {
+            app.on_tui_event(KeyCode::PageDown, KeyModifiers::NONE);
+        }
+        assert_eq!(app.output_scroll, 20);
+        app.on_tui_event(KeyCode::PageUp, KeyModifiers::NONE);
+        assert_eq!(app.output_scroll, 12);
+        app.execution_handler.stop();
+    }
+
+    #[test]
+    fn test_elevation_needs_authentication() {
+        let mut app = test_app(ExecutionMode::Unsafe);
+        app.on_tui_event(KeyCode::Char('a'), KeyModifiers::ALT);
+        assert!(app.should_jump_to_other_cmd.take().is_some(), "no password was asked for");
+        assert!(!app.elevated, "elevated before authenticating");
+        app.on_other_cmd_exit(false);
+        assert!(!app.elevated);
+        assert_eq!(
+            app.notification.as_deref(),
+            Some("Authentication with sudo failed, commands don't run elevated")
+        );
+
+        app.on_tui_event(KeyCode::Char('a'), KeyModifiers::ALT);
+        app.on_other_cmd_exit(true);
+        assert!(app.elevated);
+        app.on_other_cmd_exit(false);
+        assert!(app.elevated, "only the authentication decides about elevation");
+
+        app.set_execution_mode(ExecutionMode::Isolated);
+        assert!(!app.elevated);
+        app.execution_handler.stop();
     }
 }
@@ -0,0 +1,136 @@
+//! A searchable reference of common shell idioms, stored in the same format as bookmarks.
+//! Comment lines at the start of an entry describe it, the remaining lines are the command.
+//! Like in snippets, `||` marks where the cursor is placed after inserting the command.
+use std::path::PathBuf;
+
+use crate::commandlist::{CommandEntry, CommandList};
+
+/// The cheatsheet used when there is no cheatsheet file in the config directory
+pub const DEFAULT_CHEATSHEET: &str = "\
+# count the lines of the input
+wc -l
+---
+# count how often each line occurs, most common first
+sort | uniq -c | sort -rn
+---
+# remove duplicate lines, keeping their order
+awk '!seen[$0]++'
+---
+# print a column of whitespace separated input
+awk '{ print $||1 }'
+---
+# sum up the numbers of the first column
+awk '{ sum += $1 } END { print sum }'
+---
+# print a field, splitting lines at a delimiter
+cut -d',' -f||1
+---
+# search for a pattern, ignoring case
+grep -i '||'
+---
+# show lines not matching a pattern
+grep -v '||'
+---
+# only print the matching part of each line
+grep -oE '||'
+---
+# replace all occurrences of a pattern
+sed 's/||//g'
+---
+# print a range of lines
+sed -n '||1,10p'
+---
+# skip the first line, like the header of a table
+tail -n +2
+---
+# reverse the order of lines
+tac
+---
+# translate characters, like lowercase to uppercase
+tr '[:lower:]' '[:upper:]'
+---
+# squeeze repeated spaces into one
+tr -s ' '
+---
+# run a command for every line of the input
+xargs -I{} ||echo {}
+---
+# find files by name
+find . -name '||'
+---
+# pretty-print JSON
+jq .
+---
+# extract a field of every object in a JSON array
+jq -r '.[].||'
+---
+# show the biggest files and directories
+du -sh * | sort -h
+---
+# join all lines into a single line
+paste -sd' '
+---
+# number the lines of the input
+nl -ba";
+
+/// The entries of the cheatsheet file, or of the default cheatsheet if there is none.
+pub fn load_cheatsheet(path: PathBuf) -> Vec<CommandEntry> {
+    if path.exists() {
+        CommandList::load_from_file(path, None).entries().clone()
+    } else {
+        CommandList::deserialize(None, None, DEFAULT_CHEATSHEET).entries().clone()
+    }
+}
+
+fn is_description_line(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
+/// The description of an entry, taken from its leading comment lines.
+pub fn description(entry: &CommandEntry) -> String {
+    entry
+        .lines()
+        .iter()
+        .take_while(|line| is_description_line(line))
+        .map(|line| line.trim_start().trim_start_matches('#').trim())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The lines of an entry that are inserted into the input.
+pub fn command(entry: &CommandEntry) -> String {
+    entry
+        .lines()
+        .iter()
+        .skip_while(|line| is_description_line(line))
+        .cloned()
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Whether every word of the query appears in the entry, ignoring case.
+pub fn matches_query(entry: &CommandEntry, query: &str) -> bool {
+    let content = entry.as_string().to_lowercase();
+    query.to_lowercase().split_whitespace().all(|word| content.contains(word))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cheatsheet_entries() {
+        let entries = CommandList::deserialize(None, None, DEFAULT_CHEATSHEET).entries().clone();
+        assert!(
+            entries
+                .iter()
+                .all(|entry| !description(entry).is_empty() && !command(entry).is_empty())
+        );
+
+        let entry = CommandEntry::new(vec!["# count lines".into(), "#  of the input".into(), "wc -l".into()]);
+        assert_eq!(description(&entry), "count lines of the input");
+        assert_eq!(command(&entry), "wc -l");
+        assert!(matches_query(&entry, "WC input"));
+        assert!(!matches_query(&entry, "wc words"));
+    }
+}
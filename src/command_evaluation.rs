@@ -1,14 +1,22 @@
-use anyhow::{bail, Context};
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use libc::SIGKILL;
-use std::io::{BufRead, BufReader, Write};
-use std::process::{Child, Command, Stdio};
-use std::sync::atomic::AtomicBool;
+use anyhow::{Context, bail};
+use crossbeam_channel::{Receiver, Sender, after, never, unbounded};
+use portable_pty::{CommandBuilder, MasterPty, PtySize, native_pty_system};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Child;
 use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use wait_timeout::ChildExt;
 
+use crate::command_runner::{CommandRunner, KillFn, ProcessRunner, RunningCommand, pid_killer, spawn_process};
+use crate::shell_session::start_session_worker;
+use crate::util::shell_quote;
+
 // Constants for command execution
 const BUBBLEWRAP_ARGS: &[&str] = &[
     "--ro-bind",
@@ -26,10 +34,27 @@ const BUBBLEWRAP_ARGS: &[&str] = &[
 ];
 const UNSAFE_COMMANDS: [&str; 3] = ["rm ", "mv ", "dd "];
 const UNSAFE_CMD_ERR: &str = "Will not run this command, it's for your own good. Believe me.";
-const SPAWN_ERR: &str = "Unable to spawn command";
+/// Size of the pseudo-terminal commands are run in when using pty mode
+const PTY_SIZE: PtySize = PtySize {
+    rows: 24,
+    cols: 80,
+    pixel_width: 0,
+    pixel_height: 0,
+};
+/// How often a command running in a pseudo-terminal is checked for having finished
+const PTY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How often a request waiting for running commands to exit checks whether it can be started
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Number of commands that may run at once if not configured otherwise
+pub const DEFAULT_MAX_RUNNING_COMMANDS: usize = 4;
+
+/// Placeholder in the configured command wrapper that is replaced by the command
+const WRAPPER_CMD_TOKEN: &str = "{cmd}";
+/// Placeholder in the configured command wrapper that is replaced by the shell-quoted command
+const WRAPPER_QUOTED_CMD_TOKEN: &str = "{cmd_quoted}";
 
 /// Execution mode for commands
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
 pub enum ExecutionMode {
     /// Run commands directly without isolation (potentially dangerous)
     Unsafe,
@@ -37,34 +62,341 @@ pub enum ExecutionMode {
     Isolated,
 }
 
+impl std::str::FromStr for ExecutionMode {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<ExecutionMode> {
+        match s {
+            "unsafe" => Ok(ExecutionMode::Unsafe),
+            "isolated" => Ok(ExecutionMode::Isolated),
+            _ => bail!("Unknown execution mode: {}", s),
+        }
+    }
+}
+
+impl std::fmt::Display for ExecutionMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionMode::Unsafe => write!(f, "unsafe"),
+            ExecutionMode::Isolated => write!(f, "isolated"),
+        }
+    }
+}
+
+/// Id of the most recent reader of a named pipe
+static FIFO_READER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Line ending used for the lines written into the stdin of commands
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl std::str::FromStr for LineEnding {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<LineEnding> {
+        match s {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            _ => bail!("Unknown line ending: {}", s),
+        }
+    }
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+}
+
+/// How lines are written into the stdin of commands
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct StdinFormat {
+    pub line_ending: LineEnding,
+    /// whether the last line ends with a line ending too
+    pub trailing_newline: bool,
+}
+
+impl Default for StdinFormat {
+    fn default() -> Self {
+        StdinFormat {
+            line_ending: LineEnding::Lf,
+            trailing_newline: true,
+        }
+    }
+}
+
+/// Which startup files the shell reads before running a command
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy)]
+pub enum ShellStartup {
+    /// a non-interactive shell, reading none of them
+    #[default]
+    Plain,
+    /// an interactive shell, reading its rc file like `.bashrc`
+    Interactive,
+    /// an interactive login shell, also reading profile files like `.bash_profile`
+    Login,
+}
+
+impl std::str::FromStr for ShellStartup {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<ShellStartup> {
+        match s {
+            "plain" => Ok(ShellStartup::Plain),
+            "interactive" => Ok(ShellStartup::Interactive),
+            "login" => Ok(ShellStartup::Login),
+            _ => bail!("Unknown shell_startup: {}", s),
+        }
+    }
+}
+
+/// The bytes written into stdin for the given lines
+pub fn format_stdin_lines(lines: &[String], format: StdinFormat) -> Vec<u8> {
+    let mut content = lines.join(format.line_ending.as_str());
+    if format.trailing_newline && !lines.is_empty() {
+        content.push_str(format.line_ending.as_str());
+    }
+    content.into_bytes()
+}
+
+/// Where the stdin of a command comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StdinSource {
+    /// Lines that are written into stdin
+    Lines(Vec<String>),
+    /// A regular file that is streamed into stdin
+    File(PathBuf),
+    /// A named pipe that is streamed into stdin. Reading it waits until something is written into the pipe,
+    /// and every execution consumes whatever was written since the last one.
+    Fifo(PathBuf),
+}
+
+impl StdinSource {
+    /// A file or named pipe source, depending on what the path points to
+    pub fn from_path(path: PathBuf) -> anyhow::Result<StdinSource> {
+        let metadata = std::fs::metadata(&path).with_context(|| format!("Can't read stdin from {}", path.display()))?;
+        if metadata.file_type().is_fifo() {
+            Ok(StdinSource::Fifo(path))
+        } else if metadata.is_file() {
+            Ok(StdinSource::File(path))
+        } else {
+            bail!(
+                "Can't read stdin from {}, it is neither a file nor a named pipe",
+                path.display()
+            )
+        }
+    }
+
+    /// Write the source into `writer`, followed by `eof`.
+    /// Files and named pipes are streamed from a background thread, so reading them doesn't hold up the caller.
+    fn write_into<W: Write + Send + 'static>(
+        self,
+        mut writer: W,
+        format: StdinFormat,
+        eof: &'static [u8],
+    ) -> std::io::Result<()> {
+        match self {
+            StdinSource::Lines(lines) => {
+                writer.write_all(&format_stdin_lines(&lines, format))?;
+                writer.write_all(eof)
+            }
+            StdinSource::File(path) => {
+                thread::spawn(move || -> std::io::Result<()> {
+                    std::io::copy(&mut File::open(&path)?, &mut writer)?;
+                    writer.write_all(eof)
+                });
+                Ok(())
+            }
+            StdinSource::Fifo(path) => {
+                let reader_id = FIFO_READER_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                thread::spawn(move || -> std::io::Result<()> {
+                    let Some(mut fifo) = wait_for_fifo_data(&path, reader_id)? else {
+                        return Ok(());
+                    };
+                    std::io::copy(&mut fifo, &mut writer)?;
+                    writer.write_all(eof)
+                });
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Open the named pipe and wait until something was written into it, returning `None` once a newer reader took over.
+/// Opening doesn't block, so readers of superseded runs stop waiting instead of taking away data meant for the newest one.
+fn wait_for_fifo_data(path: &Path, reader_id: usize) -> std::io::Result<Option<File>> {
+    let fifo = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(path)?;
+    let mut poll_fd = libc::pollfd {
+        fd: fifo.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        if FIFO_READER_ID.load(std::sync::atomic::Ordering::SeqCst) != reader_id {
+            return Ok(None);
+        }
+        // SAFETY: poll_fd refers to the pipe, which stays open during the call
+        match unsafe { libc::poll(&mut poll_fd, 1, 100) } {
+            0 => {}
+            ready if ready > 0 => break,
+            _ => {
+                let err = std::io::Error::last_os_error();
+                if err.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+    // the pipe has data now, so reading it blocks again until the writer is done
+    // SAFETY: fcntl only changes the flags of the open pipe
+    unsafe {
+        let flags = libc::fcntl(poll_fd.fd, libc::F_GETFL);
+        libc::fcntl(poll_fd.fd, libc::F_SETFL, flags & !libc::O_NONBLOCK);
+    }
+    Ok(Some(fifo))
+}
+
 /// Represents a command that should be executed, with optional stdin
 pub struct CommandExecutionRequest {
     pub command: String,
-    pub stdin: Option<Vec<String>>,
+    pub stdin: Option<StdinSource>,
+    /// Run the command inside a pseudo-terminal, so it behaves like it would interactively
+    pub use_pty: bool,
+    /// Additional environment variables set for the command
+    pub env: Vec<(String, String)>,
+    /// Overrides the execution mode of the handler for this command
+    pub execution_mode: Option<ExecutionMode>,
+    /// Run the command in the persistent shell session instead of a fresh shell
+    pub use_session: bool,
+    /// How stdin lines are written
+    pub stdin_format: StdinFormat,
+    /// Run the command without a controlling terminal, so prompts on `/dev/tty` fail instead of waiting for input
+    pub detach_terminal: bool,
+    /// Startup files the shell reads before running the command, ignored in the persistent session
+    pub shell_startup: ShellStartup,
+    /// Run the command even if it is flagged as unsafe, as the user confirmed it
+    pub allow_unsafe: bool,
 }
 
 impl CommandExecutionRequest {
     /// Create a new command execution request
-    pub fn new(command: String, stdin: Option<Vec<String>>) -> Self {
-        Self { command, stdin }
+    pub fn new(
+        command: String,
+        stdin: Option<StdinSource>,
+        use_pty: bool,
+        env: Vec<(String, String)>,
+        execution_mode: Option<ExecutionMode>,
+        use_session: bool,
+    ) -> Self {
+        Self {
+            command,
+            stdin,
+            use_pty,
+            env,
+            execution_mode,
+            use_session,
+            stdin_format: StdinFormat::default(),
+            detach_terminal: false,
+            shell_startup: ShellStartup::default(),
+            allow_unsafe: false,
+        }
     }
 }
 
 /// Output from an executed command
 pub enum CmdOutput {
-    /// Command executed successfully with output
-    Ok(String),
+    /// Command was spawned at the given time and is now running
+    Started(Instant),
+    /// Command executed successfully, with the raw bytes of its output and the lines it wrote to stderr
+    Ok(Vec<u8>, Vec<StderrLine>),
+    /// Command exited with a non-zero exit code, with what it wrote to stderr
+    Failed(i32, String),
     /// Command failed with error message
     NotOk(String),
 }
 
+/// A line a command wrote to stderr, together with how many bytes of stdout had been read at that point.
+/// Both streams are read separately, so their order is only as exact as the command flushing its output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StderrLine {
+    pub stdout_offset: usize,
+    pub text: String,
+}
+
+/// Why running a command failed
+#[derive(Debug, thiserror::Error)]
+pub enum ExecError {
+    #[error("Unable to spawn command: {0}")]
+    SpawnFailed(#[source] std::io::Error),
+    #[error("Unable to spawn command in a pseudo-terminal: {0}")]
+    PtyFailed(String),
+    #[error("{}", unsafe_command_error(.0))]
+    UnsafeCommand(Vec<UnsafeMatch>),
+    #[error("Command timed out")]
+    Timeout,
+    #[error(
+        "Command timed out without printing anything, it might be waiting for input on the terminal. \
+         Set detach_terminal = true in the config to make such prompts fail right away"
+    )]
+    WaitingForInput,
+    #[error("bubblewrap (bwrap) was not found. Please make sure you have `bwrap` on your path, or use --no-isolation")]
+    BwrapMissing,
+    #[error("shell_command is empty")]
+    EmptyShellCommand,
+    #[error("Non-zero exit code: {0}")]
+    NonZeroExit(i32),
+    #[error("The command executor is not running")]
+    ExecutorStopped,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
 /// Handles command execution in a separate thread
 pub struct CommandExecutionHandler {
     pub execution_mode: ExecutionMode,
     pub shell_command: Vec<String>,
     pub cmd_out_receive: Receiver<CmdOutput>,
+    cmd_timeout: Duration,
+    /// starts the commands that aren't run in a pseudo-terminal or the persistent session
+    runner: Arc<dyn CommandRunner>,
     cmd_in_send: Sender<CommandExecutionRequest>,
     stop_send: Sender<()>,
+    executor_thread: JoinHandle<()>,
+    running_commands: Arc<RunningCommands>,
+}
+
+/// Counts the commands that haven't exited yet, to cap how many run at once.
+/// Kept across restarts of the executor, as the commands killed by a restart may take a moment to exit.
+#[derive(Debug)]
+struct RunningCommands {
+    count: AtomicUsize,
+    /// 0 means no limit
+    max: AtomicUsize,
+}
+
+impl RunningCommands {
+    fn at_limit(&self) -> bool {
+        let max = self.max.load(std::sync::atomic::Ordering::SeqCst);
+        max > 0 && self.count.load(std::sync::atomic::Ordering::SeqCst) >= max
+    }
+
+    /// Count a started command until the returned guard is dropped, once the command exited.
+    fn track(self: &Arc<Self>) -> RunningCommandGuard {
+        self.count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        RunningCommandGuard(self.clone())
+    }
+}
+
+struct RunningCommandGuard(Arc<RunningCommands>);
+
+impl Drop for RunningCommandGuard {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+    }
 }
 
 impl CommandExecutionHandler {
@@ -75,37 +407,60 @@ impl CommandExecutionHandler {
     /// * `execution_mode` - Mode in which commands are executed (ISOLATED or UNSAFE)
     /// * `shell_command` - Shell command to execute commands with (e.g., `["bash", "-c"]`)
     pub fn start(cmd_timeout: Duration, execution_mode: ExecutionMode, shell_command: Vec<String>) -> Self {
+        Self::start_with_runner(cmd_timeout, execution_mode, shell_command, Arc::new(ProcessRunner))
+    }
+
+    /// Start a CommandExecutionHandler thread that starts commands through the given runner.
+    pub fn start_with_runner(
+        cmd_timeout: Duration,
+        execution_mode: ExecutionMode,
+        shell_command: Vec<String>,
+        runner: Arc<dyn CommandRunner>,
+    ) -> Self {
+        let running_commands = Arc::new(RunningCommands {
+            count: AtomicUsize::new(0),
+            max: AtomicUsize::new(DEFAULT_MAX_RUNNING_COMMANDS),
+        });
+        Self::start_counting(cmd_timeout, execution_mode, shell_command, runner, running_commands)
+    }
+
+    fn start_counting(
+        cmd_timeout: Duration,
+        execution_mode: ExecutionMode,
+        shell_command: Vec<String>,
+        runner: Arc<dyn CommandRunner>,
+        running_commands: Arc<RunningCommands>,
+    ) -> Self {
         let (cmd_in_send, cmd_in_receive) = unbounded::<CommandExecutionRequest>();
         let (cmd_out_send, cmd_out_receive) = unbounded::<CmdOutput>();
         let (stop_send, stop_receive) = unbounded::<()>();
 
-        let executor = Self {
-            shell_command: shell_command.clone(),
-            execution_mode,
-            cmd_in_send,
-            cmd_out_receive,
-            stop_send,
-        };
-
-        thread::spawn(move || {
+        let executor_shell_command = shell_command.clone();
+        let executor_runner = runner.clone();
+        let executor_running_commands = running_commands.clone();
+        let executor_thread = thread::spawn(move || {
+            let shell_command = executor_shell_command;
             let mut active_command: Option<BackgroundChildHandle> = None;
+            // started with the first request that uses the persistent session, and stopped together with the executor
+            let mut session_worker: Option<Sender<CommandExecutionRequest>> = None;
+
+            // the latest request that has to wait until fewer commands are running
+            let mut queued_cmd: Option<CommandExecutionRequest> = None;
 
             loop {
-                crossbeam_channel::select! {
+                let queue_timer = if queued_cmd.is_some() {
+                    after(QUEUE_POLL_INTERVAL)
+                } else {
+                    never()
+                };
+                let new_cmd = crossbeam_channel::select! {
                     recv(cmd_in_receive) -> msg => {
                         let Ok(new_cmd) = msg else { break; };
-                        match spawn_command(&shell_command, &new_cmd.command, execution_mode) {
-                            Ok(mut child) => {
-                                if let Some(stdin_content) = new_cmd.stdin {
-                                    let _ = write_stdin_to_child(&mut child, stdin_content);
-                                }
-                                if let Some(old_command) = active_command.take() {
-                                    old_command.kill();
-                                }
-                                active_command = Some(wait_for_child_and_send_output(child, cmd_timeout, cmd_out_send.clone()));
-                            }
-                            Err(err) => cmd_out_send.send(CmdOutput::NotOk(err.to_string())).unwrap(),
-                        }
+                        new_cmd
+                    },
+                    recv(queue_timer) -> _ => match queued_cmd.take() {
+                        Some(new_cmd) => new_cmd,
+                        None => continue,
                     },
                     recv(stop_receive) -> _ => {
                         if let Some(handle) = active_command.take() {
@@ -114,65 +469,346 @@ impl CommandExecutionHandler {
                         break;
                     },
                 };
+                queued_cmd = None;
+                if new_cmd.use_session {
+                    if let Some(old_command) = active_command.take() {
+                        old_command.kill();
+                    }
+                    let worker = session_worker.get_or_insert_with(|| {
+                        start_session_worker(shell_command.clone(), execution_mode, cmd_timeout, cmd_out_send.clone())
+                    });
+                    if worker.send(new_cmd).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                if executor_running_commands.at_limit() {
+                    // the running command is outdated by the new one anyway
+                    if let Some(old_command) = active_command.take() {
+                        old_command.kill();
+                    }
+                    if executor_running_commands.at_limit() {
+                        queued_cmd = Some(new_cmd);
+                        continue;
+                    }
+                }
+                let execution_mode = new_cmd.execution_mode.unwrap_or(execution_mode);
+                let shell_command = shell_with_startup(&shell_command, new_cmd.shell_startup);
+                let started = if new_cmd.use_pty {
+                    spawn_pty_command(
+                        &shell_command,
+                        &new_cmd.command,
+                        execution_mode,
+                        &new_cmd.env,
+                        new_cmd.allow_unsafe,
+                    )
+                    .map(|child| {
+                        child.write_stdin(new_cmd.stdin.unwrap_or(StdinSource::Lines(Vec::new())), new_cmd.stdin_format);
+                        if let Some(old_command) = active_command.take() {
+                            old_command.kill();
+                        }
+                        // sent before the output can be, so it always arrives first
+                        let _ = cmd_out_send.send(CmdOutput::Started(Instant::now()));
+                        wait_for_pty_child_and_send_output(
+                            child,
+                            cmd_timeout,
+                            executor_running_commands.track(),
+                            cmd_out_send.clone(),
+                        )
+                    })
+                } else {
+                    command_argv(&shell_command, &new_cmd.command, execution_mode, new_cmd.allow_unsafe)
+                        .and_then(|argv| executor_runner.spawn(&argv, &new_cmd.env, new_cmd.detach_terminal))
+                        .map(|mut child| {
+                            if let Some(stdin_content) = new_cmd.stdin {
+                                let _ = write_stdin_to_child(child.as_mut(), stdin_content, new_cmd.stdin_format);
+                            }
+                            if let Some(old_command) = active_command.take() {
+                                old_command.kill();
+                            }
+                            let _ = cmd_out_send.send(CmdOutput::Started(Instant::now()));
+                            wait_for_child_and_send_output(
+                                child,
+                                cmd_timeout,
+                                new_cmd.detach_terminal,
+                                executor_running_commands.track(),
+                                cmd_out_send.clone(),
+                            )
+                        })
+                };
+                match started {
+                    Ok(handle) => active_command = Some(handle),
+                    Err(err) => {
+                        // nobody is listening for results anymore, so there is no point in running further commands
+                        if cmd_out_send.send(CmdOutput::NotOk(err.to_string())).is_err() {
+                            break;
+                        }
+                    }
+                }
             }
         });
 
-        executor
+        Self {
+            shell_command,
+            execution_mode,
+            cmd_timeout,
+            runner,
+            cmd_in_send,
+            cmd_out_receive,
+            stop_send,
+            executor_thread,
+            running_commands,
+        }
+    }
+
+    /// A new executor in the given mode, counting its commands together with the ones of this executor
+    fn restarted(&self, execution_mode: ExecutionMode) -> Self {
+        Self::start_counting(
+            self.cmd_timeout,
+            execution_mode,
+            self.shell_command.clone(),
+            self.runner.clone(),
+            self.running_commands.clone(),
+        )
+    }
+
+    /// Number of commands that were started and haven't exited yet
+    pub fn running_command_count(&self) -> usize {
+        self.running_commands.count.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Wait with starting further commands while this many are still running. 0 disables the limit.
+    pub fn set_max_running_commands(&self, max: usize) {
+        self.running_commands.max.store(max, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Whether the executor thread is still running and accepting commands
+    pub fn is_alive(&self) -> bool {
+        !self.executor_thread.is_finished()
+    }
+
+    /// Restart the executor thread if it stopped, returning whether a restart was necessary.
+    pub fn ensure_running(&mut self) -> bool {
+        if self.is_alive() {
+            return false;
+        }
+        let restarted = self.restarted(self.execution_mode);
+        let _ = std::mem::replace(self, restarted).executor_thread.join();
+        true
+    }
+
+    /// Run further commands in the given mode, restarting the executor thread.
+    /// A running command and the persistent session are stopped, as they run in the old mode.
+    pub fn set_execution_mode(&mut self, execution_mode: ExecutionMode) {
+        if execution_mode == self.execution_mode {
+            return;
+        }
+        let restarted = self.restarted(execution_mode);
+        let mut old_handler = std::mem::replace(self, restarted);
+        old_handler.stop();
+        let _ = old_handler.executor_thread.join();
+    }
+
+    /// Kill the running command right away, restarting the executor thread.
+    /// The persistent session is stopped as well, as a command running in it can't be killed on its own.
+    pub fn kill_running_command(&mut self) {
+        let restarted = self.restarted(self.execution_mode);
+        let mut old_handler = std::mem::replace(self, restarted);
+        old_handler.stop();
+        let _ = old_handler.executor_thread.join();
     }
 
     /// Execute a single command, sending its output to this executor's cmd_out channel
-    pub fn execute(&mut self, cmd: CommandExecutionRequest) {
-        self.cmd_in_send.send(cmd).unwrap();
+    pub fn execute(&mut self, cmd: CommandExecutionRequest) -> Result<(), ExecError> {
+        self.cmd_in_send.send(cmd).map_err(|_| ExecError::ExecutorStopped)
     }
 
     /// Stop the executor thread
     pub fn stop(&mut self) {
-        self.stop_send.send(()).unwrap();
+        // if the executor already stopped, there is nothing left to do
+        let _ = self.stop_send.send(());
     }
 }
 
-/// Check if a command contains potentially unsafe operations
-fn is_unsafe_command(cmd: &str) -> bool {
-    UNSAFE_COMMANDS.iter().any(|&unsafe_cmd| cmd.contains(unsafe_cmd))
+/// A part of a command that contains a potentially unsafe operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsafeMatch {
+    /// the unsafe pattern that matched
+    pub pattern: &'static str,
+    /// the part of the command starting at the match, up to the next operator like `|` or `;`
+    pub matched: String,
 }
 
-/// Spawn a child process with the given command, using the specified execution mode
-///
-/// Returns a Child process with piped stdin, stdout, and stderr
-pub fn spawn_command(shell_command: &[String], cmd: &str, mode: ExecutionMode) -> anyhow::Result<Child> {
-    let mut command = match mode {
-        ExecutionMode::Isolated => {
-            let mut command = Command::new("bwrap");
-            command.args(BUBBLEWRAP_ARGS).args(shell_command.iter());
-            command
-        }
-        ExecutionMode::Unsafe => {
-            if is_unsafe_command(cmd) {
-                bail!(UNSAFE_CMD_ERR);
+impl std::fmt::Display for UnsafeMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` (matches \"{}\")", self.matched, self.pattern.trim())
+    }
+}
+
+/// Every occurrence of a potentially unsafe operation in the command, in the order they appear in.
+pub(crate) fn unsafe_command_matches(cmd: &str) -> Vec<UnsafeMatch> {
+    let mut matches = UNSAFE_COMMANDS
+        .iter()
+        .flat_map(|&pattern| cmd.match_indices(pattern).map(move |(idx, _)| (idx, pattern)))
+        .collect::<Vec<_>>();
+    matches.sort();
+    matches
+        .into_iter()
+        .map(|(idx, pattern)| {
+            let rest = &cmd[idx..];
+            let end = rest.find(['|', ';', '&', '\n', ')']).unwrap_or(rest.len());
+            UnsafeMatch {
+                pattern,
+                matched: rest[..end].trim().to_string(),
             }
-            let mut eval_iter = shell_command.iter();
-            let shell = eval_iter.next().context("shell_command is empty")?;
-            let mut command = Command::new(shell);
-            command.args(eval_iter);
-            command
-        }
+        })
+        .collect()
+}
+
+/// The error shown for a refused command, listing why it was refused.
+fn unsafe_command_error(matches: &[UnsafeMatch]) -> String {
+    let reasons = matches.iter().map(|m| format!("  {}", m)).collect::<Vec<_>>();
+    format!(
+        "{}\nFlagged by {} unsafe pattern(s):\n{}",
+        UNSAFE_CMD_ERR,
+        matches.len(),
+        reasons.join("\n")
+    )
+}
+
+/// Apply a wrapper like `nice -n19 {cmd}` to a command.
+/// A wrapper without any placeholder is used as a prefix.
+pub fn wrap_command(wrapper: &str, cmd: &str) -> String {
+    if wrapper.contains(WRAPPER_QUOTED_CMD_TOKEN) || wrapper.contains(WRAPPER_CMD_TOKEN) {
+        wrapper
+            .replace(WRAPPER_QUOTED_CMD_TOKEN, &shell_quote(cmd))
+            .replace(WRAPPER_CMD_TOKEN, cmd)
+    } else {
+        format!("{} {}", wrapper, cmd)
+    }
+}
+
+/// Run a command through `sudo`, `doas` or a similar program, in a new instance of the given shell.
+/// `-n` makes it fail instead of asking for a password, which would hang as commands have no terminal to ask on.
+pub fn elevate_command(elevation_command: &str, shell_command: &[String], cmd: &str) -> String {
+    let shell = shell_command.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" ");
+    format!("{} -n -- {} {}", elevation_command, shell, shell_quote(cmd))
+}
+
+/// The program and arguments that start the given shell in the specified execution mode
+pub(crate) fn shell_argv(shell_command: &[String], mode: ExecutionMode) -> Vec<String> {
+    match mode {
+        ExecutionMode::Isolated => std::iter::once("bwrap")
+            .chain(BUBBLEWRAP_ARGS.iter().copied())
+            .map(String::from)
+            .chain(shell_command.iter().cloned())
+            .collect(),
+        ExecutionMode::Unsafe => shell_command.to_vec(),
+    }
+}
+
+/// The shell command with the flags making the shell read its startup files, like `["bash", "-l", "+m", "-i", "-c"]`.
+/// Job control is disabled with `+m`, as an interactive shell would otherwise take over the terminal pipr runs in.
+pub fn shell_with_startup(shell_command: &[String], startup: ShellStartup) -> Vec<String> {
+    let flags: &[&str] = match startup {
+        ShellStartup::Plain => &[],
+        ShellStartup::Interactive => &["+m", "-i"],
+        ShellStartup::Login => &["-l", "+m", "-i"],
     };
+    match shell_command.split_first() {
+        Some((shell, args)) => std::iter::once(shell.clone())
+            .chain(flags.iter().map(|flag| flag.to_string()))
+            .chain(args.iter().cloned())
+            .collect(),
+        None => Vec::new(),
+    }
+}
 
-    command
-        .arg(cmd)
-        .stdout(Stdio::piped())
-        .stdin(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .context(SPAWN_ERR)
+/// A human-readable description of what commands run in the given execution mode have access to.
+pub fn sandbox_report(shell_command: &[String], mode: ExecutionMode) -> String {
+    if mode == ExecutionMode::Unsafe {
+        return format!(
+            "Isolation is disabled (--no-isolation).\n\
+             Commands run directly on your system, with all of your permissions.\n\n\
+             Shell: {}",
+            shell_command.join(" ")
+        );
+    }
+    let mut filesystem = Vec::new();
+    let mut other = Vec::new();
+    let mut network = "shared with the host";
+    let mut processes = "visible from the host's pid namespace";
+    let mut args = BUBBLEWRAP_ARGS.iter().copied().peekable();
+    while let Some(flag) = args.next() {
+        let mut values = Vec::new();
+        while let Some(value) = args.next_if(|arg| !arg.starts_with("--")) {
+            values.push(value);
+        }
+        match (flag, values.as_slice()) {
+            ("--ro-bind", [src, dest]) => filesystem.push(format!("{:<8} read-only view of {} on the host", dest, src)),
+            ("--bind", [src, dest]) => filesystem.push(format!("{:<8} writable view of {} on the host", dest, src)),
+            ("--tmpfs", [dest]) => filesystem.push(format!("{:<8} empty, writes are discarded after the command", dest)),
+            ("--dev", [dest]) => filesystem.push(format!("{:<8} minimal set of device nodes", dest)),
+            ("--proc", [dest]) => filesystem.push(format!("{:<8} processes of the sandbox", dest)),
+            ("--share-net", []) => network = "shared with the host",
+            ("--unshare-net", []) => network = "none",
+            ("--unshare-pid", []) => processes = "own pid namespace, processes of the host are not visible",
+            ("--die-with-parent", []) => other.push("commands are killed when pipr exits".to_string()),
+            _ => other.push(format!("{} {}", flag, values.join(" ")).trim_end().to_string()),
+        }
+    }
+    format!(
+        "Commands run isolated using bubblewrap.\n\n\
+         Filesystem:\n  {}\n\n\
+         Network: {}\n\
+         Processes: {}\n\
+         Other:\n  {}\n\n\
+         Full command line:\n  {}",
+        filesystem.join("\n  "),
+        network,
+        processes,
+        other.join("\n  "),
+        shell_argv(shell_command, mode).join(" ")
+    )
+}
+
+/// Build the full argument list that runs the given command in the specified execution mode
+/// Commands flagged as unsafe are refused, unless `allow_unsafe` is set because the user confirmed running them.
+fn command_argv(shell_command: &[String], cmd: &str, mode: ExecutionMode, allow_unsafe: bool) -> Result<Vec<String>, ExecError> {
+    if mode == ExecutionMode::Unsafe {
+        let unsafe_matches = unsafe_command_matches(cmd);
+        if !unsafe_matches.is_empty() && !allow_unsafe {
+            return Err(ExecError::UnsafeCommand(unsafe_matches));
+        }
+        if shell_command.is_empty() {
+            return Err(ExecError::EmptyShellCommand);
+        }
+    }
+    let mut argv = shell_argv(shell_command, mode);
+    argv.push(cmd.to_string());
+    Ok(argv)
+}
+
+/// Spawn a child process with the given command, using the specified execution mode
+///
+/// Returns a Child process with piped stdin, stdout, and stderr
+pub fn spawn_command(
+    shell_command: &[String],
+    cmd: &str,
+    mode: ExecutionMode,
+    env: &[(String, String)],
+    allow_unsafe: bool,
+) -> Result<Child, ExecError> {
+    let argv = command_argv(shell_command, cmd, mode, allow_unsafe)?;
+    spawn_process(&argv, env, false)
 }
 
 /// Execute a command and block until it completes
 ///
 /// Returns the command output as a vector of strings, or an error if execution fails
-pub fn execute_command_blocking(shell_command: &[String], cmd: &str, mode: ExecutionMode) -> anyhow::Result<Vec<String>> {
-    let mut child = spawn_command(shell_command, cmd, mode)?;
-    let stdout = BufReader::new(child.stdout.take().context("No child stdout available")?);
+pub fn execute_command_blocking(shell_command: &[String], cmd: &str, mode: ExecutionMode) -> Result<Vec<String>, ExecError> {
+    let mut child = spawn_command(shell_command, cmd, mode, &[], false)?;
+    let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
     let lines: Vec<String> = stdout
         .lines()
         .collect::<Result<Vec<String>, _>>()
@@ -183,30 +819,144 @@ pub fn execute_command_blocking(shell_command: &[String], cmd: &str, mode: Execu
     if status.success() {
         Ok(lines)
     } else {
-        bail!("Non-zero exit code: {}", status.code().unwrap_or(-1))
+        Err(ExecError::NonZeroExit(status.code().unwrap_or(-1)))
     }
 }
 
-/// Read lines from a BufRead into a single string, stopping on the first error
-fn read_lines_to_string<R: BufRead>(reader: R) -> String {
-    reader
-        .lines()
-        .collect::<Result<Vec<String>, _>>()
-        .map(|x| x.join("\n") + "\n")
-        .unwrap_or_else(|e| e.to_string())
+/// Output of a command that has been run to completion
+pub struct FinishedCommand {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// exit code of the command, `None` if it was terminated by a signal
+    pub exit_code: Option<i32>,
+    /// time from starting the command until it exited
+    pub duration: Duration,
 }
 
-fn write_stdin_to_child(child: &mut Child, stdin_content: Vec<String>) -> anyhow::Result<()> {
-    if let Some(stdin) = &mut child.stdin {
-        for line in stdin_content {
-            writeln!(stdin, "{}", line)?;
+/// Execute a command and block until it completes or `timeout` is reached,
+/// behaving the same way the background executor does for interactive runs.
+///
+/// Returns both output streams and the exit code, or an error if the command could not be run or timed out.
+pub fn execute_command_to_completion(
+    shell_command: &[String],
+    cmd: &str,
+    mode: ExecutionMode,
+    timeout: Duration,
+) -> Result<FinishedCommand, ExecError> {
+    let started_at = Instant::now();
+    let mut child = spawn_command(shell_command, cmd, mode, &[], false)?;
+    // close stdin so commands waiting for input see EOF
+    drop(child.stdin.take());
+
+    // read both streams while the child is running, so it can't block on a full pipe
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let stdout_reader = thread::spawn(move || read_to_bytes(stdout));
+    let stderr_reader = thread::spawn(move || read_to_bytes(stderr));
+
+    let Some(status) = ChildExt::wait_timeout(&mut child, timeout)? else {
+        child.kill()?;
+        child.wait()?;
+        return Err(ExecError::Timeout);
+    };
+    let duration = started_at.elapsed();
+
+    Ok(FinishedCommand {
+        stdout: stdout_reader.join().unwrap_or_default(),
+        stderr: stderr_reader.join().unwrap_or_default(),
+        exit_code: status.code(),
+        duration,
+    })
+}
+
+fn read_to_bytes<R: Read>(mut reader: R) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    let _ = reader.read_to_end(&mut buffer);
+    buffer
+}
+
+/// terminals send \r\n as line ending, which is converted to a plain \n
+fn crlf_to_lf(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    for (idx, &b) in bytes.iter().enumerate() {
+        if !(b == b'\r' && bytes.get(idx + 1) == Some(&b'\n')) {
+            result.push(b);
+        }
+    }
+    result
+}
+
+/// Decode command output for display, replacing invalid UTF-8 and normalizing line endings.
+pub fn decode_output(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).lines().collect::<Vec<_>>().join("\n") + "\n"
+}
+
+/// Read everything from a reader, keeping `bytes_read` up to date while reading
+fn read_to_bytes_counting<R: Read>(mut reader: R, bytes_read: &AtomicUsize) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut buffer = [0; 8192];
+    while let Ok(read @ 1..) = reader.read(&mut buffer) {
+        output.extend_from_slice(&buffer[..read]);
+        bytes_read.store(output.len(), std::sync::atomic::Ordering::SeqCst);
+    }
+    output
+}
+
+/// Read the lines of stderr, noting for each line how much of stdout had been read before it.
+/// On a read error, the error is added as the last line.
+fn read_stderr_lines<R: BufRead>(reader: R, stdout_bytes_read: &AtomicUsize) -> Vec<StderrLine> {
+    let mut lines = Vec::new();
+    for line in reader.lines() {
+        let stdout_offset = stdout_bytes_read.load(std::sync::atomic::Ordering::SeqCst);
+        match line {
+            Ok(text) => lines.push(StderrLine { stdout_offset, text }),
+            Err(err) => {
+                lines.push(StderrLine {
+                    stdout_offset,
+                    text: err.to_string(),
+                });
+                break;
+            }
         }
     }
+    lines
+}
+
+/// Join stderr lines into the text shown in the stderr pane
+fn stderr_lines_to_string(lines: &[StderrLine]) -> String {
+    lines.iter().map(|line| line.text.as_str()).collect::<Vec<_>>().join("\n") + "\n"
+}
+
+/// Insert the stderr lines into the decoded stdout at the point they were written,
+/// starting each of them with `prefix`.
+pub fn merge_stderr_lines(stdout: &[u8], stderr_lines: &[StderrLine], prefix: &str) -> String {
+    let mut merged = String::new();
+    let mut position = 0;
+    for line in stderr_lines {
+        let offset = line.stdout_offset.clamp(position, stdout.len());
+        if offset > position {
+            merged.push_str(&decode_output(&stdout[position..offset]));
+            position = offset;
+        }
+        merged.push_str(prefix);
+        merged.push_str(&line.text);
+        merged.push('\n');
+    }
+    if position < stdout.len() {
+        merged.push_str(&decode_output(&stdout[position..]));
+    }
+    merged
+}
+
+fn write_stdin_to_child(child: &mut dyn RunningCommand, stdin_content: StdinSource, format: StdinFormat) -> anyhow::Result<()> {
+    if let Some(stdin) = child.take_stdin() {
+        stdin_content.write_into(stdin, format, b"")?;
+    }
     Ok(())
 }
 
 struct BackgroundChildHandle {
-    pid: u32,
+    kill: KillFn,
     /// Whether the child has already ended.
     /// If the child has been killed through the [`BackgroundChildHandle`], we don't want to handle its output at all.
     /// If it has already finished normally and sent its output, we don't want to actually kill it on [`Self::kill()`].
@@ -215,54 +965,550 @@ struct BackgroundChildHandle {
 
 impl BackgroundChildHandle {
     fn kill(&self) {
-        if self.already_killed.load(std::sync::atomic::Ordering::SeqCst) {
+        // marked before killing, so the waiting thread can't mistake the killed child for one that finished
+        if self.already_killed.swap(true, std::sync::atomic::Ordering::SeqCst) {
             return;
         }
-        unsafe {
-            libc::kill(self.pid as i32, SIGKILL);
-        }
-        self.already_killed.store(true, std::sync::atomic::Ordering::SeqCst);
+        (self.kill)();
     }
 }
 
 /// Wait for a child process to finish and send its output through the provided channel.
+/// A command that times out is killed. If it didn't print anything, it is reported as possibly waiting for input,
+/// unless it was detached from the terminal and thus can't be prompting on it.
 fn wait_for_child_and_send_output(
-    mut child: Child,
+    mut child: Box<dyn RunningCommand>,
     timeout: std::time::Duration,
+    detached_terminal: bool,
+    running: RunningCommandGuard,
     finished_channel: crossbeam_channel::Sender<CmdOutput>,
 ) -> BackgroundChildHandle {
-    let pid = child.id();
     let already_killed = Arc::new(AtomicBool::new(false));
     let child_handle = BackgroundChildHandle {
-        pid,
+        kill: child.killer(),
         already_killed: already_killed.clone(),
     };
     std::thread::spawn(move || {
+        // counts the command as running until this thread ends, after the command exited
+        let _running = running;
+        // read both streams while the child is running, so it can't block on a full pipe
+        let stdout = BufReader::new(child.take_stdout().unwrap());
+        let stderr = BufReader::new(child.take_stderr().unwrap());
+        let stdout_bytes_read = Arc::new(AtomicUsize::new(0));
+        let stdout_counter = stdout_bytes_read.clone();
+        let stderr_counter = stdout_bytes_read.clone();
+        let stdout_reader = thread::spawn(move || read_to_bytes_counting(stdout, &stdout_counter));
+        let stderr_reader = thread::spawn(move || read_stderr_lines(stderr, &stderr_counter));
+
         let status = child.wait_timeout(timeout);
         if already_killed.load(std::sync::atomic::Ordering::SeqCst) {
             return;
         }
         match status {
             Ok(Some(status)) => {
-                let out_lines = read_lines_to_string(BufReader::new(child.stdout.take().unwrap()));
-                let err_lines = read_lines_to_string(BufReader::new(child.stderr.take().unwrap()));
-                let output = if status.success() {
-                    CmdOutput::Ok(out_lines)
-                } else {
-                    CmdOutput::NotOk(err_lines)
+                let out_bytes = stdout_reader.join().unwrap_or_default();
+                let err_lines = stderr_reader.join().unwrap_or_default();
+                let output = match status.code() {
+                    _ if status.success() => CmdOutput::Ok(out_bytes, err_lines),
+                    Some(code) => CmdOutput::Failed(code, stderr_lines_to_string(&err_lines)),
+                    // terminated by a signal
+                    None => CmdOutput::NotOk(stderr_lines_to_string(&err_lines)),
                 };
-                finished_channel.send(output).unwrap();
+                let _ = finished_channel.send(output);
             }
             Ok(None) => {
-                finished_channel
-                    .send(CmdOutput::NotOk("Command timed out".to_string()))
-                    .unwrap();
+                if !already_killed.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                    (child.killer())();
+                    let _ = child.wait_timeout(timeout);
+                }
+                let error = if !detached_terminal && stdout_bytes_read.load(std::sync::atomic::Ordering::SeqCst) == 0 {
+                    ExecError::WaitingForInput
+                } else {
+                    ExecError::Timeout
+                };
+                let _ = finished_channel.send(CmdOutput::NotOk(error.to_string()));
             }
             Err(err) => {
-                finished_channel.send(CmdOutput::NotOk(err.to_string())).unwrap();
+                let _ = finished_channel.send(CmdOutput::NotOk(err.to_string()));
+            }
+        }
+        already_killed.store(true, std::sync::atomic::Ordering::SeqCst);
+    });
+    child_handle
+}
+
+/// A command running inside a pseudo-terminal
+struct PtyChild {
+    process: Box<dyn portable_pty::Child + Send + Sync>,
+    /// the controlling side of the pty, which must be kept alive while the command is running
+    master: Box<dyn MasterPty + Send>,
+}
+
+impl PtyChild {
+    /// Write the given stdin into the terminal in the background, followed by an end-of-file.
+    fn write_stdin(&self, stdin_content: StdinSource, format: StdinFormat) {
+        let Ok(writer) = self.master.take_writer() else {
+            return;
+        };
+        // ctrl-d, the terminals end of file
+        thread::spawn(move || stdin_content.write_into(writer, format, b"\x04"));
+    }
+}
+
+/// Spawn the given command inside a new pseudo-terminal, so it sees a terminal instead of pipes.
+/// stdout and stderr are both written to the terminal, and are thus merged.
+/// If `COLUMNS` is set in `env`, the terminal is sized accordingly.
+fn spawn_pty_command(
+    shell_command: &[String],
+    cmd: &str,
+    mode: ExecutionMode,
+    env: &[(String, String)],
+    allow_unsafe: bool,
+) -> Result<PtyChild, ExecError> {
+    let argv = command_argv(shell_command, cmd, mode, allow_unsafe)?;
+    let columns = env.iter().find(|(k, _)| k == "COLUMNS").and_then(|(_, v)| v.parse().ok());
+    let pair = native_pty_system()
+        .openpty(PtySize {
+            cols: columns.unwrap_or(PTY_SIZE.cols),
+            ..PTY_SIZE
+        })
+        .map_err(|err| ExecError::PtyFailed(err.to_string()))?;
+    if let Some(fd) = pair.master.as_raw_fd() {
+        disable_terminal_echo(fd);
+    }
+
+    let mut command = CommandBuilder::new(&argv[0]);
+    command.args(&argv[1..]);
+    command.cwd(std::env::current_dir()?);
+    for (key, value) in env {
+        command.env(key, value);
+    }
+    let process = pair
+        .slave
+        .spawn_command(command)
+        .map_err(|err| ExecError::PtyFailed(err.to_string()))?;
+    Ok(PtyChild {
+        process,
+        master: pair.master,
+    })
+}
+
+/// Stop the terminal from echoing the stdin we write into it back into the output
+fn disable_terminal_echo(fd: std::os::unix::io::RawFd) {
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) == 0 {
+            termios.c_lflag &= !libc::ECHO;
+            libc::tcsetattr(fd, libc::TCSANOW, &termios);
+        }
+    }
+}
+
+/// Wait for a command running in a pseudo-terminal to finish and send its output through the provided channel.
+fn wait_for_pty_child_and_send_output(
+    mut child: PtyChild,
+    timeout: Duration,
+    running: RunningCommandGuard,
+    finished_channel: Sender<CmdOutput>,
+) -> BackgroundChildHandle {
+    let already_killed = Arc::new(AtomicBool::new(false));
+    let child_handle = BackgroundChildHandle {
+        kill: pid_killer(child.process.process_id().unwrap_or_default()),
+        already_killed: already_killed.clone(),
+    };
+    thread::spawn(move || {
+        let _running = running;
+        let output_reader = child
+            .master
+            .try_clone_reader()
+            .map(|reader| thread::spawn(move || read_to_bytes(reader)));
+
+        let started_at = Instant::now();
+        let status = loop {
+            match child.process.try_wait() {
+                Ok(None) if started_at.elapsed() < timeout => thread::sleep(PTY_POLL_INTERVAL),
+                status => break status,
             }
+        };
+        if already_killed.load(std::sync::atomic::Ordering::SeqCst) {
+            return;
         }
+        let output = match (status, output_reader) {
+            (Ok(Some(status)), Ok(output_reader)) => {
+                let output = crlf_to_lf(&output_reader.join().unwrap_or_default());
+                if status.success() {
+                    // stderr is part of the terminal output already
+                    CmdOutput::Ok(output, Vec::new())
+                } else {
+                    CmdOutput::Failed(status.exit_code() as i32, String::from_utf8_lossy(&output).into_owned())
+                }
+            }
+            (Ok(None), _) => {
+                if !already_killed.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                    let _ = child.process.kill();
+                    let _ = child.process.wait();
+                }
+                CmdOutput::NotOk(ExecError::Timeout.to_string())
+            }
+            (Err(err), _) => CmdOutput::NotOk(err.to_string()),
+            (_, Err(err)) => CmdOutput::NotOk(err.to_string()),
+        };
+        // the receiver is gone if the executor was restarted in the meantime
+        let _ = finished_channel.send(output);
         already_killed.store(true, std::sync::atomic::Ordering::SeqCst);
     });
     child_handle
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command_runner::mock::MockRunner;
+
+    fn request(command: &str) -> CommandExecutionRequest {
+        CommandExecutionRequest::new(command.to_string(), None, false, Vec::new(), None, false)
+    }
+
+    #[test]
+    fn test_decode_output() {
+        assert_eq!(decode_output(b"a\r\nb"), "a\nb\n");
+        assert_eq!(decode_output(b"\xffa\n"), "\u{fffd}a\n");
+        assert_eq!(crlf_to_lf(b"a\r\nb\r"), b"a\nb\r");
+    }
+
+    #[test]
+    fn test_sandbox_report() {
+        let shell = vec!["sh".to_string(), "-c".to_string()];
+        let report = sandbox_report(&shell, ExecutionMode::Isolated);
+        assert!(report.contains("/        read-only view of / on the host"));
+        assert!(report.contains("/tmp     empty"));
+        assert!(report.contains("Network: shared with the host"));
+        assert!(report.contains("bwrap --ro-bind / / --tmpfs /tmp"));
+        assert!(report.ends_with("--unshare-pid sh -c"));
+        assert!(sandbox_report(&shell, ExecutionMode::Unsafe).contains("Isolation is disabled"));
+    }
+
+    #[test]
+    fn test_merge_stderr_lines() {
+        let line = |stdout_offset, text: &str| StderrLine {
+            stdout_offset,
+            text: text.to_string(),
+        };
+        let stderr = [line(0, "first"), line(2, "middle"), line(99, "last")];
+        assert_eq!(
+            merge_stderr_lines(b"a\nb\n", &stderr, "E| "),
+            "E| first\na\nE| middle\nb\nE| last\n"
+        );
+        assert_eq!(merge_stderr_lines(b"a\n", &[], "E| "), "a\n");
+    }
+
+    #[test]
+    fn test_format_stdin_lines() {
+        let lines = vec!["a".to_string(), "b".to_string()];
+        let format = |line_ending, trailing_newline| StdinFormat {
+            line_ending,
+            trailing_newline,
+        };
+        assert_eq!(format_stdin_lines(&lines, format(LineEnding::Lf, true)), b"a\nb\n");
+        assert_eq!(format_stdin_lines(&lines, format(LineEnding::Lf, false)), b"a\nb");
+        assert_eq!(format_stdin_lines(&lines, format(LineEnding::Crlf, true)), b"a\r\nb\r\n");
+        assert_eq!(format_stdin_lines(&lines, format(LineEnding::Crlf, false)), b"a\r\nb");
+        assert_eq!(format_stdin_lines(&[], format(LineEnding::Lf, true)), b"");
+
+        let shell = vec!["sh".to_string(), "-c".to_string()];
+        let mut handler = CommandExecutionHandler::start(Duration::from_secs(5), ExecutionMode::Unsafe, shell);
+        let mut stdin_request = request("od -An -c");
+        stdin_request.stdin = Some(StdinSource::Lines(lines));
+        stdin_request.stdin_format = format(LineEnding::Crlf, false);
+        handler.execute(stdin_request).unwrap();
+        assert!(matches!(
+            handler.cmd_out_receive.recv_timeout(Duration::from_secs(5)),
+            Ok(CmdOutput::Started(_))
+        ));
+        match handler.cmd_out_receive.recv_timeout(Duration::from_secs(5)) {
+            Ok(CmdOutput::Ok(output, _)) => {
+                let written = decode_output(&output).split_whitespace().collect::<Vec<_>>().join(" ");
+                assert_eq!(written, "a \\r \\n b");
+            }
+            _ => panic!("command did not read its stdin"),
+        }
+        handler.stop();
+    }
+
+    #[test]
+    fn test_elevate_command() {
+        let shell = vec!["bash".to_string(), "-c".to_string()];
+        assert_eq!(
+            elevate_command("sudo", &shell, "ls /root | wc -l"),
+            "sudo -n -- 'bash' '-c' 'ls /root | wc -l'"
+        );
+        assert_eq!(
+            elevate_command("doas", &shell, "echo it's"),
+            "doas -n -- 'bash' '-c' 'echo it'\\''s'"
+        );
+    }
+
+    #[test]
+    fn test_shell_with_startup() {
+        let shell = vec!["bash".to_string(), "-c".to_string()];
+        assert_eq!(shell_with_startup(&shell, ShellStartup::Plain), vec!["bash", "-c"]);
+        assert_eq!(
+            shell_with_startup(&shell, ShellStartup::Interactive),
+            vec!["bash", "+m", "-i", "-c"]
+        );
+        assert_eq!(
+            shell_with_startup(&shell, ShellStartup::Login),
+            vec!["bash", "-l", "+m", "-i", "-c"]
+        );
+        assert!(shell_with_startup(&[], ShellStartup::Login).is_empty());
+    }
+
+    #[test]
+    fn test_unsafe_command_matches() {
+        assert!(unsafe_command_matches("ls -l | wc -l").is_empty());
+        let matches = unsafe_command_matches("mv a b; ls | xargs rm -f && rm c");
+        let reasons = matches.iter().map(|m| (m.pattern, m.matched.as_str())).collect::<Vec<_>>();
+        assert_eq!(reasons, vec![("mv ", "mv a b"), ("rm ", "rm -f"), ("rm ", "rm c")]);
+        assert_eq!(matches[1].to_string(), "`rm -f` (matches \"rm\")");
+        assert!(unsafe_command_error(&matches).contains("Flagged by 3 unsafe pattern(s)"));
+
+        let shell = vec!["sh".to_string(), "-c".to_string()];
+        assert!(matches!(
+            command_argv(&shell, "rm x", ExecutionMode::Unsafe, false),
+            Err(ExecError::UnsafeCommand(matches)) if matches.len() == 1
+        ));
+        assert!(command_argv(&shell, "rm x", ExecutionMode::Unsafe, true).is_ok());
+    }
+
+    #[test]
+    fn test_exec_errors() {
+        let shell = vec!["sh".to_string(), "-c".to_string()];
+        let run = |shell: &[String], cmd: &str| {
+            execute_command_to_completion(shell, cmd, ExecutionMode::Unsafe, Duration::from_millis(300))
+        };
+        assert!(matches!(run(&shell, "sleep 5"), Err(ExecError::Timeout)));
+        assert!(matches!(run(&[], "ls"), Err(ExecError::EmptyShellCommand)));
+        match run(&["/nonexistent/shell".to_string()], "ls") {
+            Err(err @ ExecError::SpawnFailed(_)) => assert!(err.to_string().contains("No such file or directory")),
+            _ => panic!("spawning a missing shell didn't fail"),
+        }
+        assert!(matches!(
+            execute_command_blocking(&shell, "exit 3", ExecutionMode::Unsafe),
+            Err(ExecError::NonZeroExit(3))
+        ));
+        assert_eq!(ExecError::Timeout.to_string(), "Command timed out");
+    }
+
+    #[test]
+    fn test_wrap_command() {
+        assert_eq!(wrap_command("nice -n19 {cmd}", "ls | wc"), "nice -n19 ls | wc");
+        assert_eq!(
+            wrap_command("timeout 5 sh -c {cmd_quoted}", "echo it's"),
+            "timeout 5 sh -c 'echo it'\\''s'"
+        );
+        assert_eq!(wrap_command("time", "ls"), "time ls");
+    }
+
+    #[test]
+    fn test_stdin_sources() {
+        let dir = std::env::temp_dir().join(format!("pipr-test-stdin-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file");
+        std::fs::write(&file, "b\na\n").unwrap();
+        let fifo = dir.join("fifo");
+        let fifo_path = std::ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+        // SAFETY: mkfifo only reads the nul-terminated path
+        assert_eq!(unsafe { libc::mkfifo(fifo_path.as_ptr(), 0o600) }, 0);
+        assert_eq!(StdinSource::from_path(file.clone()).unwrap(), StdinSource::File(file.clone()));
+        assert_eq!(StdinSource::from_path(fifo.clone()).unwrap(), StdinSource::Fifo(fifo.clone()));
+        assert!(StdinSource::from_path(dir.clone()).is_err());
+
+        let shell = vec!["sh".to_string(), "-c".to_string()];
+        let mut handler = CommandExecutionHandler::start(Duration::from_secs(5), ExecutionMode::Unsafe, shell);
+        let fifo_writer = fifo.clone();
+        thread::spawn(move || std::fs::write(fifo_writer, "from the pipe\n"));
+        for (source, expected) in [
+            (StdinSource::File(file), "a\nb\n"),
+            (StdinSource::Fifo(fifo), "from the pipe\n"),
+        ] {
+            let mut stdin_request = request("sort");
+            stdin_request.stdin = Some(source);
+            handler.execute(stdin_request).unwrap();
+            assert!(matches!(
+                handler.cmd_out_receive.recv_timeout(Duration::from_secs(5)),
+                Ok(CmdOutput::Started(_))
+            ));
+            match handler.cmd_out_receive.recv_timeout(Duration::from_secs(5)) {
+                Ok(CmdOutput::Ok(output, _)) => assert_eq!(decode_output(&output), expected),
+                _ => panic!("command did not read its stdin"),
+            }
+        }
+        handler.stop();
+
+        let fifo = dir.join("fifo");
+        let superseded_id = FIFO_READER_ID.fetch_add(2, std::sync::atomic::Ordering::SeqCst) + 1;
+        let started = Instant::now();
+        assert!(
+            wait_for_fifo_data(&fifo, superseded_id).unwrap().is_none(),
+            "a superseded reader got the pipe"
+        );
+        assert!(
+            started.elapsed() < Duration::from_secs(1),
+            "the superseded reader kept waiting for a writer"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_detach_terminal() {
+        let shell = vec!["sh".to_string(), "-c".to_string()];
+        let mut handler = CommandExecutionHandler::start(Duration::from_secs(5), ExecutionMode::Unsafe, shell);
+        let mut tty_request = request("read answer < /dev/tty || echo no terminal");
+        tty_request.detach_terminal = true;
+        handler.execute(tty_request).unwrap();
+        assert!(matches!(
+            handler.cmd_out_receive.recv_timeout(Duration::from_secs(5)),
+            Ok(CmdOutput::Started(_))
+        ));
+        match handler.cmd_out_receive.recv_timeout(Duration::from_secs(2)) {
+            Ok(CmdOutput::Ok(output, _)) => assert_eq!(output, b"no terminal\n"),
+            _ => panic!("the command could prompt on the terminal"),
+        }
+        handler.stop();
+    }
+
+    fn mock_handler(cmd_timeout: Duration) -> (CommandExecutionHandler, Arc<MockRunner>) {
+        let runner = Arc::new(MockRunner::default());
+        let shell = vec!["sh".to_string(), "-c".to_string()];
+        let handler = CommandExecutionHandler::start_with_runner(cmd_timeout, ExecutionMode::Unsafe, shell, runner.clone());
+        (handler, runner)
+    }
+
+    fn next_output(handler: &CommandExecutionHandler) -> Option<CmdOutput> {
+        handler.cmd_out_receive.recv_timeout(Duration::from_millis(500)).ok()
+    }
+
+    #[test]
+    fn test_executor_routes_output() {
+        let (mut handler, runner) = mock_handler(Duration::from_secs(5));
+        let succeeding = runner.prepare("hi\n", "");
+        let failing = runner.prepare("", "oops\n");
+
+        let mut stdin_request = request("cat");
+        stdin_request.stdin = Some(StdinSource::Lines(vec!["a".to_string(), "b".to_string()]));
+        handler.execute(stdin_request).unwrap();
+        assert!(matches!(next_output(&handler), Some(CmdOutput::Started(_))));
+        succeeding.exit(0);
+        match next_output(&handler) {
+            Some(CmdOutput::Ok(output, stderr_lines)) => assert_eq!((output, stderr_lines.len()), (b"hi\n".to_vec(), 0)),
+            _ => panic!("the output of the command was not sent"),
+        }
+        assert_eq!(succeeding.stdin(), b"a\nb\n");
+        assert_eq!(runner.spawned.lock().unwrap()[0], vec!["sh", "-c", "cat"]);
+
+        handler.execute(request("false")).unwrap();
+        assert!(matches!(next_output(&handler), Some(CmdOutput::Started(_))));
+        failing.exit(1);
+        assert!(matches!(next_output(&handler), Some(CmdOutput::Failed(1, stderr)) if stderr == "oops\n"));
+        handler.stop();
+    }
+
+    #[test]
+    fn test_executor_timeout_and_kill() {
+        let (mut handler, runner) = mock_handler(Duration::from_millis(100));
+        let hanging = runner.prepare("", "");
+        let mut detached_request = request("sleep 9");
+        detached_request.detach_terminal = true;
+        handler.execute(detached_request).unwrap();
+        assert!(matches!(next_output(&handler), Some(CmdOutput::Started(_))));
+        assert!(matches!(next_output(&handler), Some(CmdOutput::NotOk(err)) if err == ExecError::Timeout.to_string()));
+        assert!(hanging.was_killed(), "the command kept running after it timed out");
+        // without any output, a command that isn't detached from the terminal might be prompting on it
+        let _waiting = runner.prepare("", "");
+        handler.execute(request("read answer")).unwrap();
+        assert!(matches!(next_output(&handler), Some(CmdOutput::Started(_))));
+        assert!(matches!(next_output(&handler), Some(CmdOutput::NotOk(err)) if err == ExecError::WaitingForInput.to_string()));
+        let _printing = runner.prepare("working\n", "");
+        handler.execute(request("echo working; sleep 9")).unwrap();
+        assert!(matches!(next_output(&handler), Some(CmdOutput::Started(_))));
+        assert!(matches!(next_output(&handler), Some(CmdOutput::NotOk(err)) if err == ExecError::Timeout.to_string()));
+
+        let (mut handler, runner) = mock_handler(Duration::from_secs(5));
+        let superseded = runner.prepare("old\n", "");
+        let newest = runner.prepare("new\n", "");
+        handler.execute(request("echo old")).unwrap();
+        assert!(matches!(next_output(&handler), Some(CmdOutput::Started(_))));
+        handler.execute(request("echo new")).unwrap();
+        assert!(matches!(next_output(&handler), Some(CmdOutput::Started(_))));
+        assert!(
+            superseded.was_killed(),
+            "the running command was not killed when a new one started"
+        );
+        newest.exit(0);
+        assert!(matches!(next_output(&handler), Some(CmdOutput::Ok(output, _)) if output == b"new\n"));
+        assert!(next_output(&handler).is_none(), "the output of the killed command was sent");
+        assert!(!newest.was_killed());
+        handler.stop();
+    }
+
+    #[test]
+    fn test_max_running_commands() {
+        let (mut handler, runner) = mock_handler(Duration::from_secs(5));
+        handler.set_max_running_commands(1);
+        let first = runner.prepare("", "");
+        let second = runner.prepare("second\n", "");
+        handler.execute(request("sleep 9")).unwrap();
+        assert!(matches!(next_output(&handler), Some(CmdOutput::Started(_))));
+        assert_eq!(handler.running_command_count(), 1);
+        // the limit is reached, so the running command is killed before the next one starts
+        handler.execute(request("echo second")).unwrap();
+        assert!(matches!(next_output(&handler), Some(CmdOutput::Started(_))));
+        assert!(first.was_killed());
+        assert_eq!(handler.running_command_count(), 1);
+        second.exit(0);
+        assert!(matches!(next_output(&handler), Some(CmdOutput::Ok(output, _)) if output == b"second\n"));
+        handler.stop();
+        let started_at = Instant::now();
+        while handler.running_command_count() > 0 {
+            assert!(
+                started_at.elapsed() < Duration::from_secs(5),
+                "the finished command is still counted"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn wait_until_stopped(handler: &CommandExecutionHandler) {
+        let started_at = Instant::now();
+        while handler.is_alive() && started_at.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_executor_survives_dropped_receiver() {
+        let shell = vec!["sh".to_string(), "-c".to_string()];
+        let mut handler = CommandExecutionHandler::start(Duration::from_secs(2), ExecutionMode::Unsafe, shell);
+        // drop the receiving end, so the executor can't deliver the error of the refused command
+        handler.cmd_out_receive = crossbeam_channel::never();
+        handler.execute(request("rm foo")).unwrap();
+
+        wait_until_stopped(&handler);
+        let stopped_thread = std::mem::replace(&mut handler.executor_thread, thread::spawn(|| {}));
+        assert!(stopped_thread.join().is_ok(), "executor thread panicked");
+
+        wait_until_stopped(&handler);
+        assert!(handler.ensure_running());
+        assert!(!handler.ensure_running());
+        handler.execute(request("echo hi")).unwrap();
+        assert!(matches!(
+            handler.cmd_out_receive.recv_timeout(Duration::from_secs(5)),
+            Ok(CmdOutput::Started(_))
+        ));
+        match handler.cmd_out_receive.recv_timeout(Duration::from_secs(5)) {
+            Ok(CmdOutput::Ok(output, _)) => assert_eq!(decode_output(&output), "hi\n"),
+            _ => panic!("restarted executor did not run the command"),
+        }
+        handler.stop();
+    }
+}
@@ -0,0 +1,232 @@
+//! Starting the processes commands run in, abstracted so the executor can be tested without running real processes.
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::Duration;
+
+use libc::SIGKILL;
+use wait_timeout::ChildExt;
+
+use crate::command_evaluation::ExecError;
+
+/// Kills a running command, callable from another thread than the one waiting for it
+pub type KillFn = Box<dyn Fn() + Send>;
+
+/// Starts commands from their full argument list, like `["bwrap", ..., "sh", "-c", "ls"]`
+pub trait CommandRunner: Send + Sync {
+    /// Start the command, in a new session without a controlling terminal if `detach_terminal` is set.
+    fn spawn(
+        &self,
+        argv: &[String],
+        env: &[(String, String)],
+        detach_terminal: bool,
+    ) -> Result<Box<dyn RunningCommand>, ExecError>;
+}
+
+/// A command started by a [`CommandRunner`], with piped stdin, stdout and stderr
+pub trait RunningCommand: Send {
+    fn take_stdin(&mut self) -> Option<Box<dyn Write + Send>>;
+    fn take_stdout(&mut self) -> Option<Box<dyn Read + Send>>;
+    fn take_stderr(&mut self) -> Option<Box<dyn Read + Send>>;
+    /// Wait until the command exits, returning `None` if it is still running after `timeout`.
+    fn wait_timeout(&mut self, timeout: Duration) -> std::io::Result<Option<ExitStatus>>;
+    fn killer(&self) -> KillFn;
+}
+
+/// Runs commands as real child processes
+pub struct ProcessRunner;
+
+impl CommandRunner for ProcessRunner {
+    fn spawn(
+        &self,
+        argv: &[String],
+        env: &[(String, String)],
+        detach_terminal: bool,
+    ) -> Result<Box<dyn RunningCommand>, ExecError> {
+        Ok(Box::new(spawn_process(argv, env, detach_terminal)?))
+    }
+}
+
+/// Spawn the argument list as a child process with piped stdin, stdout and stderr.
+/// A detached process runs in its own session, so it can't open `/dev/tty` to prompt on the terminal pipr runs in.
+pub(crate) fn spawn_process(argv: &[String], env: &[(String, String)], detach_terminal: bool) -> Result<Child, ExecError> {
+    let mut command = Command::new(&argv[0]);
+    if detach_terminal {
+        unsafe {
+            command.pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            });
+        }
+    }
+    command
+        .args(&argv[1..])
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdout(Stdio::piped())
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            // telling apart a missing bubblewrap installation
+            if err.kind() == std::io::ErrorKind::NotFound && argv[0] == "bwrap" {
+                ExecError::BwrapMissing
+            } else {
+                ExecError::SpawnFailed(err)
+            }
+        })
+}
+
+impl RunningCommand for Child {
+    fn take_stdin(&mut self) -> Option<Box<dyn Write + Send>> {
+        self.stdin.take().map(|stdin| Box::new(stdin) as Box<dyn Write + Send>)
+    }
+
+    fn take_stdout(&mut self) -> Option<Box<dyn Read + Send>> {
+        self.stdout.take().map(|stdout| Box::new(stdout) as Box<dyn Read + Send>)
+    }
+
+    fn take_stderr(&mut self) -> Option<Box<dyn Read + Send>> {
+        self.stderr.take().map(|stderr| Box::new(stderr) as Box<dyn Read + Send>)
+    }
+
+    fn wait_timeout(&mut self, timeout: Duration) -> std::io::Result<Option<ExitStatus>> {
+        ChildExt::wait_timeout(self, timeout)
+    }
+
+    fn killer(&self) -> KillFn {
+        pid_killer(self.id())
+    }
+}
+
+/// Kills the process with the given id
+pub(crate) fn pid_killer(pid: u32) -> KillFn {
+    Box::new(move || unsafe {
+        libc::kill(pid as i32, SIGKILL);
+    })
+}
+
+/// A [`CommandRunner`] handing out prepared commands instead of running anything
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use crossbeam_channel::{Receiver, Sender, unbounded};
+    use std::collections::VecDeque;
+    use std::os::unix::process::ExitStatusExt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// The test's side of a prepared command, to finish it and inspect what happened to it
+    pub struct MockCommandControl {
+        exit_send: Sender<ExitStatus>,
+        stdin: Arc<Mutex<Vec<u8>>>,
+        killed: Arc<AtomicBool>,
+    }
+
+    impl MockCommandControl {
+        /// Let the command exit with the given code
+        pub fn exit(&self, code: i32) {
+            let _ = self.exit_send.send(ExitStatus::from_raw(code << 8));
+        }
+
+        pub fn stdin(&self) -> Vec<u8> {
+            self.stdin.lock().unwrap().clone()
+        }
+
+        pub fn was_killed(&self) -> bool {
+            self.killed.load(Ordering::SeqCst)
+        }
+    }
+
+    pub struct MockCommand {
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+        exit_send: Sender<ExitStatus>,
+        exit_receive: Receiver<ExitStatus>,
+        stdin: Arc<Mutex<Vec<u8>>>,
+        killed: Arc<AtomicBool>,
+    }
+
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl RunningCommand for MockCommand {
+        fn take_stdin(&mut self) -> Option<Box<dyn Write + Send>> {
+            Some(Box::new(SharedWriter(self.stdin.clone())))
+        }
+
+        fn take_stdout(&mut self) -> Option<Box<dyn Read + Send>> {
+            Some(Box::new(std::io::Cursor::new(std::mem::take(&mut self.stdout))))
+        }
+
+        fn take_stderr(&mut self) -> Option<Box<dyn Read + Send>> {
+            Some(Box::new(std::io::Cursor::new(std::mem::take(&mut self.stderr))))
+        }
+
+        fn wait_timeout(&mut self, timeout: Duration) -> std::io::Result<Option<ExitStatus>> {
+            Ok(self.exit_receive.recv_timeout(timeout).ok())
+        }
+
+        fn killer(&self) -> KillFn {
+            let exit_send = self.exit_send.clone();
+            let killed = self.killed.clone();
+            Box::new(move || {
+                killed.store(true, Ordering::SeqCst);
+                let _ = exit_send.send(ExitStatus::from_raw(SIGKILL));
+            })
+        }
+    }
+
+    /// Hands out the prepared commands in order, recording the arguments they were started with
+    #[derive(Default)]
+    pub struct MockRunner {
+        commands: Mutex<VecDeque<MockCommand>>,
+        pub spawned: Mutex<Vec<Vec<String>>>,
+    }
+
+    impl MockRunner {
+        /// Prepare the next command that is started, which writes the given output and runs until told to exit.
+        pub fn prepare(&self, stdout: &str, stderr: &str) -> MockCommandControl {
+            let (exit_send, exit_receive) = unbounded();
+            let stdin = Arc::new(Mutex::new(Vec::new()));
+            let killed = Arc::new(AtomicBool::new(false));
+            self.commands.lock().unwrap().push_back(MockCommand {
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: stderr.as_bytes().to_vec(),
+                exit_send: exit_send.clone(),
+                exit_receive,
+                stdin: stdin.clone(),
+                killed: killed.clone(),
+            });
+            MockCommandControl {
+                exit_send,
+                stdin,
+                killed,
+            }
+        }
+    }
+
+    impl CommandRunner for MockRunner {
+        fn spawn(
+            &self,
+            argv: &[String],
+            _env: &[(String, String)],
+            _detach_terminal: bool,
+        ) -> Result<Box<dyn RunningCommand>, ExecError> {
+            self.spawned.lock().unwrap().push(argv.to_vec());
+            match self.commands.lock().unwrap().pop_front() {
+                Some(command) => Ok(Box::new(command)),
+                None => Err(ExecError::SpawnFailed(std::io::ErrorKind::NotFound.into())),
+            }
+        }
+    }
+}
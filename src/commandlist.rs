@@ -1,24 +1,81 @@
 //! [`CommandList`] is a list of stored commands that can be persisted to disk.
 //! This is used, amongst other things, to store bookmarks and the command history.
 
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::command_evaluation::ExecutionMode;
 
 const SERIALIZATION_ENTRY_SEPERATOR: &str = "---";
+/// Prefix of the line storing the preferred execution mode of an entry, placed before its content.
+const SERIALIZATION_MODE_PREFIX: &str = "--- mode: ";
+/// Line separating the content of an entry from the output saved with it.
+const SERIALIZATION_OUTPUT_SEPARATOR: &str = "--- output";
+/// Added in front of content lines that start with "---", so they can't be mistaken for a separator or mode line.
+const SERIALIZATION_ESCAPE: char = '\\';
+/// How many symlinks are followed at most when resolving where to write a list to, to not get stuck in a loop.
+const MAX_SYMLINK_DEPTH: usize = 40;
 
 /// A command entry consisting of multiple lines of text.
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct CommandEntry(Vec<String>);
+///
+/// Entries are compared by their content only, the preferred execution mode and saved output are not taken into account.
+#[derive(Debug, Clone)]
+pub struct CommandEntry {
+    lines: Vec<String>,
+    execution_mode: Option<ExecutionMode>,
+    /// lines of the output of the command, saved together with it for reference
+    output: Option<Vec<String>>,
+}
+
+impl PartialEq for CommandEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.lines == other.lines
+    }
+}
+
+impl Eq for CommandEntry {}
+
+impl std::hash::Hash for CommandEntry {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.lines.hash(state);
+    }
+}
 
 impl CommandEntry {
     /// Creates a new command entry from lines of content.
     pub fn new(content: Vec<String>) -> CommandEntry {
-        CommandEntry(content)
+        CommandEntry {
+            lines: content,
+            execution_mode: None,
+            output: None,
+        }
     }
     /// Returns the lines in this entry.
     pub fn lines(&self) -> &Vec<String> {
-        &self.0
+        &self.lines
+    }
+    /// The mode this entry should be executed in, if it differs from the global one.
+    pub fn execution_mode(&self) -> Option<ExecutionMode> {
+        self.execution_mode
+    }
+    pub fn set_execution_mode(&mut self, execution_mode: Option<ExecutionMode>) {
+        self.execution_mode = execution_mode;
+    }
+    /// The output saved together with the command, if any.
+    pub fn output(&self) -> Option<String> {
+        self.output.as_ref().map(|lines| lines.join("\n"))
+    }
+    /// Save the output together with the command. A single trailing newline is not kept.
+    pub fn with_output(mut self, output: &str) -> CommandEntry {
+        let output = output.strip_suffix('\n').unwrap_or(output);
+        self.output = Some(output.split('\n').filter(|_| !output.is_empty()).map(String::from).collect());
+        self
     }
     /// Converts the entry to a single string, joining lines with newlines.
     pub fn as_string(&self) -> String {
@@ -26,21 +83,100 @@ impl CommandEntry {
     }
 }
 
+/// How entries that differ but are similar enough to count as versions of the same command are detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NearDuplicateMatch {
+    /// the first lines are the same
+    FirstLine,
+    /// the first lines start with the same program
+    Program,
+}
+
+impl NearDuplicateMatch {
+    pub fn matches(&self, a: &CommandEntry, b: &CommandEntry) -> bool {
+        let first_line = |entry: &CommandEntry| entry.lines.first().map(|line| line.trim().to_string()).unwrap_or_default();
+        match self {
+            NearDuplicateMatch::FirstLine => first_line(a) == first_line(b),
+            NearDuplicateMatch::Program => {
+                let program = |entry| first_line(entry).split_whitespace().next().map(String::from);
+                program(a).is_some() && program(a) == program(b)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for NearDuplicateMatch {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<NearDuplicateMatch> {
+        match s {
+            "first_line" => Ok(NearDuplicateMatch::FirstLine),
+            "program" => Ok(NearDuplicateMatch::Program),
+            _ => anyhow::bail!("Unknown near duplicate match: {}", s),
+        }
+    }
+}
+
+/// What happens when an entry is added to a list that reached its max size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimStrategy {
+    /// drop the oldest entry to make room
+    DropOldest,
+    /// keep the list as it is and don't add the entry
+    RefuseNew,
+}
+
+impl std::str::FromStr for TrimStrategy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<TrimStrategy> {
+        match s {
+            "drop_oldest" => Ok(TrimStrategy::DropOldest),
+            "refuse_new" => Ok(TrimStrategy::RefuseNew),
+            _ => anyhow::bail!("Unknown trim strategy: {}", s),
+        }
+    }
+}
+
+/// What became of an entry pushed to a list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushResult {
+    Added,
+    /// the entry is empty, the same as the last one or ignored
+    Skipped,
+    /// the list is full and refuses new entries
+    Refused,
+}
+
 /// A list of command entries that can be persisted to disk.
-/// 
-/// When serialized, entries are separated by "---" surrounded by newlines:
+///
+/// When serialized, entries are separated by "---" surrounded by newlines.
+/// Content lines starting with "---" (after any backslashes) get an additional backslash in front,
+/// and blank lines within entries are kept as they are.
+/// An entry with a preferred execution mode starts with a "--- mode: <mode>" line,
+/// and an output saved with an entry follows its content after a "--- output" line, escaped like the content:
 /// ```text
 /// echo hello
 /// ---
+/// --- mode: unsafe
 /// grep pattern file.txt
 /// ---
 /// ls -la
+/// --- output
+/// total 0
 /// ```
+///
+/// By default, every change is written to disk right away. With a write interval,
+/// changes are only marked as unsaved and written by [`CommandList::flush_if_due`] or [`CommandList::flush`].
 #[derive(Debug, Clone)]
 pub struct CommandList {
     entries: Vec<CommandEntry>,
     file: Option<PathBuf>,
     max_size: Option<usize>,
+    trim_strategy: TrimStrategy,
+    write_interval: Option<Duration>,
+    last_write: Instant,
+    dirty: bool,
+    /// commands matching any of these are never added
+    ignore_patterns: Vec<Regex>,
 }
 
 impl CommandList {
@@ -49,10 +185,75 @@ impl CommandList {
         CommandList {
             entries: Vec::new(),
             max_size,
+            trim_strategy: TrimStrategy::DropOldest,
             file,
+            write_interval: None,
+            last_write: Instant::now(),
+            dirty: false,
+            ignore_patterns: Vec::new(),
         }
     }
 
+    /// Limit the list to the max size, handling a full list with the given strategy.
+    /// Entries beyond the max size are dropped right away only when dropping the oldest ones.
+    pub fn with_max_size(mut self, max_size: Option<usize>, trim_strategy: TrimStrategy) -> CommandList {
+        self.max_size = max_size;
+        self.trim_strategy = trim_strategy;
+        self.drop_overflow();
+        self
+    }
+
+    /// Batch changes and write them at most once per interval, instead of on every change.
+    pub fn with_write_interval(mut self, write_interval: Option<Duration>) -> CommandList {
+        self.write_interval = write_interval;
+        self
+    }
+
+    /// Never add commands fully matching one of the patterns.
+    pub fn with_ignore_patterns(mut self, ignore_patterns: Vec<Regex>) -> CommandList {
+        self.ignore_patterns = ignore_patterns;
+        self
+    }
+
+    /// Keep all changes in memory, never writing the list to disk.
+    pub fn read_only(mut self) -> CommandList {
+        self.file = None;
+        self
+    }
+
+    fn is_ignored(&self, command: &CommandEntry) -> bool {
+        let command = command.as_string();
+        self.ignore_patterns.iter().any(|pattern| pattern.is_match(&command))
+    }
+
+    /// The number of entries the list holds at most, `None` if it is unlimited.
+    pub fn max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+
+    /// Whether no entries can be added without dropping or refusing one
+    pub fn is_full(&self) -> bool {
+        self.max_size.is_some_and(|max_size| self.len() >= max_size)
+    }
+
+    /// Drop the oldest entries beyond the max size, unless new entries are refused instead.
+    /// Returns the number of dropped entries.
+    fn drop_overflow(&mut self) -> usize {
+        match self.max_size {
+            Some(max_size) if self.trim_strategy == TrimStrategy::DropOldest && self.len() > max_size => {
+                let overflow = self.len() - max_size;
+                self.entries.drain(0..overflow);
+                overflow
+            }
+            _ => 0,
+        }
+    }
+
+    /// The file the list is stored in, `None` if it is kept in memory only.
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.file.as_ref()
+    }
+
     /// Returns all entries in the list.
     pub fn entries(&self) -> &Vec<CommandEntry> {
         &self.entries
@@ -61,21 +262,52 @@ impl CommandList {
     /// Replaces all entries and saves to disk.
     pub fn set_entries(&mut self, entries: Vec<CommandEntry>) {
         self.entries = entries;
-        self.write_to_file();
+        self.save();
     }
 
     /// Adds a command entry if not empty or duplicate, respecting max size.
-    pub fn push(&mut self, command: CommandEntry) {
-        if !command.as_string().is_empty() && self.entries.last() != Some(&command) {
-            self.entries.push(command);
-            if let Some(max_size) = self.max_size {
-                if self.len() > max_size {
-                    self.entries.remove(0);
-                }
-            }
-            self.write_to_file();
+    pub fn push(&mut self, command: CommandEntry) -> PushResult {
+        if command.as_string().is_empty() || self.entries.last() == Some(&command) || self.is_ignored(&command) {
+            return PushResult::Skipped;
+        }
+        if self.is_full() && self.trim_strategy == TrimStrategy::RefuseNew {
+            return PushResult::Refused;
+        }
+        self.entries.push(command);
+        self.drop_overflow();
+        self.save();
+        PushResult::Added
+    }
+    /// Adds entries that are older than the existing ones to the front of the list, skipping duplicates.
+    /// If the list grows beyond its max size, the oldest entries are dropped, but only added ones when refusing new entries.
+    /// Returns the number of entries that were added.
+    pub fn prepend_unique(&mut self, older_entries: Vec<CommandEntry>) -> usize {
+        let mut seen: HashSet<CommandEntry> = self.entries.iter().cloned().collect();
+        // keep the most recent occurrence of duplicated entries
+        let mut new_entries: Vec<CommandEntry> = older_entries
+            .into_iter()
+            .rev()
+            .filter(|entry| !self.is_ignored(entry) && seen.insert(entry.clone()))
+            .collect();
+        new_entries.reverse();
+
+        let mut added = new_entries.len();
+        new_entries.append(&mut self.entries);
+        self.entries = new_entries;
+        if let Some(max_size) = self.max_size
+            && self.len() > max_size
+        {
+            let overflow = match self.trim_strategy {
+                TrimStrategy::DropOldest => self.len() - max_size,
+                TrimStrategy::RefuseNew => (self.len() - max_size).min(added),
+            };
+            self.entries.drain(0..overflow);
+            added = added.saturating_sub(overflow);
         }
+        self.save();
+        added
     }
+
     /// Returns all entries as strings.
     pub fn as_strings(&self) -> Vec<String> {
         self.entries.iter().map(|x| x.as_string()).collect()
@@ -96,7 +328,27 @@ impl CommandList {
         if let Some(idx) = self.entries.iter().position(|e| e == entry) {
             self.entries.remove(idx);
         }
-        self.write_to_file();
+        self.save();
+    }
+
+    /// Index of an entry that is not equal to the given one, but a near duplicate of it.
+    pub fn find_near_duplicate(&self, entry: &CommandEntry, matching: NearDuplicateMatch) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|existing| existing != entry && matching.matches(existing, entry))
+    }
+
+    /// Replace the entry at the given index, keeping its execution mode unless the new entry has its own.
+    /// A saved output is only kept when the entry is replaced by the same command, as it doesn't fit any other one.
+    pub fn replace_entry(&mut self, idx: usize, mut entry: CommandEntry) {
+        if let Some(existing) = self.entries.get_mut(idx) {
+            entry.execution_mode = entry.execution_mode.or(existing.execution_mode);
+            if *existing == entry {
+                entry.output = entry.output.or(existing.output.take());
+            }
+            *existing = entry;
+            self.save();
+        }
     }
 
     /// Adds the entry if not present, or removes it if present.
@@ -112,39 +364,104 @@ impl CommandList {
 
     /// Serializes entries to a string with separators.
     pub fn serialize(&self) -> String {
-        self.as_strings().join(&format!("\n{}\n", SERIALIZATION_ENTRY_SEPERATOR))
+        let serialized = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let mut content = entry
+                    .lines
+                    .iter()
+                    .map(|line| escape_line(line))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if let Some(output) = &entry.output {
+                    content.push('\n');
+                    content.push_str(SERIALIZATION_OUTPUT_SEPARATOR);
+                    for line in output {
+                        content.push('\n');
+                        content.push_str(&escape_line(line));
+                    }
+                }
+                match entry.execution_mode {
+                    Some(mode) => format!("{}{}\n{}", SERIALIZATION_MODE_PREFIX, mode, content),
+                    None => content,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(&format!("\n{}\n", SERIALIZATION_ENTRY_SEPERATOR));
+        // a single trailing newline is ignored when deserializing, so a trailing blank line needs another one
+        if serialized.ends_with('\n') {
+            serialized + "\n"
+        } else {
+            serialized
+        }
     }
 
     /// Creates a [`CommandList`] from serialized string data.
+    /// Only "---" lines split entries, blank lines within an entry are part of its content.
     pub fn deserialize(path: Option<PathBuf>, max_size: Option<usize>, lines: &str) -> CommandList {
-        let mut entries = CommandList::new(path, max_size);
-        let mut current_entry = Vec::new();
-        for line in lines.lines().filter(|x| !x.is_empty()) {
+        // the file is only set at the end, so loading the entries does not write them back one by one.
+        // The max size is applied at the end as well, so loading a full list doesn't refuse the remaining entries.
+        let mut entries = CommandList::new(None, None);
+        let mut current_entry = CommandEntry::new(Vec::new());
+        // files edited by hand usually end with a newline, which is not part of the last entry
+        let lines = lines.strip_suffix('\n').unwrap_or(lines);
+        for line in lines.split('\n').filter(|_| !lines.is_empty()) {
             if line == SERIALIZATION_ENTRY_SEPERATOR {
-                entries.push(CommandEntry::new(current_entry));
-                current_entry = Vec::new();
+                entries.push(std::mem::replace(&mut current_entry, CommandEntry::new(Vec::new())));
+            } else if let Some(mode) = line.strip_prefix(SERIALIZATION_MODE_PREFIX)
+                && current_entry.lines.is_empty()
+            {
+                current_entry.execution_mode = mode.parse().ok();
+            } else if line == SERIALIZATION_OUTPUT_SEPARATOR && current_entry.output.is_none() {
+                current_entry.output = Some(Vec::new());
+            } else if let Some(output) = current_entry.output.as_mut() {
+                output.push(unescape_line(line).to_owned());
             } else {
-                current_entry.push(line.to_owned());
+                current_entry.lines.push(unescape_line(line).to_owned());
             }
         }
-        if !current_entry.is_empty() {
-            entries.push(CommandEntry::new(current_entry)); // add last started entry
+        if !current_entry.lines.is_empty() {
+            entries.push(current_entry); // add last started entry
         }
 
-        // remove entries to fit into max_size
-        if let Some(max_size) = max_size {
-            if entries.len() > max_size {
-                entries.entries.drain(0..(entries.len() - max_size));
-            }
-        }
+        let mut entries = entries.with_max_size(max_size, TrimStrategy::DropOldest);
+        entries.file = path;
         entries
     }
 
     /// Writes entries to file if path is set.
-    pub fn write_to_file(&self) {
+    pub fn write_to_file(&mut self) {
         if let Some(file) = &self.file {
-            let mut file = File::create(file).unwrap();
-            file.write_all(self.serialize().as_bytes()).unwrap();
+            write_file_atomically(file, &self.serialize()).unwrap();
+        }
+        self.dirty = false;
+        self.last_write = Instant::now();
+    }
+
+    /// Persist a change, either right away or with the next flush if a write interval is set.
+    fn save(&mut self) {
+        if self.write_interval.is_some() {
+            self.dirty = true;
+        } else {
+            self.write_to_file();
+        }
+    }
+
+    /// Write unsaved changes if the write interval has passed since the last write.
+    pub fn flush_if_due(&mut self) {
+        if self
+            .write_interval
+            .is_some_and(|interval| self.last_write.elapsed() >= interval)
+        {
+            self.flush();
+        }
+    }
+
+    /// Write unsaved changes.
+    pub fn flush(&mut self) {
+        if self.dirty {
+            self.write_to_file();
         }
     }
 
@@ -159,3 +476,296 @@ impl CommandList {
         }
     }
 }
+
+fn needs_escaping(line: &str) -> bool {
+    line.trim_start_matches(SERIALIZATION_ESCAPE)
+        .starts_with(SERIALIZATION_ENTRY_SEPERATOR)
+}
+
+fn escape_line(line: &str) -> Cow<'_, str> {
+    if needs_escaping(line) {
+        Cow::Owned(format!("{}{}", SERIALIZATION_ESCAPE, line))
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+fn unescape_line(line: &str) -> &str {
+    if needs_escaping(line) {
+        line.strip_prefix(SERIALIZATION_ESCAPE).unwrap_or(line)
+    } else {
+        line
+    }
+}
+
+/// Follow the symlinks at `path`, returning the path of the file they finally point to, which might not exist yet.
+fn resolve_symlinks(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_SYMLINK_DEPTH {
+        match std::fs::read_link(&path) {
+            // relative link targets are relative to the directory of the link, absolute ones replace the path
+            Ok(target) => path = path.parent().unwrap_or(Path::new("")).join(target),
+            Err(_) => break,
+        }
+    }
+    path
+}
+
+/// Replace the content of the file at `path` by writing a temporary file next to it and renaming it over the file,
+/// so an interrupted write can't leave a truncated file behind.
+/// Symlinks are followed, so a symlinked file is updated instead of the link being replaced by a regular file.
+fn write_file_atomically(path: &Path, content: &str) -> std::io::Result<()> {
+    let target = resolve_symlinks(path);
+    let existing = std::fs::metadata(&target).ok();
+    // special files like /dev/null can't be replaced, so they are written to directly
+    if existing.as_ref().is_some_and(|metadata| !metadata.is_file()) {
+        return File::create(&target)?.write_all(content.as_bytes());
+    }
+    let file_name = target.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let temp_path = target.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+    let result = File::create(&temp_path)
+        .and_then(|mut temp_file| {
+            temp_file.write_all(content.as_bytes())?;
+            temp_file.sync_all()
+        })
+        .and_then(|_| match existing {
+            Some(metadata) => std::fs::set_permissions(&temp_path, metadata.permissions()),
+            None => Ok(()),
+        })
+        .and_then(|_| std::fs::rename(&temp_path, &target));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_execution_mode_roundtrip() {
+        let mut list = CommandList::new(None, None);
+        let mut unsafe_entry = CommandEntry::new(vec!["curl example.com".into(), "| jq".into()]);
+        unsafe_entry.set_execution_mode(Some(ExecutionMode::Unsafe));
+        list.push(CommandEntry::new(vec!["ls".into()]));
+        list.push(unsafe_entry);
+
+        let serialized = list.serialize();
+        assert_eq!(serialized, "ls\n---\n--- mode: unsafe\ncurl example.com\n| jq");
+        let deserialized = CommandList::deserialize(None, None, &serialized);
+        let modes = deserialized.entries().iter().map(|x| x.execution_mode()).collect::<Vec<_>>();
+        assert_eq!(deserialized.entries(), list.entries());
+        assert_eq!(modes, vec![None, Some(ExecutionMode::Unsafe)]);
+    }
+
+    #[test]
+    fn test_output_roundtrip() {
+        let mut list = CommandList::new(None, None);
+        list.push(CommandEntry::new(vec!["df -h".into()]).with_output("Filesystem Size\n/dev/sda1 10G\n"));
+        list.push(CommandEntry::new(vec!["echo".into(), "--- output".into()]).with_output("---\n\n"));
+        list.push(CommandEntry::new(vec!["true".into()]).with_output(""));
+        list.push(CommandEntry::new(vec!["ls".into()]));
+
+        let serialized = list.serialize();
+        assert!(serialized.starts_with("df -h\n--- output\nFilesystem Size\n/dev/sda1 10G\n---\n"));
+        let deserialized = CommandList::deserialize(None, None, &serialized);
+        assert_eq!(deserialized.entries(), list.entries());
+        let outputs = deserialized.entries().iter().map(|x| x.output()).collect::<Vec<_>>();
+        assert_eq!(
+            outputs,
+            vec![
+                Some("Filesystem Size\n/dev/sda1 10G".to_string()),
+                Some("---\n".to_string()),
+                Some(String::new()),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_separator_lines_roundtrip() {
+        let entries = vec![
+            CommandEntry::new(vec![
+                "cat <<EOF".into(),
+                "a: 1".into(),
+                "---".into(),
+                "b: 2".into(),
+                "EOF".into(),
+            ]),
+            CommandEntry::new(vec!["--- mode: unsafe".into(), "\\---".into(), "---x".into()]),
+            CommandEntry::new(vec!["echo a".into(), "".into(), "".into(), "echo b".into()]),
+            CommandEntry::new(vec!["ends with blank lines".into(), "".into(), "".into()]),
+        ];
+        let mut list = CommandList::new(None, None);
+        list.set_entries(entries.clone());
+        let deserialized = CommandList::deserialize(None, None, &list.serialize());
+        let lines = deserialized.entries().iter().map(|x| x.lines().clone()).collect::<Vec<_>>();
+        assert_eq!(lines, entries.iter().map(|x| x.lines().clone()).collect::<Vec<_>>());
+        assert_eq!(deserialized.entries()[1].execution_mode(), None);
+    }
+
+    #[test]
+    fn test_blank_lines_roundtrip_through_file() {
+        let path = std::env::temp_dir().join(format!("pipr-test-bookmarks-{}", std::process::id()));
+        let heredoc = CommandEntry::new(
+            [
+                "",
+                "cat <<'EOF' | grep -c ''",
+                "first paragraph",
+                "",
+                "",
+                "second paragraph",
+                "EOF",
+                "",
+            ]
+            .iter()
+            .map(|x| x.to_string())
+            .collect(),
+        );
+        let mut list = CommandList::new(Some(path.clone()), None);
+        list.push(CommandEntry::new(vec!["ls".into()]));
+        list.push(heredoc.clone());
+        list.push(CommandEntry::new(vec!["".into(), "pwd".into()]));
+
+        let loaded = CommandList::load_from_file(path.clone(), None);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.entries()[1].lines(), heredoc.lines());
+        assert_eq!(loaded.as_strings(), list.as_strings());
+    }
+
+    #[test]
+    fn test_read_only() {
+        let path = std::env::temp_dir().join(format!("pipr-test-read-only-{}", std::process::id()));
+        std::fs::write(&path, "ls").unwrap();
+        let mut list = CommandList::load_from_file(path.clone(), None).read_only();
+        list.push(CommandEntry::new(vec!["pwd".into()]));
+        list.flush();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(list.as_strings(), vec!["ls", "pwd"]);
+        assert_eq!(content, "ls");
+    }
+
+    #[test]
+    fn test_deserialize_trailing_newline() {
+        let list = CommandList::deserialize(None, None, "ls\n---\npwd\n");
+        assert_eq!(list.as_strings(), vec!["ls", "pwd"]);
+        assert!(CommandList::deserialize(None, None, "").entries().is_empty());
+    }
+
+    #[test]
+    fn test_write_through_symlink() {
+        let dir = std::env::temp_dir().join(format!("pipr-test-symlink-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("dotfiles")).unwrap();
+        std::fs::write(dir.join("dotfiles/bookmarks"), "old").unwrap();
+        std::os::unix::fs::symlink("dotfiles/bookmarks", dir.join("bookmarks")).unwrap();
+        // a link to a file that doesn't exist yet creates the file
+        std::os::unix::fs::symlink(dir.join("dotfiles/history"), dir.join("history")).unwrap();
+
+        for name in ["bookmarks", "history"] {
+            let mut list = CommandList::new(Some(dir.join(name)), None);
+            list.push(CommandEntry::new(vec!["ls".into()]));
+            assert!(std::fs::symlink_metadata(dir.join(name)).unwrap().file_type().is_symlink());
+            assert_eq!(std::fs::read_to_string(dir.join("dotfiles").join(name)).unwrap(), "ls");
+        }
+        assert_eq!(
+            std::fs::read_dir(dir.join("dotfiles")).unwrap().count(),
+            2,
+            "temporary files were left behind"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_near_duplicates() {
+        let entry = |lines: &[&str]| CommandEntry::new(lines.iter().map(|x| x.to_string()).collect());
+        let mut list = CommandList::new(None, None);
+        list.push(entry(&["grep -r foo", "| wc -l"]));
+        list.push(entry(&["ls"]));
+
+        let similar = entry(&["grep -r foo ", "| head"]);
+        assert_eq!(list.find_near_duplicate(&similar, NearDuplicateMatch::FirstLine), Some(0));
+        assert_eq!(
+            list.find_near_duplicate(&entry(&["grep -i bar"]), NearDuplicateMatch::FirstLine),
+            None
+        );
+        assert_eq!(
+            list.find_near_duplicate(&entry(&["grep -i bar"]), NearDuplicateMatch::Program),
+            Some(0)
+        );
+        assert_eq!(
+            list.find_near_duplicate(&entry(&["ls"]), NearDuplicateMatch::Program),
+            None,
+            "equal entries are no near duplicates"
+        );
+
+        list.entries[0].set_execution_mode(Some(ExecutionMode::Unsafe));
+        list.entries[0].output = Some(vec!["3".to_string()]);
+        list.replace_entry(0, similar.clone());
+        assert_eq!(list.entries(), &vec![similar, entry(&["ls"])]);
+        assert_eq!(list.entries()[0].execution_mode(), Some(ExecutionMode::Unsafe));
+        assert_eq!(list.entries()[0].output, None, "the output of a different command was kept");
+    }
+
+    #[test]
+    fn test_batched_writes() {
+        let path = std::env::temp_dir().join(format!("pipr-test-history-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut list = CommandList::new(Some(path.clone()), None).with_write_interval(Some(Duration::from_secs(3600)));
+        list.push(CommandEntry::new(vec!["ls".into()]));
+        list.flush_if_due();
+        assert!(!path.exists());
+
+        list.flush();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ls");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_ignore_patterns() {
+        let path = std::env::temp_dir().join(format!("pipr-test-history-ignore-{}", std::process::id()));
+        let patterns = vec![Regex::new("^(?:clear)$").unwrap(), Regex::new("^(?:ls( -\\w+)*)$").unwrap()];
+        let mut list = CommandList::new(Some(path.clone()), None).with_ignore_patterns(patterns);
+        for command in ["clear", "ls -la", "ls | wc -l", "lsblk"] {
+            list.push(CommandEntry::new(vec![command.into()]));
+        }
+        list.prepend_unique(vec![
+            CommandEntry::new(vec!["ls".into()]),
+            CommandEntry::new(vec!["pwd".into()]),
+        ]);
+
+        let loaded = CommandList::load_from_file(path.clone(), None);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.as_strings(), vec!["pwd", "ls | wc -l", "lsblk"]);
+    }
+
+    #[test]
+    fn test_trim_strategy() {
+        let entry = |command: &str| CommandEntry::new(vec![command.into()]);
+        let mut dropping = CommandList::new(None, Some(2));
+        for command in ["a", "b", "c"] {
+            assert_eq!(dropping.push(entry(command)), PushResult::Added);
+        }
+        assert_eq!(dropping.as_strings(), vec!["b", "c"]);
+
+        let mut refusing =
+            CommandList::deserialize(None, None, "a\n---\nb\n---\nc").with_max_size(Some(2), TrimStrategy::RefuseNew);
+        assert_eq!(refusing.len(), 3, "entries beyond the max size are kept");
+        assert_eq!(
+            refusing.push(entry("c")),
+            PushResult::Skipped,
+            "a repeated entry is no refused one"
+        );
+        assert_eq!(refusing.push(entry("d")), PushResult::Refused);
+        assert_eq!(refusing.prepend_unique(vec![entry("z")]), 0);
+        assert_eq!(refusing.as_strings(), vec!["a", "b", "c"]);
+
+        let mut refusing = CommandList::new(None, Some(2)).with_max_size(Some(2), TrimStrategy::RefuseNew);
+        refusing.push(entry("a"));
+        assert_eq!(refusing.prepend_unique(vec![entry("y"), entry("z")]), 1);
+        assert_eq!(refusing.as_strings(), vec!["z", "a"]);
+        assert!(refusing.is_full());
+    }
+}
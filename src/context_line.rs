@@ -0,0 +1,144 @@
+//! A configurable, prompt-like line shown above the input, giving some context about where commands are run.
+//! The format supports the tokens `{cwd}`, `{git_branch}`, `{mode}` and `{hostname}`.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::command_evaluation::ExecutionMode;
+
+/// The git branch is looked up at most this often, as the context line is resolved on every draw.
+const GIT_BRANCH_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Values the tokens of the context line format are replaced with
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ContextInfo {
+    pub cwd: String,
+    pub git_branch: Option<String>,
+    pub mode: String,
+    pub hostname: String,
+}
+
+pub struct ContextLine {
+    format: String,
+    hostname: String,
+    git_branch: Option<String>,
+    git_branch_checked_at: Option<Instant>,
+}
+
+impl ContextLine {
+    pub fn new(format: String) -> ContextLine {
+        ContextLine {
+            format,
+            hostname: hostname().unwrap_or_default(),
+            git_branch: None,
+            git_branch_checked_at: None,
+        }
+    }
+
+    /// Resolve the configured format for the current state.
+    pub fn render(&mut self, execution_mode: ExecutionMode) -> String {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        if self
+            .git_branch_checked_at
+            .is_none_or(|time| time.elapsed() >= GIT_BRANCH_REFRESH_INTERVAL)
+        {
+            self.git_branch = git_branch(&cwd);
+            self.git_branch_checked_at = Some(Instant::now());
+        }
+        let info = ContextInfo {
+            cwd: display_path(&cwd),
+            git_branch: self.git_branch.clone(),
+            mode: execution_mode.to_string(),
+            hostname: self.hostname.clone(),
+        };
+        resolve_format(&self.format, &info)
+    }
+}
+
+pub fn resolve_format(format: &str, info: &ContextInfo) -> String {
+    format
+        .replace("{cwd}", &info.cwd)
+        .replace("{git_branch}", info.git_branch.as_deref().unwrap_or(""))
+        .replace("{mode}", &info.mode)
+        .replace("{hostname}", &info.hostname)
+}
+
+/// path with the home directory replaced by ~
+pub(crate) fn display_path(path: &Path) -> String {
+    let home = std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .filter(|home| !home.as_os_str().is_empty());
+    match home.and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(relative) if relative.as_os_str().is_empty() => "~".to_string(),
+        Some(relative) => format!("~/{}", relative.to_string_lossy()),
+        None => path.to_string_lossy().to_string(),
+    }
+}
+
+/// Find the checked out branch of the repository containing dir, by reading its HEAD file.
+fn git_branch(dir: &Path) -> Option<String> {
+    let dot_git = dir.ancestors().map(|dir| dir.join(".git")).find(|path| path.exists())?;
+    // in worktrees and submodules, .git is a file pointing to the actual git directory
+    let git_dir = if dot_git.is_file() {
+        let content = fs::read_to_string(&dot_git).ok()?;
+        let git_dir = PathBuf::from(content.strip_prefix("gitdir:")?.trim());
+        dot_git.parent()?.join(git_dir)
+    } else {
+        dot_git
+    };
+    parse_git_head(&fs::read_to_string(git_dir.join("HEAD")).ok()?)
+}
+
+/// The branch name, or the abbreviated commit hash for a detached HEAD
+fn parse_git_head(head: &str) -> Option<String> {
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(reference.trim_start_matches("refs/heads/").to_string()),
+        None if head.len() >= 7 && head.chars().all(|c| c.is_ascii_hexdigit()) => Some(head[..7].to_string()),
+        None => None,
+    }
+}
+
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for the given length, and gethostname null-terminates on success
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_format() {
+        let info = ContextInfo {
+            cwd: "~/src/pipr".to_string(),
+            git_branch: Some("main".to_string()),
+            mode: "isolated".to_string(),
+            hostname: "box".to_string(),
+        };
+        assert_eq!(
+            resolve_format("{hostname}:{cwd} ({git_branch}) [{mode}]", &info),
+            "box:~/src/pipr (main) [isolated]"
+        );
+        let info = ContextInfo {
+            git_branch: None,
+            ..info
+        };
+        assert_eq!(resolve_format("{git_branch}{cwd}", &info), "~/src/pipr");
+    }
+
+    #[test]
+    fn test_parse_git_head() {
+        assert_eq!(parse_git_head("ref: refs/heads/feature/x\n"), Some("feature/x".to_string()));
+        assert_eq!(
+            parse_git_head("0123456789abcdef0123456789abcdef01234567\n"),
+            Some("0123456".to_string())
+        );
+        assert_eq!(parse_git_head("garbage"), None);
+    }
+}
@@ -0,0 +1,88 @@
+//! Finding the environment variables a line refers to, to preview what they expand to before running it.
+use std::collections::HashMap;
+
+/// Names of the variables referenced as `$NAME` or `${NAME...}` in the line, in order and without duplicates.
+/// References within single quotes or escaped with a backslash are not expanded by the shell, and thus skipped,
+/// as are special parameters like `$1` or `$?`.
+pub fn find_variables(line: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut in_single_quotes = false;
+    let mut in_double_quotes = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '\\' if !in_single_quotes => {
+                chars.next();
+            }
+            '\'' if !in_double_quotes => in_single_quotes = !in_single_quotes,
+            '"' if !in_single_quotes => in_double_quotes = !in_double_quotes,
+            '$' if !in_single_quotes => {
+                let rest = &line[idx + 1..];
+                let rest = rest.strip_prefix('{').unwrap_or(rest);
+                let name_len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                let name = &rest[..name_len];
+                if !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) && !names.iter().any(|n| n == name) {
+                    names.push(name.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+/// A line per variable in the line, describing what it expands to with the given environment.
+pub fn describe_expansions(line: &str, env: &HashMap<String, String>) -> Vec<String> {
+    find_variables(line)
+        .into_iter()
+        .map(|name| {
+            let assigned_in_line = line.match_indices(&format!("{}=", name)).any(|(idx, _)| {
+                line[..idx]
+                    .chars()
+                    .last()
+                    .is_none_or(|c| c.is_whitespace() || ";&|(".contains(c))
+            });
+            match env.get(&name) {
+                _ if assigned_in_line => format!("${} is assigned by the command itself", name),
+                Some(value) if value.is_empty() => format!("${} is set, but empty", name),
+                Some(value) => format!("${} = {}", name, value),
+                None => format!("${} is not set, and expands to nothing", name),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_variables() {
+        assert_eq!(
+            find_variables("echo $HOME ${USER}/x \"$HOME\" $1 $? $(pwd)"),
+            vec!["HOME", "USER"]
+        );
+        assert!(find_variables("echo '$HOME' \\$HOME").is_empty());
+        assert_eq!(find_variables("echo \"it's $HOME\" '\"$USER\"'"), vec!["HOME"]);
+        assert_eq!(find_variables("echo ${X:-default}$Y_2"), vec!["X", "Y_2"]);
+    }
+
+    #[test]
+    fn test_describe_expansions() {
+        let env = HashMap::from([
+            ("HOME".to_string(), "/home/me".to_string()),
+            ("EMPTY".to_string(), String::new()),
+        ]);
+        assert_eq!(
+            describe_expansions("ls $HOME $EMPTY $MISSING; X=1; echo $X", &env),
+            vec![
+                "$HOME = /home/me",
+                "$EMPTY is set, but empty",
+                "$MISSING is not set, and expands to nothing",
+                "$X is assigned by the command itself",
+            ]
+        );
+    }
+}
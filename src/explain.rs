@@ -0,0 +1,306 @@
+//! A simple breakdown of a shell command into its sequential commands and piped stages,
+//! listing the flags, arguments and redirections of each stage.
+use itertools::Itertools;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    /// a control operator like `|`, `&&` or `;`
+    Operator(String),
+    /// a redirection operator like `>`, `2>>` or `<`
+    Redirect(String),
+}
+
+/// A single command within a pipeline
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stage {
+    pub env: Vec<String>,
+    pub program: Option<String>,
+    pub flags: Vec<String>,
+    pub arguments: Vec<String>,
+    pub redirections: Vec<String>,
+}
+
+/// A pipeline, together with the operator that connects it to the following one
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+    pub followed_by: Option<String>,
+}
+
+fn tokenize(command: &str) -> Vec<Token> {
+    tokenize_with_offsets(command).into_iter().map(|(_, token)| token).collect()
+}
+
+/// Split the command into tokens, together with the byte offset each token starts at.
+fn tokenize_with_offsets(command: &str) -> Vec<(usize, Token)> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut word_start = 0;
+    let mut chars = command.char_indices().peekable();
+    let mut quote: Option<char> = None;
+    let mut paren_depth = 0;
+
+    while let Some((idx, c)) = chars.next() {
+        if word.is_empty() {
+            word_start = idx;
+        }
+        if let Some(q) = quote {
+            word.push(c);
+            if c == q {
+                quote = None;
+            } else if c == '\\' && q != '\'' {
+                word.extend(chars.next().map(|(_, c)| c));
+            }
+            continue;
+        }
+        match c {
+            '\'' | '"' | '`' => {
+                quote = Some(c);
+                word.push(c);
+            }
+            '\\' => {
+                word.push(c);
+                word.extend(chars.next().map(|(_, c)| c));
+            }
+            '(' => {
+                paren_depth += 1;
+                word.push(c);
+            }
+            ')' if paren_depth > 0 => {
+                paren_depth -= 1;
+                word.push(c);
+            }
+            _ if paren_depth > 0 => word.push(c),
+            '>' | '<' => {
+                // a file descriptor directly in front of the redirection belongs to it, like in 2>&1
+                let (redirect_start, mut redirect) = if word.chars().all(|c| c.is_ascii_digit()) {
+                    (word_start, std::mem::take(&mut word))
+                } else {
+                    push_word(&mut tokens, &mut word, word_start);
+                    (idx, String::new())
+                };
+                redirect.push(c);
+                while let Some(&(_, next)) = chars.peek().filter(|&&(_, next)| next == '>' || next == '&') {
+                    redirect.push(next);
+                    chars.next();
+                }
+                // duplicating a file descriptor, like in 2>&1, needs no separate target
+                if redirect.ends_with('&') {
+                    while let Some(&(_, next)) = chars.peek().filter(|&&(_, next)| next.is_ascii_digit() || next == '-') {
+                        redirect.push(next);
+                        chars.next();
+                    }
+                }
+                tokens.push((redirect_start, Token::Redirect(redirect)));
+            }
+            '|' | '&' | ';' | '\n' => {
+                push_word(&mut tokens, &mut word, word_start);
+                let mut operator = if c == '\n' { ";".to_string() } else { c.to_string() };
+                if let Some(&(_, next)) = chars
+                    .peek()
+                    .filter(|&&(_, next)| (c != ';' && next == c) || (c == '|' && next == '&'))
+                {
+                    operator.push(next);
+                    chars.next();
+                }
+                tokens.push((idx, Token::Operator(operator)));
+            }
+            _ if c.is_whitespace() => push_word(&mut tokens, &mut word, word_start),
+            _ => word.push(c),
+        }
+    }
+    push_word(&mut tokens, &mut word, word_start);
+    tokens
+}
+
+fn push_word(tokens: &mut Vec<(usize, Token)>, word: &mut String, word_start: usize) {
+    if !word.is_empty() {
+        tokens.push((word_start, Token::Word(std::mem::take(word))));
+    }
+}
+
+/// The commands that run the pipeline up to and including each of its stages,
+/// like `a`, `a | b` and `a | b | c` for `a | b | c`.
+/// Pipes within quotes or subshells don't split stages. Empty if the command has no pipes.
+pub fn pipeline_stage_prefixes(command: &str) -> Vec<String> {
+    let pipe_offsets = tokenize_with_offsets(command)
+        .into_iter()
+        .filter(|(_, token)| matches!(token, Token::Operator(operator) if operator == "|" || operator == "|&"))
+        .map(|(offset, _)| offset)
+        .collect_vec();
+    if pipe_offsets.is_empty() {
+        return Vec::new();
+    }
+    pipe_offsets
+        .into_iter()
+        .map(|offset| command[..offset].trim().to_string())
+        .chain(std::iter::once(command.trim().to_string()))
+        .collect()
+}
+
+/// Split a command into pipelines, each consisting of its piped stages.
+pub fn parse_command(command: &str) -> Vec<Pipeline> {
+    let mut pipelines = Vec::new();
+    let mut pipeline = Pipeline::default();
+    let mut stage = Stage::default();
+    let mut tokens = tokenize(command).into_iter().peekable();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(word) if stage.program.is_none() && is_env_assignment(&word) => stage.env.push(word),
+            Token::Word(word) if stage.program.is_none() => stage.program = Some(word),
+            Token::Word(word) if word.starts_with('-') && word.len() > 1 => stage.flags.push(word),
+            Token::Word(word) => stage.arguments.push(word),
+            Token::Redirect(redirect) if redirect.ends_with(|c: char| c.is_ascii_digit() || c == '-') => {
+                stage.redirections.push(redirect)
+            }
+            Token::Redirect(redirect) => match tokens.next_if(|token| matches!(token, Token::Word(_))) {
+                Some(Token::Word(target)) => stage.redirections.push(format!("{} {}", redirect, target)),
+                _ => stage.redirections.push(redirect),
+            },
+            Token::Operator(operator) if operator == "|" || operator == "|&" => {
+                pipeline.stages.push(std::mem::take(&mut stage));
+            }
+            Token::Operator(operator) => {
+                if stage != Stage::default() {
+                    pipeline.stages.push(std::mem::take(&mut stage));
+                }
+                if !pipeline.stages.is_empty() {
+                    pipeline.followed_by = Some(operator);
+                    pipelines.push(std::mem::take(&mut pipeline));
+                }
+            }
+        }
+    }
+    if stage != Stage::default() {
+        pipeline.stages.push(stage);
+    }
+    if !pipeline.stages.is_empty() {
+        pipelines.push(pipeline);
+    }
+    pipelines
+}
+
+fn is_env_assignment(word: &str) -> bool {
+    word.split_once('=')
+        .is_some_and(|(name, _)| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+fn describe_operator(operator: &str) -> &'static str {
+    match operator {
+        "&&" => "and, if it succeeds, then",
+        "||" => "or, if it fails, then",
+        "&" => "in the background, and then",
+        _ => "and then",
+    }
+}
+
+/// Render a human readable breakdown of the given command.
+pub fn explain_command(command: &str) -> String {
+    let pipelines = parse_command(command);
+    if pipelines.is_empty() {
+        return "There is no command to explain.".to_string();
+    }
+
+    let mut result = Vec::new();
+    for (pipeline_idx, pipeline) in pipelines.iter().enumerate() {
+        if pipelines.len() > 1 {
+            result.push(format!("Command {}:", pipeline_idx + 1));
+        }
+        if pipeline.stages.len() > 1 {
+            result.push(format!("This splits into {} piped stages:", pipeline.stages.len()));
+        }
+        for (stage_idx, stage) in pipeline.stages.iter().enumerate() {
+            let full_stage = stage
+                .env
+                .iter()
+                .chain(&stage.program)
+                .chain(&stage.flags)
+                .chain(&stage.arguments)
+                .chain(&stage.redirections)
+                .join(" ");
+            result.push(format!("  {}. {}", stage_idx + 1, full_stage));
+            let details = [
+                ("program", stage.program.iter().cloned().collect_vec()),
+                ("environment", stage.env.clone()),
+                ("flags", stage.flags.clone()),
+                ("arguments", stage.arguments.clone()),
+                ("redirections", stage.redirections.clone()),
+            ];
+            for (label, values) in details.iter().filter(|(_, values)| !values.is_empty()) {
+                result.push(format!("       {:<13} {}", format!("{}:", label), values.join(", ")));
+            }
+            if stage_idx + 1 < pipeline.stages.len() {
+                result.push("     ↓ output is piped into".to_string());
+            }
+        }
+        if let Some(operator) = &pipeline.followed_by
+            && pipeline_idx + 1 < pipelines.len()
+        {
+            result.push(format!("  ({} {})", operator, describe_operator(operator)));
+        }
+        result.push(String::new());
+    }
+    result.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stage(program: &str, flags: &[&str], arguments: &[&str]) -> Stage {
+        Stage {
+            program: Some(program.to_string()),
+            flags: flags.iter().map(|x| x.to_string()).collect(),
+            arguments: arguments.iter().map(|x| x.to_string()).collect(),
+            ..Stage::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        let pipelines = parse_command("cat file.txt | grep -i 'a | b' | wc -l");
+        assert_eq!(pipelines.len(), 1);
+        assert_eq!(
+            pipelines[0].stages,
+            vec![
+                stage("cat", &[], &["file.txt"]),
+                stage("grep", &["-i"], &["'a | b'"]),
+                stage("wc", &["-l"], &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_sequence_and_redirections() {
+        let pipelines = parse_command("LANG=C sort x 2>&1 > out && echo \"$(ls | head)\"");
+        assert_eq!(pipelines.len(), 2);
+        assert_eq!(pipelines[0].followed_by, Some("&&".to_string()));
+        assert_eq!(pipelines[0].stages[0].env, vec!["LANG=C"]);
+        assert_eq!(pipelines[0].stages[0].redirections, vec!["2>&1", "> out"]);
+        assert_eq!(pipelines[1].stages, vec![stage("echo", &[], &["\"$(ls | head)\""])]);
+    }
+
+    #[test]
+    fn test_pipeline_stage_prefixes() {
+        assert_eq!(
+            pipeline_stage_prefixes("cat x | grep 'a | b' | wc -l "),
+            vec!["cat x", "cat x | grep 'a | b'", "cat x | grep 'a | b' | wc -l"]
+        );
+        assert_eq!(
+            pipeline_stage_prefixes("echo \"$(ls | head)\" `ls | wc` || true |& tr a b"),
+            vec![
+                "echo \"$(ls | head)\" `ls | wc` || true",
+                "echo \"$(ls | head)\" `ls | wc` || true |& tr a b"
+            ]
+        );
+        assert!(pipeline_stage_prefixes("ls -l").is_empty());
+    }
+
+    #[test]
+    fn test_explain() {
+        assert_eq!(explain_command("  "), "There is no command to explain.");
+        assert!(explain_command("ls | wc").starts_with("This splits into 2 piped stages:"));
+    }
+}
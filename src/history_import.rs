@@ -0,0 +1,187 @@
+//! Parsing of the history files of other shells, to bootstrap pipr's history.
+//! Supported are bash, zsh (including its extended `: <timestamp>:<duration>;<command>` format) and fish.
+use anyhow::Context;
+use std::fs;
+use std::path::Path;
+
+use crate::commandlist::CommandEntry;
+
+/// zsh "metafies" special bytes in its history file, prefixing them with this byte.
+const ZSH_META: u8 = 0x83;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HistoryFormat {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl HistoryFormat {
+    /// guess the format of a history file from its name and content
+    fn detect(path: &Path, content: &str) -> HistoryFormat {
+        let file_name = path.file_name().map(|x| x.to_string_lossy()).unwrap_or_default();
+        if file_name.contains("fish") || content.starts_with("- cmd: ") {
+            HistoryFormat::Fish
+        } else if file_name.contains("zsh") || content.lines().next().is_some_and(is_zsh_extended_line) {
+            HistoryFormat::Zsh
+        } else {
+            HistoryFormat::Bash
+        }
+    }
+}
+
+/// Read a shell history file, returning its commands from oldest to newest.
+pub fn import_history_file(path: &Path) -> anyhow::Result<Vec<CommandEntry>> {
+    let bytes = fs::read(path).with_context(|| format!("Could not read history file {}", path.display()))?;
+    let content = String::from_utf8_lossy(&bytes);
+    Ok(match HistoryFormat::detect(path, &content) {
+        HistoryFormat::Bash => parse_bash_history(&content),
+        HistoryFormat::Zsh => parse_zsh_history(&String::from_utf8_lossy(&unmetafy(&bytes))),
+        HistoryFormat::Fish => parse_fish_history(&content),
+    })
+}
+
+fn parse_bash_history(content: &str) -> Vec<CommandEntry> {
+    content
+        .lines()
+        // timestamps written when HISTTIMEFORMAT is set
+        .filter(|line| !(line.starts_with('#') && line[1..].chars().all(|c| c.is_ascii_digit())))
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| CommandEntry::new(vec![line.to_string()]))
+        .collect()
+}
+
+fn is_zsh_extended_line(line: &str) -> bool {
+    let Some(rest) = line.strip_prefix(": ") else {
+        return false;
+    };
+    rest.split_once(';').is_some_and(|(meta, _)| {
+        meta.split_once(':')
+            .is_some_and(|(timestamp, duration)| is_number(timestamp) && is_number(duration))
+    })
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
+}
+
+fn parse_zsh_history(content: &str) -> Vec<CommandEntry> {
+    let mut entries = Vec::new();
+    let mut current_entry: Vec<String> = Vec::new();
+    for line in content.lines() {
+        let line = if current_entry.is_empty() && is_zsh_extended_line(line) {
+            line.split_once(';').map(|(_, cmd)| cmd).unwrap_or_default()
+        } else {
+            line
+        };
+        // multi-line commands are stored with a backslash before each line break
+        match line.strip_suffix('\\') {
+            Some(continued_line) => current_entry.push(continued_line.to_string()),
+            None => {
+                current_entry.push(line.to_string());
+                entries.push(CommandEntry::new(std::mem::take(&mut current_entry)));
+            }
+        }
+    }
+    if !current_entry.is_empty() {
+        entries.push(CommandEntry::new(current_entry));
+    }
+    entries.retain(|entry| !entry.as_string().trim().is_empty());
+    entries
+}
+
+fn parse_fish_history(content: &str) -> Vec<CommandEntry> {
+    content
+        .lines()
+        .filter_map(|line| line.strip_prefix("- cmd: "))
+        .map(|cmd| CommandEntry::new(unescape_fish(cmd).lines().map(String::from).collect()))
+        .filter(|entry| !entry.as_string().trim().is_empty())
+        .collect()
+}
+
+/// fish escapes newlines as `\n` and backslashes as `\\`
+fn unescape_fish(cmd: &str) -> String {
+    let mut result = String::with_capacity(cmd.len());
+    let mut chars = cmd.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                result.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                result.push('\\');
+                chars.next();
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter();
+    while let Some(&b) = bytes.next() {
+        if b == ZSH_META {
+            if let Some(&next) = bytes.next() {
+                result.push(next ^ 0x20);
+            }
+        } else {
+            result.push(b);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(lines: &[&str]) -> CommandEntry {
+        CommandEntry::new(lines.iter().map(|x| x.to_string()).collect())
+    }
+
+    #[test]
+    fn test_bash() {
+        let content = "ls -la\n#1600000000\ngit status\n\n";
+        assert_eq!(parse_bash_history(content), vec![entry(&["ls -la"]), entry(&["git status"])]);
+    }
+
+    #[test]
+    fn test_zsh() {
+        let content = ": 1600000000:0;ls -la\n: 1600000001:12;for x in a b; do\\\necho $x\\\ndone\nplain command\n";
+        assert_eq!(
+            parse_zsh_history(content),
+            vec![
+                entry(&["ls -la"]),
+                entry(&["for x in a b; do", "echo $x", "done"]),
+                entry(&["plain command"]),
+            ]
+        );
+        assert_eq!(unmetafy(&[b'a', ZSH_META, 0xa3 ^ 0x20]), vec![b'a', 0xa3]);
+    }
+
+    #[test]
+    fn test_fish() {
+        let content = "- cmd: ls -la\n  when: 1600000000\n- cmd: echo a\\nb \\\\n\n  when: 1600000001\n  paths:\n    - foo\n";
+        assert_eq!(
+            parse_fish_history(content),
+            vec![entry(&["ls -la"]), entry(&["echo a", "b \\n"])]
+        );
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            HistoryFormat::detect(Path::new("/home/x/.zsh_history"), ""),
+            HistoryFormat::Zsh
+        );
+        assert_eq!(
+            HistoryFormat::detect(Path::new("hist"), ": 1600000000:0;ls"),
+            HistoryFormat::Zsh
+        );
+        assert_eq!(HistoryFormat::detect(Path::new("fish_history"), ""), HistoryFormat::Fish);
+        assert_eq!(HistoryFormat::detect(Path::new(".bash_history"), "ls"), HistoryFormat::Bash);
+    }
+}
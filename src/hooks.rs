@@ -0,0 +1,100 @@
+//! User scripts run on events like running or bookmarking a command, to integrate pipr with other tools.
+//! Hooks run in the background, with their output discarded, so they can neither block nor break the ui.
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::thread;
+
+use anyhow::{anyhow, bail};
+
+use crate::command_template::CommandTemplate;
+use crate::util::shell_quote;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    /// a command was run with Enter
+    Execute,
+    /// a command was bookmarked
+    Bookmark,
+    /// pipr is closing
+    Exit,
+}
+
+impl HookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            HookEvent::Execute => "on_execute",
+            HookEvent::Bookmark => "on_bookmark",
+            HookEvent::Exit => "on_exit",
+        }
+    }
+}
+
+/// The hooks configured in the `[hooks]` table
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    templates: HashMap<&'static str, CommandTemplate>,
+}
+
+impl Hooks {
+    pub fn from_table(table: HashMap<String, String>) -> anyhow::Result<Hooks> {
+        let mut templates = HashMap::new();
+        for (name, template) in table {
+            let Some(event) = [HookEvent::Execute, HookEvent::Bookmark, HookEvent::Exit]
+                .into_iter()
+                .find(|e| e.name() == name)
+            else {
+                bail!("Unknown hook `{}`, expected one of on_execute, on_bookmark or on_exit", name);
+            };
+            let template = CommandTemplate::from_string(template).map_err(|err| anyhow!("Invalid hook `{}`: {}", name, err))?;
+            templates.insert(event.name(), template);
+        }
+        Ok(Hooks { templates })
+    }
+
+    /// Run the hook of the event in the background, if one is configured.
+    /// `??` in the hook is replaced with the shell-quoted command, which is also passed as `$PIPR_COMMAND`,
+    /// together with the event name as `$PIPR_EVENT` and the given extra variables.
+    pub fn run(&self, event: HookEvent, command: &str, env: &[(&str, String)]) -> anyhow::Result<()> {
+        let Some(template) = self.templates.get(event.name()) else {
+            return Ok(());
+        };
+        let mut hook = template.resolve_to_command(&shell_quote(command));
+        hook.env("PIPR_EVENT", event.name())
+            .env("PIPR_COMMAND", command)
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let mut child = hook.spawn()?;
+        // reap the hook once it is done, without waiting for it
+        thread::spawn(move || child.wait());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hooks() {
+        assert!(Hooks::from_table(HashMap::from([("on_save".to_string(), "true".to_string())])).is_err());
+        assert!(Hooks::from_table(HashMap::from([("on_exit".to_string(), String::new())])).is_err());
+
+        let path = std::env::temp_dir().join(format!("pipr-hook-{}", std::process::id()));
+        let hook = format!("echo \"$PIPR_EVENT $PIPR_OUT_FILE\" ?? > {}", path.display());
+        let hooks = Hooks::from_table(HashMap::from([("on_exit".to_string(), hook)])).unwrap();
+        hooks.run(HookEvent::Execute, "ls", &[]).unwrap();
+        hooks
+            .run(HookEvent::Exit, "echo it's", &[("PIPR_OUT_FILE", "out".to_string())])
+            .unwrap();
+
+        let started_at = std::time::Instant::now();
+        while std::fs::read_to_string(&path).map_or(true, |content| !content.ends_with('\n')) {
+            assert!(started_at.elapsed().as_secs() < 5, "the hook did not run");
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "on_exit out echo it's\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
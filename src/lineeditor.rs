@@ -1,5 +1,7 @@
 use super::commandlist::*;
+use crate::util::{display_width, shell_quote, tab_advance};
 use crossterm::event::{KeyCode, KeyModifiers};
+use std::collections::HashMap;
 use unicode_width::*;
 
 #[derive(Debug, Clone)]
@@ -7,24 +9,59 @@ pub struct EditorState {
     lines: Vec<String>,
     pub cursor_line: usize,
     pub cursor_col: usize,
+    /// line and column where the selection started, the selection spans from here to the cursor
+    selection_anchor: Option<(usize, usize)>,
+    /// number of columns between tab stops, used to find the displayed position of the cursor
+    tab_width: usize,
+    /// contents that were cleared, most recent last, so clearing can be undone
+    cleared_contents: Vec<Vec<String>>,
 }
+
+/// number of cleared contents that can be restored
+const MAX_CLEARED_CONTENTS: usize = 20;
+
+/// tab width used unless configured otherwise
+pub const DEFAULT_TAB_WIDTH: usize = 8;
 pub enum EditorEvent {
     NewCharacter(char),
     NewLine,
     Backspace,
     Delete,
     Clear,
+    RestoreCleared,
     GoLeft,
     GoRight,
     GoUp,
     GoDown,
     Home,
     End,
+    GoWordLeft,
+    GoWordRight,
     KillWordBack,
+    QuoteWord,
+}
+
+impl EditorEvent {
+    /// whether this event only moves the cursor, so it can be used to extend a selection
+    pub fn is_movement(&self) -> bool {
+        matches!(
+            self,
+            EditorEvent::GoLeft
+                | EditorEvent::GoRight
+                | EditorEvent::GoUp
+                | EditorEvent::GoDown
+                | EditorEvent::Home
+                | EditorEvent::End
+                | EditorEvent::GoWordLeft
+                | EditorEvent::GoWordRight
+        )
+    }
 }
 
 pub fn convert_keyevent_to_editorevent(code: KeyCode, modifiers: KeyModifiers) -> Option<EditorEvent> {
     match code {
+        KeyCode::Left if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::GoWordLeft),
+        KeyCode::Right if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::GoWordRight),
         KeyCode::Left => Some(EditorEvent::GoLeft),
         KeyCode::Right => Some(EditorEvent::GoRight),
         KeyCode::Up => Some(EditorEvent::GoUp),
@@ -34,13 +71,16 @@ pub fn convert_keyevent_to_editorevent(code: KeyCode, modifiers: KeyModifiers) -
         KeyCode::Char('a') if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::Home),
         KeyCode::Char('e') if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::End),
         KeyCode::Char('u') if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::Clear),
+        KeyCode::Char('u') if modifiers.contains(KeyModifiers::ALT) => Some(EditorEvent::RestoreCleared),
         KeyCode::Char('w') if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::KillWordBack),
+        KeyCode::Char('q') if modifiers.contains(KeyModifiers::ALT) => Some(EditorEvent::QuoteWord),
         KeyCode::Enter | KeyCode::Char('\r') | KeyCode::Char('\n') if modifiers.contains(KeyModifiers::ALT) => {
             Some(EditorEvent::NewLine)
         }
         KeyCode::Char('h') if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::Backspace),
         KeyCode::Insert => Some(EditorEvent::NewLine),
         KeyCode::Char(c) => Some(EditorEvent::NewCharacter(c)),
+        KeyCode::Backspace if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::KillWordBack),
         KeyCode::Backspace => Some(EditorEvent::Backspace),
         KeyCode::Delete => Some(EditorEvent::Delete),
         _ => None,
@@ -59,9 +99,17 @@ impl EditorState {
             lines: vec![String::new()],
             cursor_line: 0,
             cursor_col: 0,
+            selection_anchor: None,
+            tab_width: DEFAULT_TAB_WIDTH,
+            cleared_contents: Vec::new(),
         }
     }
 
+    pub fn with_tab_width(mut self, tab_width: usize) -> EditorState {
+        self.tab_width = tab_width;
+        self
+    }
+
     pub fn content_to_commandentry(&self) -> CommandEntry {
         CommandEntry::new(self.lines.clone())
     }
@@ -79,6 +127,7 @@ impl EditorState {
         };
         self.cursor_line = self.lines.len() - 1;
         self.cursor_col = self.current_line().len();
+        self.selection_anchor = None;
     }
 
     pub fn content_str(&self) -> String {
@@ -94,12 +143,31 @@ impl EditorState {
     }
 
     pub fn displayed_cursor_column(&self) -> usize {
-        UnicodeWidthStr::width(&self.current_line()[..self.cursor_col])
+        display_width(&self.current_line()[..self.cursor_col], self.tab_width)
+    }
+
+    /// Move the cursor to the given line and displayed column, clamped to the existing content.
+    pub fn set_cursor_position(&mut self, line: usize, displayed_col: usize) {
+        self.selection_anchor = None;
+        self.cursor_line = line.min(self.lines.len() - 1);
+        let mut width = 0;
+        self.cursor_col = self.current_line().len();
+        for (idx, c) in self.current_line().char_indices() {
+            width += match c {
+                '\t' => tab_advance(width, self.tab_width),
+                _ => UnicodeWidthChar::width(c).unwrap_or(0),
+            };
+            if width > displayed_col {
+                self.cursor_col = idx;
+                break;
+            }
+        }
     }
 
     /// insert a given string at the cursor position
     /// second argument determines if the cursor should be moved to the end of the inserted text or not.
     pub fn insert_at_cursor(&mut self, text: &str, move_cursor: bool) {
+        self.selection_anchor = None;
         let cursor_col = self.cursor_col;
         self.current_line_mut().insert_str(cursor_col, text);
         if move_cursor {
@@ -107,6 +175,71 @@ impl EditorState {
         }
     }
 
+    /// insert text that may span multiple lines at the cursor, moving the cursor to the end of the inserted text.
+    pub fn insert_text(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let mut lines = text.split('\n');
+        if let Some(first_line) = lines.next() {
+            self.insert_at_cursor(first_line, true);
+        }
+        for line in lines {
+            self.apply_event(EditorEvent::NewLine);
+            self.insert_at_cursor(line, true);
+        }
+    }
+
+    /// Append the lines as another stage of the pipeline, after a `|` unless they start with one,
+    /// moving the cursor to the end of the appended stage. Without a pipeline yet, the stage becomes the command.
+    pub fn append_pipe_stage(&mut self, stage: &[String]) {
+        let Some((first_line, rest)) = stage.split_first() else {
+            return;
+        };
+        let first_line = first_line.trim_start();
+        let stage_command = first_line.strip_prefix('|').map_or(first_line, str::trim_start);
+        let mut lines = self.lines.clone();
+        while lines.last().is_some_and(|line| line.trim().is_empty()) {
+            lines.pop();
+        }
+        match lines.last_mut() {
+            Some(last_line) => {
+                let trimmed_len = last_line.trim_end().len();
+                last_line.truncate(trimmed_len);
+                let separator = if last_line.ends_with('|') { " " } else { " | " };
+                last_line.push_str(separator);
+                last_line.push_str(stage_command);
+            }
+            None => lines.push(stage_command.to_string()),
+        }
+        lines.extend(rest.iter().cloned());
+        self.set_content(lines);
+    }
+
+    /// Replace the word before the cursor with its expansion, if it is an abbreviation in command position,
+    /// meaning at the start of the line or after an operator like `|` or `&&`.
+    /// Returns whether the word was expanded.
+    pub fn expand_abbreviation(&mut self, abbreviations: &HashMap<String, String>) -> bool {
+        let before_cursor = &self.current_line()[..self.cursor_col];
+        let word_start = before_cursor
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| c.is_whitespace() || COMMAND_SEPARATORS.contains(&c))
+            .map(|(idx, c)| idx + c.len_utf8())
+            .unwrap_or(0);
+        let preceding = before_cursor[..word_start].trim_end();
+        let is_command_word = preceding.is_empty()
+            || (preceding.ends_with(COMMAND_SEPARATORS) && !preceding.ends_with(">&") && !preceding.ends_with("<&"));
+        match abbreviations.get(&before_cursor[word_start..]) {
+            Some(expansion) if is_command_word => {
+                let expansion = expansion.clone();
+                let cursor_col = self.cursor_col;
+                self.current_line_mut().replace_range(word_start..cursor_col, &expansion);
+                self.cursor_col = word_start + expansion.len();
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub fn hovered_char(&self) -> Option<&str> {
         self.current_line().get(self.cursor_col..self.next_char_index())
     }
@@ -137,6 +270,39 @@ impl EditorState {
         new_cursor
     }
 
+    /// byte range of the whitespace-delimited word the cursor is on (or directly behind)
+    fn hovered_word_range(&self) -> (usize, usize) {
+        let line = self.current_line();
+        let start = line[..self.cursor_col]
+            .char_indices()
+            .rfind(|(_, c)| c.is_whitespace())
+            .map(|(idx, c)| idx + c.len_utf8())
+            .unwrap_or(0);
+        let end = line[self.cursor_col..]
+            .find(char::is_whitespace)
+            .map(|idx| self.cursor_col + idx)
+            .unwrap_or(line.len());
+        (start, end)
+    }
+
+    /// Replace the text between the (line, column) positions `start` and `end` with `text`,
+    /// moving the cursor to the end of the inserted text.
+    fn replace_range(&mut self, start: (usize, usize), end: (usize, usize), text: &str) {
+        let (start_line, start_col) = start;
+        let (end_line, end_col) = end;
+        let rest_of_line = self.lines[end_line][end_col..].to_string();
+        let mut new_lines: Vec<String> = format!("{}{}", &self.lines[start_line][..start_col], text)
+            .split('\n')
+            .map(String::from)
+            .collect();
+        self.cursor_line = start_line + new_lines.len() - 1;
+        self.cursor_col = new_lines.last().map_or(0, String::len);
+        if let Some(last_line) = new_lines.last_mut() {
+            last_line.push_str(&rest_of_line);
+        }
+        self.lines.splice(start_line..=end_line, new_lines);
+    }
+
     /// go to another line, keeping the cursor column the same if possible,
     /// otherwise going to the last column of the line
     fn goto_line(&mut self, line_nr: usize) {
@@ -147,7 +313,49 @@ impl EditorState {
         }
     }
 
+    /// Apply a movement, selecting the text between where the selection started and the new cursor position.
+    pub fn apply_selecting_event(&mut self, event: EditorEvent) {
+        let anchor = self.selection_anchor.unwrap_or((self.cursor_line, self.cursor_col));
+        self.apply_event(event);
+        self.selection_anchor = Some(anchor).filter(|&anchor| anchor != (self.cursor_line, self.cursor_col));
+    }
+
+    /// start and end (line, column) of the selection, in the order they appear in the text
+    fn selection_bounds(&self) -> Option<((usize, usize), (usize, usize))> {
+        let anchor = self.selection_anchor?;
+        let cursor = (self.cursor_line, self.cursor_col);
+        Some((anchor.min(cursor), anchor.max(cursor)))
+    }
+
+    /// byte ranges of the selection within each line, as (line, start, end)
+    pub fn selected_ranges(&self) -> Vec<(usize, usize, usize)> {
+        let Some(((start_line, start_col), (end_line, end_col))) = self.selection_bounds() else {
+            return Vec::new();
+        };
+        (start_line..=end_line)
+            .map(|line| {
+                let start = if line == start_line { start_col } else { 0 };
+                let end = if line == end_line { end_col } else { self.lines[line].len() };
+                (line, start, end)
+            })
+            .collect()
+    }
+
+    /// the selected text, with the selected parts of multiple lines joined by newlines
+    pub fn selected_text(&self) -> Option<String> {
+        self.selection_anchor?;
+        Some(
+            self.selected_ranges()
+                .into_iter()
+                .map(|(line, start, end)| &self.lines[line][start..end])
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+
     pub fn apply_event(&mut self, event: EditorEvent) {
+        let selection = self.selection_bounds();
+        self.selection_anchor = None;
         match event {
             EditorEvent::NewCharacter(c) => {
                 let cursor_col = self.cursor_col;
@@ -188,8 +396,23 @@ impl EditorState {
             }
 
             EditorEvent::Clear => {
+                if self.lines.iter().any(|line| !line.is_empty()) {
+                    if self.cleared_contents.len() >= MAX_CLEARED_CONTENTS {
+                        self.cleared_contents.remove(0);
+                    }
+                    self.cleared_contents.push(std::mem::take(&mut self.lines));
+                }
                 self.set_content(vec![String::new()]);
             }
+            EditorEvent::RestoreCleared => {
+                if let Some(cleared) = self.cleared_contents.pop() {
+                    // whatever was typed since is kept, so restoring again brings it back
+                    if self.lines.iter().any(|line| !line.is_empty()) {
+                        self.cleared_contents.push(std::mem::take(&mut self.lines));
+                    }
+                    self.set_content(cleared);
+                }
+            }
 
             EditorEvent::GoLeft => {
                 if self.cursor_col > 0 {
@@ -212,19 +435,114 @@ impl EditorState {
             EditorEvent::Home => self.cursor_col = 0,
             EditorEvent::End => self.cursor_col = self.current_line().len(),
 
-            EditorEvent::KillWordBack if !self.current_line().is_empty() => {
-                while let Some(c) = self.current_line().to_owned().get(self.prev_char_index()..self.cursor_col) {
-                    let cursor_col = self.prev_char_index();
-                    self.cursor_col = cursor_col;
-                    self.current_line_mut().remove(cursor_col);
-                    if c == " " || c == "/" || c == "\\" || c == ":" || c == "_" || c == "-" || self.cursor_col == 0 {
+            EditorEvent::GoWordLeft if self.cursor_col == 0 => self.apply_event(EditorEvent::GoLeft),
+            EditorEvent::GoWordLeft => self.cursor_col = prev_word_boundary(self.current_line(), self.cursor_col),
+            EditorEvent::GoWordRight if self.cursor_col == self.current_line().len() => self.apply_event(EditorEvent::GoRight),
+            EditorEvent::GoWordRight => self.cursor_col = next_word_boundary(self.current_line(), self.cursor_col),
+
+            EditorEvent::KillWordBack if self.cursor_col == 0 => self.apply_event(EditorEvent::Backspace),
+            EditorEvent::KillWordBack => {
+                let start = prev_word_boundary(self.current_line(), self.cursor_col);
+                let cursor_col = self.cursor_col;
+                self.current_line_mut().replace_range(start..cursor_col, "");
+                self.cursor_col = start;
+            }
+            EditorEvent::QuoteWord => {
+                let (start, end) = selection.unwrap_or_else(|| {
+                    let (start, end) = self.hovered_word_range();
+                    ((self.cursor_line, start), (self.cursor_line, end))
+                });
+                if start < end {
+                    let text = (start.0..=end.0)
+                        .map(|line| {
+                            let from = if line == start.0 { start.1 } else { 0 };
+                            let to = if line == end.0 { end.1 } else { self.lines[line].len() };
+                            &self.lines[line][from..to]
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    self.replace_range(start, end, &shell_quote(&text));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// characters after which a new command starts
+const COMMAND_SEPARATORS: &[char] = &['|', ';', '&', '(', '{', '`'];
+
+/// characters that split a word into smaller parts for word-wise movement, like the segments of a path or a flag
+const SUBWORD_SEPARATORS: &[char] = &['/', '.', '-', '_', ':', '=', ',', '|', ';', '&', '<', '>', '(', ')'];
+
+fn is_word_separator(c: char) -> bool {
+    c.is_whitespace() || SUBWORD_SEPARATORS.contains(&c)
+}
+
+/// byte ranges of the complete quoted strings in the line, including their quotes
+fn quoted_ranges(line: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut chars = line.char_indices();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\'' | '"' => {
+                let mut end = None;
+                while let Some((idx, next)) = chars.next() {
+                    if next == '\\' && c == '"' {
+                        chars.next();
+                    } else if next == c {
+                        end = Some(idx + 1);
                         break;
                     }
                 }
+                ranges.extend(end.map(|end| (start, end)));
             }
             _ => {}
         }
     }
+    ranges
+}
+
+/// Where moving one word to the left from `col` ends up.
+/// Whitespace and separators are skipped, then the cursor moves to the start of the word,
+/// treating a quoted string as a single word.
+fn prev_word_boundary(line: &str, col: usize) -> usize {
+    let quoted = quoted_ranges(line);
+    let mut chars = line[..col].char_indices().rev().peekable();
+    while chars.next_if(|&(_, c)| is_word_separator(c)).is_some() {}
+    let Some(&(idx, _)) = chars.peek() else {
+        return 0;
+    };
+    if let Some(&(start, _)) = quoted.iter().find(|&&(_, end)| end == idx + 1) {
+        return start;
+    }
+    // a lone quote is stepped over like a separator
+    let mut boundary = idx;
+    while let Some((idx, _)) = chars.next_if(|&(_, c)| !is_word_separator(c) && c != '\'' && c != '"') {
+        boundary = idx;
+    }
+    boundary
+}
+
+/// Where moving one word to the right from `col` ends up: the end of the next word.
+fn next_word_boundary(line: &str, col: usize) -> usize {
+    let quoted = quoted_ranges(line);
+    let mut chars = line[col..].char_indices().map(|(idx, c)| (col + idx, c)).peekable();
+    while chars.next_if(|&(_, c)| is_word_separator(c)).is_some() {}
+    let Some(&(idx, _)) = chars.peek() else {
+        return line.len();
+    };
+    if let Some(&(_, end)) = quoted.iter().find(|&&(start, _)| start == idx) {
+        return end;
+    }
+    let mut boundary = idx;
+    while let Some((idx, c)) = chars.next_if(|&(_, c)| !is_word_separator(c) && c != '\'' && c != '"') {
+        boundary = idx + c.len_utf8();
+    }
+    boundary.max(idx + 1)
 }
 
 #[cfg(test)]
@@ -322,8 +640,237 @@ pub mod test {
         assert_eq!(le.displayed_cursor_column(), 8_usize);
 
         le.apply_event(EditorEvent::KillWordBack);
-        assert_eq!(le.content_str(), "as as");
-        assert_eq!(le.displayed_cursor_column(), 5_usize);
+        assert_eq!(le.content_str(), "as as ");
+        assert_eq!(le.displayed_cursor_column(), 6_usize);
+
+        le.apply_event(EditorEvent::KillWordBack);
+        assert_eq!(le.content_str(), "as ");
+
+        le.set_content(vec!["a".to_string(), "b".to_string()]);
+        le.cursor_col = 0;
+        le.apply_event(EditorEvent::KillWordBack);
+        assert_eq!(*le.content_lines(), vec!["ab"]);
+    }
+
+    #[test]
+    pub fn test_word_boundaries() {
+        let line = "ls /usr/local/bin";
+        assert_eq!(prev_word_boundary(line, line.len()), 14);
+        assert_eq!(prev_word_boundary(line, 14), 8);
+        assert_eq!(prev_word_boundary(line, 4), 0);
+        assert_eq!(next_word_boundary(line, 2), 7);
+        assert_eq!(next_word_boundary(line, 13), line.len());
+
+        let line = "grep --ignore-case foo";
+        assert_eq!(prev_word_boundary(line, 18), 14);
+        assert_eq!(prev_word_boundary(line, 14), 7);
+        assert_eq!(next_word_boundary(line, 4), 13);
+
+        let line = "echo 'a b/c' \"d \\\" e\" x";
+        assert_eq!(prev_word_boundary(line, 12), 5);
+        assert_eq!(next_word_boundary(line, 4), 12);
+        assert_eq!(prev_word_boundary(line, 21), 13);
+        assert_eq!(next_word_boundary(line, 12), 21);
+        assert_eq!(prev_word_boundary(line, 10), 8);
+
+        assert_eq!(prev_word_boundary("echo 'x", 6), 5);
+        assert_eq!(next_word_boundary("echo 'x", 4), 6);
+        assert_eq!(prev_word_boundary("ä ö", 5), 3);
+    }
+
+    #[test]
+    pub fn test_expand_abbreviation() {
+        let abbreviations: HashMap<String, String> = [("gco", "git checkout"), ("k", "kubectl")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let mut le = EditorState::new();
+        le.set_content(vec!["gco".to_string()]);
+        assert!(le.expand_abbreviation(&abbreviations));
+        assert_eq!((le.content_str().as_str(), le.cursor_col), ("git checkout", 12));
+
+        le.set_content(vec!["cat x | k".to_string()]);
+        assert!(le.expand_abbreviation(&abbreviations));
+        assert_eq!(le.content_str(), "cat x | kubectl");
+
+        le.set_content(vec!["true&&k".to_string()]);
+        assert!(le.expand_abbreviation(&abbreviations));
+        assert_eq!(le.content_str(), "true&&kubectl");
+
+        // only the command word is expanded
+        for line in ["echo k", "ls gco", "ls 2>&k", "gcox"] {
+            le.set_content(vec![line.to_string()]);
+            assert!(!le.expand_abbreviation(&abbreviations));
+            assert_eq!(le.content_str(), line);
+        }
+    }
+
+    #[test]
+    pub fn test_word_movement() {
+        let mut le = EditorState::new();
+        le.set_content(vec!["a".to_string(), "cat x.txt".to_string()]);
+        le.apply_event(EditorEvent::GoWordLeft);
+        assert_eq!((le.cursor_line, le.cursor_col), (1, 6));
+        le.cursor_col = 0;
+        le.apply_event(EditorEvent::GoWordLeft);
+        assert_eq!((le.cursor_line, le.cursor_col), (0, 1));
+        le.apply_event(EditorEvent::GoWordRight);
+        assert_eq!((le.cursor_line, le.cursor_col), (1, 0));
+        le.apply_event(EditorEvent::GoWordRight);
+        assert_eq!((le.cursor_line, le.cursor_col), (1, 3));
+    }
+
+    #[test]
+    pub fn test_selection() {
+        let mut editor = EditorState::new();
+        editor.set_content(vec!["echo $(ls | wc)".into(), "| cat".into()]);
+        editor.cursor_line = 0;
+        editor.cursor_col = 7;
+        assert_eq!(editor.selected_text(), None);
+        for _ in 0..7 {
+            editor.apply_selecting_event(EditorEvent::GoRight);
+        }
+        assert_eq!(editor.selected_text(), Some("ls | wc".into()));
+
+        // selecting backwards across lines
+        editor.apply_event(EditorEvent::GoDown);
+        assert_eq!(editor.selected_text(), None, "moving without selecting clears the selection");
+        editor.apply_event(EditorEvent::End);
+        editor.apply_selecting_event(EditorEvent::Home);
+        editor.apply_selecting_event(EditorEvent::GoLeft);
+        editor.apply_selecting_event(EditorEvent::GoLeft);
+        assert_eq!(editor.selected_ranges(), vec![(0, 14, 15), (1, 0, 5)]);
+        assert_eq!(editor.selected_text(), Some(")\n| cat".into()));
+
+        editor.apply_event(EditorEvent::NewCharacter('x'));
+        assert_eq!(editor.selected_text(), None);
+    }
+
+    #[test]
+    pub fn test_quote_word() {
+        let mut le = EditorState::new();
+        le.set_content(vec!["cat it's".to_string()]);
+        le.apply_event(EditorEvent::QuoteWord);
+        assert_eq!(le.content_str(), "cat 'it'\\''s'");
+        assert_eq!(le.cursor_col, le.current_line().len());
+
+        le.set_content(vec!["ls foo bar".to_string()]);
+        le.cursor_col = 4;
+        le.apply_event(EditorEvent::QuoteWord);
+        assert_eq!(le.content_str(), "ls 'foo' bar");
+        assert_eq!(le.cursor_col, 8);
+
+        le.set_content(vec!["ls ".to_string()]);
+        le.apply_event(EditorEvent::QuoteWord);
+        assert_eq!(le.content_str(), "ls ");
+
+        le.set_content(vec!["ls\tfoo".to_string()]);
+        le.apply_event(EditorEvent::QuoteWord);
+        assert_eq!(
+            le.content_lines(),
+            &vec!["ls\t'foo'".to_string()],
+            "tabs don't belong to the word"
+        );
+
+        le.set_content(vec!["grep my file x".to_string()]);
+        le.cursor_col = 5;
+        for _ in 0..7 {
+            le.apply_selecting_event(EditorEvent::GoRight);
+        }
+        le.apply_event(EditorEvent::QuoteWord);
+        assert_eq!(le.content_str(), "grep 'my file' x");
+        assert_eq!(le.cursor_col, 14);
+        assert_eq!(le.selected_text(), None);
+
+        le.set_content(vec!["echo a".to_string(), "b c".to_string()]);
+        le.cursor_line = 0;
+        le.cursor_col = 5;
+        le.apply_selecting_event(EditorEvent::GoDown);
+        le.apply_event(EditorEvent::QuoteWord);
+        assert_eq!(le.content_lines(), &vec!["echo 'a".to_string(), "b c'".to_string()]);
+        assert_eq!((le.cursor_line, le.cursor_col), (1, 4));
+    }
+
+    #[test]
+    pub fn test_restore_cleared() {
+        let mut le = EditorState::new();
+        le.set_content(vec!["ls".to_string(), "| wc -l".to_string()]);
+        le.apply_event(EditorEvent::Clear);
+        assert_eq!(le.content_lines(), &vec![String::new()]);
+        // clearing an empty input doesn't hide the content cleared before
+        le.apply_event(EditorEvent::Clear);
+
+        le.apply_event(EditorEvent::RestoreCleared);
+        assert_eq!(le.content_lines(), &vec!["ls".to_string(), "| wc -l".to_string()]);
+        assert_eq!((le.cursor_line, le.cursor_col), (1, 7));
+
+        le.set_content(vec!["echo".to_string()]);
+        le.apply_event(EditorEvent::Clear);
+        le.apply_event(EditorEvent::NewCharacter('x'));
+        le.apply_event(EditorEvent::RestoreCleared);
+        assert_eq!(le.content_str(), "echo");
+        le.apply_event(EditorEvent::RestoreCleared);
+        assert_eq!(le.content_str(), "x");
+    }
+
+    #[test]
+    pub fn test_set_cursor_position() {
+        let mut le = EditorState::new();
+        le.set_content(vec!["ls".to_string(), "echo äöü".to_string()]);
+        le.set_cursor_position(0, 1);
+        assert_eq!((le.cursor_line, le.cursor_col), (0, 1));
+
+        le.set_cursor_position(1, 6);
+        assert_eq!((le.cursor_line, le.cursor_col), (1, 7));
+        assert_eq!(le.displayed_cursor_column(), 6);
+
+        le.set_cursor_position(5, 50);
+        assert_eq!((le.cursor_line, le.cursor_col), (1, le.current_line().len()));
+    }
+
+    #[test]
+    pub fn test_cursor_column_with_tabs() {
+        let mut le = EditorState::new().with_tab_width(4);
+        le.set_content(vec!["a\tb\t\tc".to_string()]);
+        le.set_cursor_position(0, 4);
+        assert_eq!(le.cursor_col, 2);
+        assert_eq!(le.displayed_cursor_column(), 4);
+
+        // clicking into a tab puts the cursor in front of it
+        le.set_cursor_position(0, 6);
+        assert_eq!(le.cursor_col, 3);
+        assert_eq!(le.displayed_cursor_column(), 5);
+
+        le.apply_event(EditorEvent::GoRight);
+        le.apply_event(EditorEvent::GoRight);
+        assert_eq!(le.displayed_cursor_column(), 12);
+    }
+
+    #[test]
+    pub fn test_insert_text() {
+        let mut le = EditorState::new();
+        le.set_content(vec!["ab".to_string()]);
+        le.cursor_col = 1;
+        le.insert_text("x\r\ny\nz");
+        assert_eq!(*le.content_lines(), vec!["ax", "y", "zb"]);
+        assert_eq!((le.cursor_col, le.cursor_line), (1, 2));
+    }
+
+    #[test]
+    pub fn test_append_pipe_stage() {
+        let mut le = EditorState::new();
+        le.append_pipe_stage(&[" | sort".to_string()]);
+        assert_eq!(*le.content_lines(), vec!["sort"]);
+        le.set_content(vec!["cat x  ".to_string(), "".to_string()]);
+        le.cursor_line = 0;
+        le.append_pipe_stage(&["jq .".to_string()]);
+        assert_eq!(*le.content_lines(), vec!["cat x | jq ."]);
+        le.append_pipe_stage(&["| uniq -c".to_string(), "  | head".to_string()]);
+        assert_eq!(*le.content_lines(), vec!["cat x | jq . | uniq -c", "  | head"]);
+        assert_eq!((le.cursor_col, le.cursor_line), (8, 1));
+        le.set_content(vec!["ls |".to_string()]);
+        le.append_pipe_stage(&["wc -l".to_string()]);
+        assert_eq!(*le.content_lines(), vec!["ls | wc -l"]);
     }
 
     #[test]
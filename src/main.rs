@@ -1,57 +1,159 @@
 use atty::Stream;
-use crossbeam_channel::{select, unbounded, Receiver};
-use getopts::Options;
+use base64::Engine;
+use clap::{CommandFactory, Parser, ValueHint};
+use clap_complete::Shell;
+use crossbeam_channel::{Receiver, after, never, select, unbounded};
 use itertools::Itertools;
 use std::env;
+use std::fs::DirBuilder;
 use std::fs::File;
 use std::io::{self, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use ratatui::{backend::CrosstermBackend, Terminal};
+use ratatui::{Terminal, backend::CrosstermBackend};
 
 use crossterm::{
-    event::{self, Event as CEvent},
+    cursor,
+    event::{self, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, Event as CEvent},
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
 
 mod app;
+mod cheatsheet;
 mod command_evaluation;
+mod command_runner;
 mod command_template;
 mod commandlist;
+mod context_line;
+mod env_expansion;
+mod explain;
+mod history_import;
+mod hooks;
 mod lineeditor;
+mod output_cache;
 mod pipr_config;
+mod recent_dirs;
+mod shell_session;
 mod snippets;
+mod substitution;
+mod syntax_check;
+mod trailing_whitespace;
 pub mod ui;
 mod util;
 
 use app::App;
 use command_evaluation::*;
 use commandlist::CommandList;
+use hooks::HookEvent;
 use pipr_config::*;
 
+/// A commandline-utility to interactively build complex shell pipelines
+#[derive(Parser)]
+#[command(name = "pipr", version)]
 pub struct CliArgs {
+    /// text inserted into the textfield on startup
+    #[arg(short = 'd', long = "default", value_name = "TEXT")]
     default_content: Option<String>,
+    /// write final command to file
+    #[arg(short = 'o', long = "out-file", value_name = "FILE", value_hint = ValueHint::FilePath)]
     output_file: Option<String>,
+    /// read initial command from file
+    #[arg(long = "in-file", value_name = "FILE", value_hint = ValueHint::FilePath)]
     input_file: Option<String>,
-    unsafe_mode: bool,
+    /// feed this file or named pipe into the stdin of the commands, like a stored output (see F9)
+    #[arg(long = "stdin-file", value_name = "FILE", value_hint = ValueHint::FilePath)]
+    stdin_file: Option<String>,
+    /// run the command without opening the UI, printing its output and exiting with its exit code
+    #[arg(long = "exec", value_name = "COMMAND", value_hint = ValueHint::CommandString)]
+    exec_command: Option<String>,
+    /// with --exec, print the result as a single JSON object instead of the plain output
+    #[arg(long = "json", requires = "exec_command")]
+    json_output: bool,
+    /// import the commands of a bash, zsh or fish history file into pipr's history
+    #[arg(long = "import-history", value_name = "FILE", value_hint = ValueHint::FilePath)]
+    import_history: Option<String>,
+    /// use the given config file instead of looking it up in $XDG_CONFIG_HOME/pipr
+    #[arg(long = "config", value_name = "FILE", value_hint = ValueHint::FilePath)]
+    config_file: Option<String>,
+    /// print out the default configuration file
+    #[arg(long = "config-reference")]
+    config_reference: bool,
+    /// print a completion script for the given shell
+    #[arg(long = "generate-completions", value_name = "SHELL")]
+    generate_completions: Option<Shell>,
+    /// keep linebreaks in finished command when closing
+    #[arg(short = 'r', long = "raw-mode")]
     raw_mode: bool,
+    /// don't run the startup_command configured in the config file
+    #[arg(long = "no-startup")]
+    skip_startup_command: bool,
+    /// use the bookmarks, history and config overrides of this profile, stored in $XDG_CONFIG_HOME/pipr/profiles/<name>
+    #[arg(long = "profile", value_name = "NAME")]
+    profile: Option<String>,
+    /// don't write the history or bookmarks to disk for this run
+    #[arg(long = "incognito")]
+    incognito: bool,
+    /// disable isolation. This will run the commands directly on your system, without protection. Take care.
+    #[arg(long = "no-isolation")]
+    unsafe_mode: bool,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = handle_cli_arguments();
     let home_path = env::var("HOME").expect("$HOME not set");
-    let config_path = &env::var("XDG_CONFIG_HOME")
-        .map(|dir| Path::new(&dir).to_path_buf())
-        .unwrap_or(Path::new(&home_path).join(".config"))
-        .join("pipr");
+    let xdg_config_home = env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or(Path::new(&home_path).join(".config"));
+    // bookmarks and history are always stored here, or in the profile's directory, regardless of where the config file is
+    let config_path = &match &args.profile {
+        Some(profile) => pipr_config::profile_dir(&xdg_config_home.join("pipr"), profile).unwrap_or_else(|err| {
+            eprintln!("pipr: {:#}", err);
+            std::process::exit(1);
+        }),
+        None => xdg_config_home.join("pipr"),
+    };
+    DirBuilder::new().recursive(true).create(config_path)?;
+
+    let config_file = match args.config_file {
+        Some(config_file) => PathBuf::from(config_file),
+        None => pipr_config::find_config_file(&xdg_config_home),
+    };
+    let profile_config_file = args.profile.as_ref().map(|_| config_path.join("pipr.toml"));
+    let mut config = match PiprConfig::load_from_file(&config_file, profile_config_file.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("pipr: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+
+    config.incognito |= args.incognito;
+    config.profile = args.profile;
+
+    if let Some(syntax_dir) = &config.syntax_dir
+        && let Err(err) = ui::load_syntax_dir(syntax_dir)
+    {
+        eprintln!("pipr: {:#}", err);
+        std::process::exit(1);
+    }
 
-    let config = PiprConfig::load_from_file(&config_path.join("pipr.toml"));
+    if let Some(history_file) = args.import_history {
+        let mut history = CommandList::load_from_file(config_path.join("history"), None)
+            .with_max_size(Some(config.history_size), config.history_when_full)
+            .with_ignore_patterns(config.history_ignore.clone());
+        let imported = history_import::import_history_file(Path::new(&history_file))?;
+        let added = history.prepend_unique(imported);
+        println!("Imported {} commands into the history", added);
+        return Ok(());
+    }
 
-    let execution_mode = if args.unsafe_mode {
+    let execution_mode = if args.unsafe_mode || !config.isolation {
         ExecutionMode::Unsafe
     } else {
         ExecutionMode::Isolated
@@ -60,18 +162,38 @@ fn main() -> anyhow::Result<()> {
     let bubblewrap_available = which::which("bwrap").is_ok();
 
     if !bubblewrap_available && execution_mode != ExecutionMode::Unsafe {
-        println!("bubblewrap installation not found. Please make sure you have `bwrap` on your path, or supply --no-isolation to disable safe-mode");
+        println!(
+            "bubblewrap installation not found. Please make sure you have `bwrap` on your path, or supply --no-isolation to disable safe-mode"
+        );
         std::process::exit(1);
     }
 
-    let execution_handler = CommandExecutionHandler::start(config.cmd_timeout, execution_mode, config.eval_environment.clone());
+    if let Some(command) = args.exec_command {
+        let exit_code = run_batch(&config, execution_mode, &command, args.json_output);
+        std::process::exit(exit_code);
+    }
 
-    let bookmarks = CommandList::load_from_file(config_path.join("bookmarks"), None);
-    let history = CommandList::load_from_file(config_path.join("history"), Some(config.history_size));
+    let execution_handler = CommandExecutionHandler::start(config.cmd_timeout, execution_mode, config.eval_environment.clone());
+    execution_handler.set_max_running_commands(config.max_running_commands);
+
+    let mut bookmarks = CommandList::load_from_file(config_path.join("bookmarks"), None);
+    let mut history = CommandList::load_from_file(config_path.join("history"), None)
+        .with_max_size(Some(config.history_size), config.history_when_full)
+        .with_write_interval(config.history_write_interval)
+        .with_ignore_patterns(config.history_ignore.clone());
+    let mut recent_dirs = CommandList::load_from_file(config_path.join("recent_dirs"), Some(config.recent_dirs_size));
+    if config.incognito {
+        bookmarks = bookmarks.read_only();
+        history = history.read_only();
+        recent_dirs = recent_dirs.read_only();
+    }
 
     // create app and set default
     let mut app = App::new(execution_handler, args.raw_mode, config.clone(), bookmarks, history);
 
+    app.cheatsheet = cheatsheet::load_cheatsheet(config_path.join("cheatsheet"));
+    app.recent_dirs = recent_dirs;
+
     if let Some(default_value) = args.default_content {
         app.input_state.set_content(default_value.lines().map_into().collect());
     }
@@ -80,62 +202,93 @@ fn main() -> anyhow::Result<()> {
         File::open(input_file)?.read_to_string(&mut buffer)?;
         app.input_state.set_content(buffer.lines().map_into().collect());
     }
+    if let Some(stdin_file) = args.stdin_file {
+        app.primed_stdin = Some(StdinSource::from_path(PathBuf::from(stdin_file))?);
+    }
+    if let Some(startup_command) = &config.startup_command
+        && !args.skip_startup_command
+    {
+        app.run_startup_command(startup_command);
+    }
 
     // render on stdout if output is not piped into something. if it is, use stderr.
-    if atty::is(Stream::Stdout) {
-        run_app(&mut app, io::stdout())?;
+    let render_on_stdout = atty::is(Stream::Stdout);
+    install_panic_hook(render_on_stdout);
+    let result = if render_on_stdout {
+        run_app(&mut app, io::stdout())
     } else {
-        run_app(&mut app, io::stderr())?;
-    }
+        run_app(&mut app, io::stderr())
+    };
+    // batched history changes must not get lost, even if the ui failed
+    app.history.flush();
+    result?;
 
     after_finish(&app, args.output_file)?;
 
     Ok(())
 }
 
-/// parses the arguments, handles printing help and config-reference if requested
+/// parses the arguments, handles printing the config-reference or completions if requested
 /// and otherwise returns an CliArgs instance.
 fn handle_cli_arguments() -> CliArgs {
-    let cli_args: Vec<String> = env::args().collect();
-    let program = cli_args[0].clone();
-
-    // arguments
-    let mut opts = Options::new();
-    opts.optopt("d", "default", "text inserted into the textfield on startup", "TEXT");
-    opts.optopt("o", "out-file", "write final command to file", "FILE");
-    opts.optopt("", "in-file", "read initial command from file", "FILE");
-    opts.optflag("", "config-reference", "print out the default configuration file");
-    opts.optflag("r", "raw-mode", "keep linebreaks in finished command when closing");
-    opts.optflag(
-        "",
-        "no-isolation",
-        "disable isolation. This will run the commands directly on your system, without protection. Take care.",
-    );
-    opts.optflag("h", "help", "print this help menu");
-
-    let matches = match opts.parse(&cli_args[1..]) {
-        Ok(m) => m,
-        Err(e) => {
-            eprintln!("{}: {}", program, e);
-            std::process::exit(1);
-        }
-    };
-
-    if matches.opt_present("help") {
-        let brief = format!("Usage: {} [options]", program);
-        print!("{}", opts.usage(&brief));
-        std::process::exit(0);
-    } else if matches.opt_present("config-reference") {
+    let args = CliArgs::parse();
+    if args.config_reference {
         println!("{}", pipr_config::DEFAULT_CONFIG);
         std::process::exit(0);
+    } else if let Some(shell) = args.generate_completions {
+        clap_complete::generate(shell, &mut CliArgs::command(), "pipr", &mut io::stdout());
+        std::process::exit(0);
     }
+    args
+}
 
-    CliArgs {
-        default_content: matches.opt_str("default"),
-        output_file: matches.opt_str("out-file"),
-        input_file: matches.opt_str("in-file"),
-        unsafe_mode: matches.opt_present("no-isolation"),
-        raw_mode: matches.opt_present("raw-mode"),
+/// run a single command without the UI, using the same isolation and timeout settings.
+/// prints the commands stdout and stderr, or a JSON object describing the result,
+/// and returns the exit code pipr should exit with.
+fn run_batch(config: &PiprConfig, execution_mode: ExecutionMode, command: &str, json_output: bool) -> i32 {
+    let wrapped_command = match &config.command_wrapper {
+        Some(wrapper) => wrap_command(wrapper, command),
+        None => command.to_string(),
+    };
+    let shell_command = shell_with_startup(&config.eval_environment, config.shell_startup);
+    let result = execute_command_to_completion(&shell_command, &wrapped_command, execution_mode, config.cmd_timeout);
+    let exit_code = result.as_ref().ok().and_then(|finished| finished.exit_code).unwrap_or(1);
+    if json_output {
+        println!("{}", batch_result_json(command, &result));
+        return exit_code;
+    }
+    match result {
+        Ok(finished) => {
+            let _ = io::stdout().write_all(&finished.stdout);
+            let _ = io::stderr().write_all(&finished.stderr);
+        }
+        Err(err) => eprintln!("pipr: {}", err),
+    }
+    exit_code
+}
+
+/// The result of a batch run as JSON.
+/// Output that isn't valid UTF-8 is base64 encoded, which is noted in the `stdout_encoding` / `stderr_encoding` fields.
+fn batch_result_json(command: &str, result: &Result<FinishedCommand, ExecError>) -> serde_json::Value {
+    let encode = |bytes: &[u8]| match std::str::from_utf8(bytes) {
+        Ok(text) => (text.to_string(), "utf-8"),
+        Err(_) => (base64::engine::general_purpose::STANDARD.encode(bytes), "base64"),
+    };
+    match result {
+        Ok(finished) => {
+            let (stdout, stdout_encoding) = encode(&finished.stdout);
+            let (stderr, stderr_encoding) = encode(&finished.stderr);
+            serde_json::json!({
+                "command": command,
+                "exit_code": finished.exit_code,
+                "stdout": stdout,
+                "stdout_encoding": stdout_encoding,
+                "stderr": stderr,
+                "stderr_encoding": stderr_encoding,
+                "duration_ms": finished.duration.as_millis() as u64,
+            })
+        }
+        Err(err) => serde_json::json!({ "command": command, "error": err.to_string() }),
     }
 }
 
@@ -159,6 +312,16 @@ fn after_finish(app: &App, out_file: Option<String>) -> anyhow::Result<()> {
         }
     }
 
+    if !app.config.incognito {
+        let out_file_env = out_file
+            .iter()
+            .map(|file| ("PIPR_OUT_FILE", file.clone()))
+            .collect::<Vec<_>>();
+        if let Err(err) = app.config.hooks.run(HookEvent::Exit, &finished_command, &out_file_env) {
+            eprintln!("pipr: failed to run the on_exit hook: {}", err);
+        }
+    }
+
     println!("{}", finished_command);
     if let Some(out_file) = out_file {
         File::create(out_file)?.write_all(finished_command.as_bytes())?;
@@ -166,12 +329,52 @@ fn after_finish(app: &App, out_file: Option<String>) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Leave the alternate screen and raw mode, and show the cursor again.
+/// Used both when pipr exits normally and when it panics.
+fn restore_terminal<W: Write>(output_stream: &mut W) -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(
+        output_stream,
+        LeaveAlternateScreen,
+        DisableBracketedPaste,
+        DisableMouseCapture,
+        cursor::Show
+    )
+}
+
+/// Restore the terminal before a panic message is printed, so it is readable and the terminal stays usable.
+/// Panics in any thread end pipr, as the UI can't continue without its helper threads.
+fn install_panic_hook(render_on_stdout: bool) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // errors are ignored, as nothing more can be done about them while panicking
+        let _ = if render_on_stdout {
+            restore_terminal(&mut io::stdout())
+        } else {
+            restore_terminal(&mut io::stderr())
+        };
+        default_hook(info);
+        std::process::exit(1);
+    }));
+}
+
 // Start a thread that reads events from crossterm and sends them through a channel
 fn spawn_event_reader_thread() -> Receiver<CEvent> {
     let (sender, receiver) = unbounded();
 
     thread::spawn(move || {
-        while let Ok(event) = event::read() {
+        loop {
+            // while another program runs in the terminal, its input must not be taken away from it
+            if ui::PAUSE_EVENT_READER.load(std::sync::atomic::Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(50));
+                continue;
+            }
+            match event::poll(Duration::from_millis(50)) {
+                Ok(false) => continue,
+                Ok(true) => {}
+                Err(_) => break,
+            }
+            let Ok(event) = event::read() else { break };
             // If sending fails, the channel is closed, so exit the thread
             if sender.send(event).is_err() {
                 break;
@@ -183,19 +386,14 @@ fn spawn_event_reader_thread() -> Receiver<CEvent> {
 }
 
 fn run_app<W: Write>(app: &mut App, mut output_stream: W) -> anyhow::Result<()> {
-    execute!(output_stream, EnterAlternateScreen)?;
+    execute!(output_stream, EnterAlternateScreen, EnableBracketedPaste)?;
+    if app.config.mouse_support {
+        execute!(output_stream, EnableMouseCapture)?;
+    }
     enable_raw_mode()?;
     let backend = CrosstermBackend::new(output_stream);
     let mut terminal = Terminal::new(backend)?;
 
-    std::panic::set_hook(Box::new(|data| {
-        disable_raw_mode().unwrap();
-        execute!(io::stdout(), LeaveAlternateScreen).unwrap();
-        execute!(io::stderr(), LeaveAlternateScreen).unwrap();
-        eprintln!("{}", data);
-        std::process::exit(1);
-    }));
-
     let mut all_errors = Vec::new();
 
     // Create a tick channel
@@ -213,36 +411,99 @@ fn run_app<W: Write>(app: &mut App, mut output_stream: W) -> anyhow::Result<()>
     // Create an event reader thread
     let event_receiver = spawn_event_reader_thread();
 
+    // events are handled as they come in, but redraws are limited to the configured frame rate,
+    // so bursts of events only cause a single redraw
+    let frame_interval = app.config.min_frame_interval.unwrap_or_default();
+    let mut last_draw: Option<Instant> = None;
+    let mut needs_redraw = true;
     while !app.should_quit {
-        let draw_result = ui::draw_app(&mut terminal, app);
-        if let Err(err) = draw_result {
-            all_errors.push(format!("{}", err));
+        let until_next_frame = last_draw.map_or(Duration::ZERO, |last_draw| frame_interval.saturating_sub(last_draw.elapsed()));
+        if needs_redraw && until_next_frame.is_zero() {
+            let draw_result = ui::draw_app(&mut terminal, app);
+            if let Err(err) = draw_result {
+                all_errors.push(format!("{}", err));
+            }
+            last_draw = Some(Instant::now());
+            needs_redraw = false;
         }
+        let redraw_timer = if needs_redraw { after(until_next_frame) } else { never() };
 
         select! {
+            recv(redraw_timer) -> _ => continue,
             recv(app.execution_handler.cmd_out_receive) -> msg => {
                 if let Ok(cmd_output) = msg {
                     app.on_cmd_output(cmd_output);
                 }
+                if std::mem::take(&mut app.ring_bell) {
+                    write!(terminal.backend_mut(), "\x07")?;
+                    std::io::Write::flush(terminal.backend_mut())?;
+                }
             },
             recv(tick_receiver) -> _ => {
                 app.on_tick();
             },
             recv(event_receiver) -> msg => {
-                if let Ok(CEvent::Key(key_evt)) = msg {
-                    app.on_tui_event(key_evt.code, key_evt.modifiers);
+                match msg {
+                    Ok(CEvent::Key(key_evt)) => app.on_tui_event(key_evt.code, key_evt.modifiers),
+                    Ok(CEvent::Paste(text)) => app.on_paste(text),
+                    Ok(CEvent::Mouse(mouse_evt)) => app.on_mouse_event(mouse_evt),
+                    Ok(CEvent::Resize(..)) => {
+                        // redraw everything, instead of only what changed compared to the content before the resize
+                        terminal.clear()?;
+                        app.on_resize();
+                    }
+                    _ => {}
                 }
             }
         }
+        needs_redraw = true;
     }
 
     app.execution_handler.stop();
 
-    disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    restore_terminal(terminal.backend_mut())?;
     std::io::Write::flush(&mut terminal.backend_mut())?;
     if !all_errors.is_empty() {
         eprintln!("{}", all_errors.join("\n"));
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cli_definition() {
+        CliArgs::command().debug_assert();
+        let args = CliArgs::try_parse_from(["pipr", "-d", "ls", "--no-isolation", "--generate-completions", "fish"]).unwrap();
+        assert_eq!(args.default_content.as_deref(), Some("ls"));
+        assert!(args.unsafe_mode);
+        assert_eq!(args.generate_completions, Some(Shell::Fish));
+        assert!(CliArgs::try_parse_from(["pipr", "--json"]).is_err(), "--json needs --exec");
+    }
+
+    #[test]
+    fn test_batch_result_json() {
+        let finished = FinishedCommand {
+            stdout: b"hi\n".to_vec(),
+            stderr: vec![0xff, 0x00],
+            exit_code: Some(0),
+            duration: Duration::from_millis(12),
+        };
+        assert_eq!(
+            batch_result_json("echo hi", &Ok(finished)),
+            serde_json::json!({
+                "command": "echo hi",
+                "exit_code": 0,
+                "stdout": "hi\n",
+                "stdout_encoding": "utf-8",
+                "stderr": "/wA=",
+                "stderr_encoding": "base64",
+                "duration_ms": 12,
+            })
+        );
+        let timed_out = batch_result_json("sleep 9", &Err(ExecError::Timeout));
+        assert_eq!(timed_out["error"], "Command timed out");
+    }
+}
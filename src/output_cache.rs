@@ -0,0 +1,112 @@
+//! Outputs of recently run commands, so running the same command again within a configurable time
+//! shows its output right away instead of running it again.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::command_evaluation::{ExecutionMode, StderrLine};
+
+/// Everything that decides what a command outputs, as far as pipr knows
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub command: String,
+    pub stdin: Option<Vec<String>>,
+    pub use_pty: bool,
+    pub execution_mode: ExecutionMode,
+}
+
+/// The output of a command that finished successfully
+#[derive(Debug, Clone)]
+pub struct CachedOutput {
+    pub stdout: Vec<u8>,
+    pub stderr_lines: Vec<StderrLine>,
+    pub created: Instant,
+}
+
+pub struct OutputCache {
+    entries: HashMap<CacheKey, CachedOutput>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl OutputCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> OutputCache {
+        OutputCache {
+            entries: HashMap::new(),
+            ttl,
+            max_entries,
+        }
+    }
+
+    /// The output stored for the key, unless it is older than the ttl.
+    pub fn get(&self, key: &CacheKey) -> Option<&CachedOutput> {
+        self.entries.get(key).filter(|output| output.created.elapsed() < self.ttl)
+    }
+
+    /// Store the output of a command, dropping expired outputs and, if the cache is still full, the oldest one.
+    pub fn insert(&mut self, key: CacheKey, stdout: Vec<u8>, stderr_lines: Vec<StderrLine>) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let ttl = self.ttl;
+        self.entries.retain(|_, output| output.created.elapsed() < ttl);
+        if self.entries.len() >= self.max_entries && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, output)| output.created)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        let created = Instant::now();
+        self.entries.insert(
+            key,
+            CachedOutput {
+                stdout,
+                stderr_lines,
+                created,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(command: &str) -> CacheKey {
+        CacheKey {
+            command: command.to_string(),
+            stdin: None,
+            use_pty: false,
+            execution_mode: ExecutionMode::Isolated,
+        }
+    }
+
+    #[test]
+    fn test_output_cache() {
+        let mut cache = OutputCache::new(Duration::from_secs(60), 2);
+        cache.insert(key("a"), b"1".to_vec(), Vec::new());
+        cache.insert(key("b"), b"2".to_vec(), Vec::new());
+        assert_eq!(cache.get(&key("a")).unwrap().stdout, b"1");
+        assert!(
+            cache
+                .get(&CacheKey {
+                    use_pty: true,
+                    ..key("a")
+                })
+                .is_none()
+        );
+
+        // the oldest output makes room for a new one
+        cache.insert(key("c"), b"3".to_vec(), Vec::new());
+        assert!(cache.get(&key("a")).is_none());
+        assert_eq!(cache.get(&key("b")).unwrap().stdout, b"2");
+        assert_eq!(cache.get(&key("c")).unwrap().stdout, b"3");
+
+        let mut cache = OutputCache::new(Duration::ZERO, 2);
+        cache.insert(key("a"), b"1".to_vec(), Vec::new());
+        assert!(cache.get(&key("a")).is_none());
+    }
+}
@@ -1,12 +1,21 @@
 use std::collections::HashMap;
 use std::fs::{DirBuilder, File};
 use std::io::prelude::*;
-use std::{path::PathBuf, time::Duration};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::style::Color;
+use regex::Regex;
 
 use super::snippets::*;
+use crate::command_evaluation::{DEFAULT_MAX_RUNNING_COMMANDS, LineEnding, ShellStartup, StdinFormat};
 use maplit::hashmap;
 
-use crate::command_template::CommandTemplate;
+use crate::command_template::{COMMAND_TEMPLATE_PLACEHOLDER_TOKEN, CommandTemplate};
+use crate::commandlist::{NearDuplicateMatch, TrimStrategy};
+use crate::hooks::Hooks;
 
 pub const DEFAULT_CONFIG: &str = "
 #  ____  _
@@ -19,6 +28,10 @@ pub const DEFAULT_CONFIG: &str = "
 # A commandline utility by
 # Leon Kowarschick
 
+# startup_command: Run once when pipr starts, showing its output before you typed anything.
+# Use it to see where you are, or to print a banner with your own notes. Skip it with --no-startup.
+# startup_command = \"git status --short --branch\"
+
 # finish_hook: Executed once you close pipr, getting the command you constructed piped into stdin.
 # finish_hook = \"xclip -selection clipboard -in\"
 
@@ -26,66 +39,544 @@ pub const DEFAULT_CONFIG: &str = "
 paranoid_history_mode_default = false
 
 autoeval_mode_default = true
+# In autoeval mode, make Enter insert a newline, as commands are run while typing anyway.
+# Alt+Enter or Ctrl+Enter then run the command right away.
+autoeval_enter_inserts_newline = false
+# Panic button for autoeval: turns it off and kills the running command right away, before anything else you type runs.
+# A key like \"F12\", \"esc\" or \"x\", optionally with modifiers like \"ctrl+alt+x\".
+autoeval_off_key = \"F12\"
+
+# Commands that are empty or only consist of disabled lines are never run. Show a notification when trying to run one.
+notify_on_empty_command = false
+
+# Run commands inside a pseudo-terminal, so tools that check for a terminal behave like they would interactively.
+# Stdout and stderr are merged in this mode. Can be toggled at runtime with Alt+T.
+pty_mode_default = false
+
+# Run commands in a single shell that is kept alive, so state like the working directory or variables
+# carries over from one command to the next. The shell is started from eval_environment, without its -c.
+# Commands only run when pressing Enter in this mode, and pty mode has no effect. Toggle at runtime with Alt+S.
+persistent_session_default = false
+
+# Pretty-print and highlight output that is a JSON object or array. Toggle at runtime with Alt+J.
+pretty_json_default = false
+
+# Set FORCE_COLOR, CLICOLOR_FORCE and COLUMNS (the width of the output) for commands,
+# which makes a lot of tools print colored output even though they're not running in a terminal.
+force_color = false
 
 history_size = 500
+# What to do once the history holds history_size commands: \"drop_oldest\" to make room for new commands,
+# or \"refuse_new\" to keep the history as it is and not add new commands, for keeping it as an archive.
+history_when_full = \"drop_oldest\"
+# Write the history to disk at most once every this many seconds, instead of after every command.
+# Useful if your config directory is on a slow or network filesystem. The history is always saved on exit.
+history_write_interval_secs = 0
+# Commands that are never added to the history, as regular expressions matching the whole command.
+# e.g. [\"clear\", \"ls( -\\\\w+)*\", \" .*\"] skips clear, ls with only flags and commands starting with a space.
+history_ignore = []
+# Remove trailing whitespace and trailing empty lines from commands before running them and adding them to the history.
+# Whitespace within quotes and heredocs is kept. The input itself is left as you typed it.
+trim_trailing_whitespace = false
+# Number of directories remembered from the commands you run, which Alt+G offers to insert. 0 disables this.
+recent_dirs_size = 20
+# Never write the history or bookmarks to disk. Commands are still kept in the history until pipr exits,
+# and new bookmarks are lost on exit. Can also be enabled for a single run with --incognito.
+incognito = false
+# Profiles (pipr --profile work) keep their own bookmarks and history in ~/.config/pipr/profiles/<name>/.
+# A pipr.toml in that directory overrides the settings of this file for the profile, like isolation or snippets.
 cmdlist_always_show_preview = false
+# Show the bookmarks in a sidebar next to the input, taking up this percentage of the width. Toggle at runtime with Alt+B.
+bookmark_sidebar_default = false
+bookmark_sidebar_width = 30
+# What to do when bookmarking a command that is similar to an existing bookmark:
+# \"ask\" whether to replace the existing one, \"always\" replace it, or \"never\" replace it and add a new bookmark.
+bookmark_replace_near_duplicates = \"never\"
+# When a bookmark counts as similar: \"first_line\" if the first lines are equal, \"program\" if they start with the same program.
+bookmark_near_duplicate_match = \"first_line\"
 cmd_timeout_millis = 2000
+# Commands that are replaced by a newer one or time out are killed, but may take a moment to actually exit.
+# Once this many of them are still running, new commands wait until one of them exited. Set to 0 to disable the limit.
+max_running_commands = 4
+# Once a command has been running for this many seconds, the output title shows how long it has been running for.
+# Set to 0 to never show it.
+slow_command_warn_secs = 3
+# Let you know when a command that ran for at least completion_notify_secs seconds finished, so you can switch away
+# while it runs. \"bell\" rings the terminal bell, \"desktop\" sends a desktop notification through notify-send,
+# \"both\" does both and \"off\" neither.
+completion_notify = \"off\"
+completion_notify_secs = 10
 
+# Syntax highlight the input, previews and pretty-printed JSON output. Toggle at runtime with Alt+H.
 highlighting_enabled = true
+# Folder with additional .sublime-syntax files to highlight with, searched recursively. Syntaxes for the sh or json extensions
+# replace the built-in ones used for the input and for pretty-printed JSON output.
+# syntax_dir = \"~/.config/pipr/syntaxes\"
+show_line_numbers = false
+# Render spaces as · and tabs as → in the input, to spot stray whitespace. Toggle at runtime with Alt+V.
+show_whitespace = false
+# Number of columns between tab stops when showing tabs in the input and the output.
+tab_width = 8
+# Show the number of output lines in the output title, like \"Output (42 lines)\".
+show_output_line_count = true
+# In autoeval mode, output lines that weren't in the previous output are highlighted for this many milliseconds,
+# so you can see what your last edit changed. Set to 0 to disable. The color is a name like \"blue\" or \"#1e3a5f\".
+output_change_highlight_millis = 1000
+output_change_highlight_color = \"darkgray\"
+# How stderr of successful commands is shown:
+# \"split\" only shows stderr in its own pane when the command failed,
+# \"merged\" shows it within the output, and \"tagged\" does the same but marks its lines with a red \"E| \".
+stderr_display = \"split\"
+
+# Click to move the cursor or select entries, and scroll the output with the mouse wheel.
+# While enabled, selecting text with the mouse needs Shift held in most terminals.
+mouse_support = false
+
+# Redraw the screen at most this many times per second, so commands producing lots of output don't keep the cpu busy.
+# Set to 0 to redraw after every event.
+max_fps = 30
+
+# Color codes are removed from the output before it is passed to output viewers or \"Open in\" targets.
+# Set this to true to pass the output on unchanged.
+keep_ansi_on_export = false
+
+# Only the first output_line_limit lines of the output are shown until you expand it with F8.
+# Set to 0 to always show the whole output.
+output_line_limit = 1000
+
+# Output lines longer than this many columns are cut off with a marker, until you expand the output with F8.
+# Set to 0 to always show whole lines.
+max_display_line_len = 1000
+
+# Show an output of a single line without the bordered output pane, as one line right below the input.
+compact_output = false
+
+# Keep the output scrolled to the same position when the command is run again, instead of jumping back to the top.
+keep_output_scroll = false
+# Like vim's scrolloff: lines kept visible around the selected entry in the bookmark, history and cheatsheet lists,
+# and lines of the previous page that stay visible when paging through the output. Set to 0 to disable.
+scrolloff = 2
+
+# Ctrl+F filters the shown output down to the lines matching a pattern, without running the command again.
+# The pattern is taken literally, set this to true to use regular expressions instead.
+output_filter_regex = false
+
+# Ctrl+G exports the command as an executable shell script, starting with these lines. Leave them empty to omit them.
+script_shebang = \"#!/usr/bin/env bash\"
+script_header = \"set -euo pipefail\"
+
+# Show the output of a command that ran successfully within this many seconds right away, instead of running it again.
+# Handy for slow, read-only commands that autoeval keeps re-running. Alt+R runs the command regardless. 0 disables caching.
+output_cache_ttl_secs = 0
+# Number of command outputs kept in the cache
+output_cache_size = 50
+
+# Keep the outputs of this many commands run with Enter, to page back to them with Alt+Left and Alt+Right.
+# 0 disables keeping them.
+output_history_size = 5
 
 eval_environment = [\"bash\", \"-c\"]
+# Let the shell read its startup files before every command, to make your aliases and functions available:
+# \"interactive\" reads the rc file like .bashrc, \"login\" also reads profile files like .bash_profile.
+# This slows down every command by the time these files take to load, so it is off (\"plain\") by default.
+# Needs a shell supporting the -i, -l and +m flags, like bash or zsh. Doesn't apply to the persistent session.
+shell_startup = \"plain\"
+
+# Path completions are offered when pressing Tab. With autocomplete_automatic,
+# they are shown while typing, once the current word is at least autocomplete_min_prefix_length characters long.
+autocomplete_automatic = false
+autocomplete_min_prefix_length = 0
+# Maximum number of completions shown at once. Set to 0 to show all of them.
+autocomplete_max_options = 100
+
+# Wrap every executed command, for example to lower its priority or measure its runtime.
+# {cmd} is replaced by the command as-is, {cmd_quoted} by the shell-quoted command.
+# command_wrapper = \"nice -n19 {cmd}\"
+
+# Run commands in a bubblewrap sandbox. Setting this to false is the same as always passing --no-isolation.
+isolation = true
+
+# Alt+A runs commands with elevated privileges through this program, asking for your password first.
+# Only works with isolation disabled (--no-isolation), as commands can't gain privileges inside the sandbox.
+elevation_command = \"sudo\"
+
+# Line ending used for the lines of an output stored with F9 when they are fed into commands, either \"lf\" or \"crlf\".
+stdin_line_ending = \"lf\"
+# Whether the last of these lines ends with a line ending too.
+# Commands in the persistent session (Alt+S) always get a trailing newline.
+stdin_trailing_newline = true
+
+# Commands prompting on the terminal (/dev/tty), like ssh or sudo asking for a password, can't be answered and
+# run until they time out, which is reported as possibly waiting for input. Set this to true to run commands
+# without a controlling terminal, so such prompts fail right away. Has no effect in pty mode.
+detach_terminal = false
+
+# A line shown above the input, like a shell prompt. Supports {cwd}, {git_branch}, {mode} and {hostname}.
+# context_line = \"{hostname}:{cwd} {git_branch} [{mode}]\"
+
+# tmux pane that Ctrl+T sends the current command to. Defaults to the previously active pane.
+# tmux_target_pane = \"{last}\"
+
+# Favorites are shown in a bar below the input, and can be inserted with Alt+1 to Alt+9.
+# Like in snippets, use || where you want your cursor to be after insertion.
+favorites = [\" | sort | uniq -c\", \" | grep -i '||'\"]
+
+# A file with more snippets, in the same format as the [snippets] table below: one key = \"snippet\" entry per line.
+# Snippets of the table take precedence over those of the file with the same key.
+# snippets_file = \"~/.config/pipr/snippets.toml\"
 
 # Snippets can be used to quickly insert common bits of shell
 # use || (two pipes) where you want your cursor to be after insertion
 [snippets]
 s = \" | sed -r 's/||//g'\"
 
+# Abbreviations are expanded when you type a space after them, if they are used as the command
+# (at the start of a line or after a pipe or another operator).
+[abbreviations]
+# gco = \"git checkout\"
+
 [help_viewers]
 'm' = \"man ??\"
 'h' = \"?? --help | less\"
 
 [output_viewers]
 'l' = \"less\"
+
+# Targets for the \"Open in\" menu (Ctrl+O). The current output is piped into the command,
+# and ?? is replaced with the (shell-quoted) current command.
+[open_in]
+'v' = { label = \"Edit output in vim\", command = \"vim -\" }
+# 'e' = { label = \"Explain with explainshell\", command = \"xdg-open 'https://explainshell.com/explain?cmd='??\" }
+
+# Scripts run in the background on events, with ?? replaced by the (shell-quoted) command.
+# They get the event and the command as $PIPR_EVENT and $PIPR_COMMAND, on_bookmark also gets $PIPR_BOOKMARKS_FILE,
+# and on_exit gets $PIPR_OUT_FILE when --out-file is used. Hooks never run in incognito mode.
+[hooks]
+# on_execute = \"echo ?? >> ~/.pipr_commands.log\"
+# on_bookmark = \"notify-send 'Bookmarked' ??\"
+# on_exit = \"notify-send 'Finished with' ??\"
 ";
 
+/// A configurable entry of the "Open in" menu.
+#[derive(Debug, Clone)]
+pub struct OpenInTarget {
+    pub label: String,
+    pub command: CommandTemplate,
+}
+
+/// A key together with the modifiers that have to be held, like `ctrl+x`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    pub fn matches(self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        // shift is part of the character for keys like `X`
+        let modifiers = if matches!(code, KeyCode::Char(_)) {
+            modifiers - KeyModifiers::SHIFT
+        } else {
+            modifiers
+        };
+        self.code == code && self.modifiers == modifiers
+    }
+}
+
+impl std::str::FromStr for KeyBinding {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<KeyBinding> {
+        let mut parts = s.split('+').map(str::trim).collect::<Vec<_>>();
+        let key = parts.pop().unwrap_or_default();
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in parts {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => anyhow::bail!("Unknown modifier `{}` in key {}", modifier, s),
+            };
+        }
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match key.to_lowercase().as_str() {
+                "esc" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                function_key => match function_key.strip_prefix('f').and_then(|num| num.parse().ok()) {
+                    Some(num @ 1..=12) => KeyCode::F(num),
+                    _ => anyhow::bail!("Unknown key: {}", s),
+                },
+            },
+        };
+        Ok(KeyBinding { code, modifiers })
+    }
+}
+
+/// How finished commands are announced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionNotify {
+    Off,
+    Bell,
+    Desktop,
+    Both,
+}
+
+impl CompletionNotify {
+    pub fn bell(self) -> bool {
+        matches!(self, CompletionNotify::Bell | CompletionNotify::Both)
+    }
+
+    pub fn desktop(self) -> bool {
+        matches!(self, CompletionNotify::Desktop | CompletionNotify::Both)
+    }
+}
+
+impl std::str::FromStr for CompletionNotify {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<CompletionNotify> {
+        match s {
+            "off" => Ok(CompletionNotify::Off),
+            "bell" => Ok(CompletionNotify::Bell),
+            "desktop" => Ok(CompletionNotify::Desktop),
+            "both" => Ok(CompletionNotify::Both),
+            _ => anyhow::bail!("Unknown completion_notify: {}", s),
+        }
+    }
+}
+
+/// Where stderr of successful commands is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StderrDisplay {
+    Split,
+    Merged,
+    Tagged,
+}
+
+impl std::str::FromStr for StderrDisplay {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<StderrDisplay> {
+        match s {
+            "split" => Ok(StderrDisplay::Split),
+            "merged" => Ok(StderrDisplay::Merged),
+            "tagged" => Ok(StderrDisplay::Tagged),
+            _ => anyhow::bail!("Unknown stderr_display: {}", s),
+        }
+    }
+}
+
+/// Whether a bookmark that is a near duplicate of a new one is replaced by it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaceNearDuplicates {
+    Ask,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ReplaceNearDuplicates {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<ReplaceNearDuplicates> {
+        match s {
+            "ask" => Ok(ReplaceNearDuplicates::Ask),
+            "always" => Ok(ReplaceNearDuplicates::Always),
+            "never" => Ok(ReplaceNearDuplicates::Never),
+            _ => anyhow::bail!("Unknown bookmark_replace_near_duplicates: {}", s),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PiprConfig {
     pub finish_hook: Option<String>,
+    pub startup_command: Option<String>,
     pub cmdlist_always_show_preview: bool,
+    pub bookmark_sidebar_default: bool,
+    /// Percentage of the width taken up by the bookmark sidebar
+    pub bookmark_sidebar_width: u16,
     pub paranoid_history_mode_default: bool,
     /// The shell or other environment to run the commands in. Default: `["bash", "-c"]`
     pub eval_environment: Vec<String>,
+    /// Startup files the shell reads before every command
+    pub shell_startup: ShellStartup,
     pub autoeval_mode_default: bool,
+    pub autoeval_enter_inserts_newline: bool,
+    /// Key turning autoeval off and killing the running command
+    pub autoeval_off_key: KeyBinding,
+    pub notify_on_empty_command: bool,
+    pub pty_mode_default: bool,
+    pub persistent_session_default: bool,
+    pub pretty_json_default: bool,
+    pub force_color: bool,
     pub cmd_timeout: Duration,
+    /// Number of commands that may run at once, 0 means no limit
+    pub max_running_commands: usize,
+    /// Running time after which a command is shown as slow. `None` never shows it.
+    pub slow_command_warn: Option<Duration>,
+    pub completion_notify: CompletionNotify,
+    /// Minimum running time of a command for its completion to be announced
+    pub completion_notify_after: Duration,
     pub history_size: usize,
+    pub history_when_full: TrimStrategy,
+    /// Batch history writes into this interval. `None` writes after every change.
+    pub history_write_interval: Option<Duration>,
+    /// Commands matching any of these are not added to the history
+    pub history_ignore: Vec<Regex>,
+    /// Trim trailing whitespace from commands before running them and adding them to the history
+    pub trim_trailing_whitespace: bool,
+    /// Keep the history and bookmarks in memory only
+    pub incognito: bool,
+    /// The profile selected with --profile, `None` for the default one
+    pub profile: Option<String>,
+    /// Run commands sandboxed, unless disabled with --no-isolation
+    pub isolation: bool,
+    /// Number of recently referenced directories that are remembered
+    pub recent_dirs_size: usize,
+    pub bookmark_replace_near_duplicates: ReplaceNearDuplicates,
+    pub bookmark_near_duplicate_match: NearDuplicateMatch,
     pub snippets: HashMap<char, Snippet>,
+    pub abbreviations: HashMap<String, String>,
+    pub favorites: Vec<Snippet>,
     pub help_viewers: HashMap<char, CommandTemplate>,
     pub output_viewers: HashMap<char, String>,
+    pub open_in_targets: HashMap<char, OpenInTarget>,
+    pub hooks: Hooks,
+    pub tmux_target_pane: String,
     pub highlighting_enabled: bool,
+    /// Folder with additional syntax definitions
+    pub syntax_dir: Option<PathBuf>,
+    pub show_line_numbers: bool,
+    pub show_whitespace: bool,
+    pub tab_width: usize,
+    pub show_output_line_count: bool,
+    /// How long changed output lines are highlighted in autoeval mode. `None` disables the highlight.
+    pub output_change_highlight: Option<Duration>,
+    pub output_change_highlight_color: Color,
+    pub stderr_display: StderrDisplay,
+    pub mouse_support: bool,
+    pub min_frame_interval: Option<Duration>,
+    pub context_line: Option<String>,
+    pub command_wrapper: Option<String>,
+    /// Program like `sudo` or `doas` that elevated commands are run through
+    pub elevation_command: String,
+    /// How stored outputs are written into the stdin of commands
+    pub stdin_format: StdinFormat,
+    /// Run commands without a controlling terminal, so they can't prompt on it
+    pub detach_terminal: bool,
+    /// Number of output lines shown before the output has to be expanded. 0 means no limit.
+    pub output_line_limit: usize,
+    /// Number of columns output lines are cut off at until the output is expanded. 0 means no limit.
+    pub max_display_line_len: usize,
+    /// Show single line outputs without the output pane
+    pub compact_output: bool,
+    pub keep_output_scroll: bool,
+    /// Number of context lines kept visible around the selected list entry, and when paging through the output
+    pub scrolloff: usize,
+    /// Whether ANSI escape codes are kept when passing the output to other programs
+    pub keep_ansi_on_export: bool,
+    pub output_filter_regex: bool,
+    /// How long outputs are reused for. `None` disables the output cache.
+    pub output_cache_ttl: Option<Duration>,
+    pub output_cache_size: usize,
+    /// Number of outputs of commands run with Enter that can be paged back to
+    pub output_history_size: usize,
+    /// First lines of scripts the command is exported to
+    pub script_shebang: String,
+    pub script_header: String,
+    /// Whether completions are shown while typing, instead of only when pressing Tab
+    pub autocomplete_automatic: bool,
+    /// Minimum length of the current word before completions are offered
+    pub autocomplete_min_prefix_length: usize,
+    /// Maximum number of completions shown. 0 means no limit.
+    pub autocomplete_max_options: usize,
+}
+
+/// names the config file is looked up by in each config directory, in order of preference
+const CONFIG_FILE_NAMES: [&str; 2] = ["config.toml", "pipr.toml"];
+
+/// Possible config file locations, in the order they are looked up in.
+/// `xdg_config_home` is checked first, then each of the `xdg_config_dirs`.
+fn config_file_candidates(xdg_config_home: &Path, xdg_config_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    std::iter::once(xdg_config_home)
+        .chain(xdg_config_dirs.iter().map(PathBuf::as_path))
+        .flat_map(|dir| CONFIG_FILE_NAMES.iter().map(move |name| dir.join("pipr").join(name)))
+        .collect()
+}
+
+/// Find the config file according to the XDG base directory specification.
+/// If none exists yet, this is the path where the default config should be created.
+pub fn find_config_file(xdg_config_home: &Path) -> PathBuf {
+    let xdg_config_dirs = std::env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".to_string())
+        .split(':')
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    config_file_candidates(xdg_config_home, &xdg_config_dirs)
+        .into_iter()
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| xdg_config_home.join("pipr").join("pipr.toml"))
 }
 
 impl PiprConfig {
-    pub fn load_from_file(path: &PathBuf) -> PiprConfig {
-        DirBuilder::new().recursive(true).create(path.parent().unwrap()).unwrap();
+    /// Load the config file, creating the default config if it does not exist yet.
+    /// Settings in the profile's config file, if it exists, take precedence.
+    pub fn load_from_file(path: &Path, profile_file: Option<&Path>) -> anyhow::Result<PiprConfig> {
         if !path.exists() {
-            create_default_file(path);
+            if let Some(parent) = path.parent() {
+                DirBuilder::new().recursive(true).create(parent)?;
+            }
+            create_default_file(path).with_context(|| format!("Could not create config file {}", path.display()))?;
         }
 
+        let mut builder = config::Config::builder().add_source(config::File::from(path).format(config::FileFormat::Toml));
+        if let Some(profile_file) = profile_file {
+            builder = builder.add_source(
+                config::File::from(profile_file)
+                    .format(config::FileFormat::Toml)
+                    .required(false),
+            );
+        }
+        let settings = builder
+            .build()
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+
+        PiprConfig::from_settings(settings).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// The configuration of a freshly created config file.
+    #[cfg(test)]
+    pub fn default_config() -> PiprConfig {
         let settings = config::Config::builder()
-            .add_source(config::File::new(path.to_str().unwrap(), config::FileFormat::Toml))
+            .add_source(config::File::from_str(DEFAULT_CONFIG, config::FileFormat::Toml))
             .build()
             .unwrap();
-
-        PiprConfig::from_settings(settings)
+        PiprConfig::from_settings(settings).unwrap()
     }
 
-    fn from_settings(settings: config::Config) -> PiprConfig {
-        let snippets = settings
-            .get::<HashMap<_, String>>("snippets")
+    fn from_settings(settings: config::Config) -> anyhow::Result<PiprConfig> {
+        let history_ignore = settings
+            .get::<Vec<String>>("history_ignore")
             .unwrap_or_default()
             .iter()
-            .map(|(&k, v)| (k, Snippet::parse(v)))
-            .collect();
+            .map(|pattern| {
+                Regex::new(&format!("^(?:{})$", pattern)).with_context(|| format!("Invalid history_ignore pattern {:?}", pattern))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut snippets = match settings.get_string("snippets_file").ok().filter(|file| !file.is_empty()) {
+            Some(file) => load_snippets_file(&expand_home(&file))?,
+            None => HashMap::new(),
+        };
+        snippets.extend(
+            settings
+                .get::<HashMap<_, String>>("snippets")
+                .unwrap_or_default()
+                .iter()
+                .map(|(&k, v)| (k, Snippet::parse(v))),
+        );
 
         let help_viewers = settings
             .get::<HashMap<char, String>>("help_viewers")
@@ -97,28 +588,289 @@ impl PiprConfig {
             .map(|(k, v)| (k, CommandTemplate::from_string(v).unwrap()))
             .collect::<HashMap<_, _>>();
 
-        PiprConfig {
+        let open_in_targets = settings
+            .get::<HashMap<char, HashMap<String, String>>>("open_in")
+            .unwrap_or_else(|_| {
+                hashmap! {
+                    'v' => hashmap! { "label".into() => "Edit output in vim".into(), "command".into() => "vim -".into() },
+                }
+            })
+            .into_iter()
+            .filter_map(|(k, mut v)| {
+                let command = CommandTemplate::from_string(v.remove("command")?).ok()?;
+                let label = v
+                    .remove("label")
+                    .unwrap_or_else(|| command.resolve(COMMAND_TEMPLATE_PLACEHOLDER_TOKEN));
+                Some((k, OpenInTarget { label, command }))
+            })
+            .collect();
+
+        Ok(PiprConfig {
             finish_hook: settings.get_string("finish_hook").ok(),
+            startup_command: settings.get_string("startup_command").ok().filter(|x| !x.is_empty()),
             paranoid_history_mode_default: settings.get_bool("paranoid_history_mode_default").unwrap_or(false),
             autoeval_mode_default: settings.get_bool("autoeval_mode_default").unwrap_or(false),
+            autoeval_enter_inserts_newline: settings.get_bool("autoeval_enter_inserts_newline").unwrap_or(false),
+            autoeval_off_key: settings
+                .get_string("autoeval_off_key")
+                .unwrap_or_else(|_| "F12".into())
+                .parse()
+                .context("Invalid autoeval_off_key")?,
+            notify_on_empty_command: settings.get_bool("notify_on_empty_command").unwrap_or(false),
+            pty_mode_default: settings.get_bool("pty_mode_default").unwrap_or(false),
+            persistent_session_default: settings.get_bool("persistent_session_default").unwrap_or(false),
+            pretty_json_default: settings.get_bool("pretty_json_default").unwrap_or(false),
+            force_color: settings.get_bool("force_color").unwrap_or(false),
             cmd_timeout: Duration::from_millis(settings.get_int("cmd_timeout_millis").unwrap_or(2000) as u64),
+            max_running_commands: settings
+                .get_int("max_running_commands")
+                .map_or(DEFAULT_MAX_RUNNING_COMMANDS, |max| max.max(0) as usize),
+            completion_notify: settings
+                .get_string("completion_notify")
+                .ok()
+                .and_then(|notify| notify.parse().ok())
+                .unwrap_or(CompletionNotify::Off),
+            completion_notify_after: Duration::from_secs(settings.get_int("completion_notify_secs").unwrap_or(10).max(0) as u64),
+            slow_command_warn: Some(settings.get_int("slow_command_warn_secs").unwrap_or(3))
+                .filter(|&secs| secs > 0)
+                .map(|secs| Duration::from_secs(secs as u64)),
             eval_environment: settings
                 .get_array("eval_environment")
                 .map(|arr| arr.iter().filter_map(|v| v.clone().into_string().ok()).collect())
                 .unwrap_or_else(|_| vec!["bash".into(), "-c".into()]),
+            shell_startup: settings
+                .get_string("shell_startup")
+                .ok()
+                .and_then(|startup| startup.parse().ok())
+                .unwrap_or_default(),
             history_size: settings.get_int("history_size").unwrap_or(500) as usize,
+            history_when_full: settings
+                .get_string("history_when_full")
+                .ok()
+                .and_then(|strategy| strategy.parse().ok())
+                .unwrap_or(TrimStrategy::DropOldest),
+            history_write_interval: Some(settings.get_int("history_write_interval_secs").unwrap_or(0))
+                .filter(|&secs| secs > 0)
+                .map(|secs| Duration::from_secs(secs as u64)),
+            history_ignore,
+            trim_trailing_whitespace: settings.get_bool("trim_trailing_whitespace").unwrap_or(false),
+            incognito: settings.get_bool("incognito").unwrap_or(false),
+            profile: None,
+            isolation: settings.get_bool("isolation").unwrap_or(true),
+            recent_dirs_size: settings.get_int("recent_dirs_size").unwrap_or(20) as usize,
             cmdlist_always_show_preview: settings.get_bool("cmdlist_always_show_preview").unwrap_or(false),
+            bookmark_sidebar_default: settings.get_bool("bookmark_sidebar_default").unwrap_or(false),
+            bookmark_sidebar_width: settings.get_int("bookmark_sidebar_width").unwrap_or(30).clamp(10, 90) as u16,
+            bookmark_replace_near_duplicates: settings
+                .get_string("bookmark_replace_near_duplicates")
+                .ok()
+                .and_then(|replace| replace.parse().ok())
+                .unwrap_or(ReplaceNearDuplicates::Never),
+            bookmark_near_duplicate_match: settings
+                .get_string("bookmark_near_duplicate_match")
+                .ok()
+                .and_then(|matching| matching.parse().ok())
+                .unwrap_or(NearDuplicateMatch::FirstLine),
             highlighting_enabled: settings.get_bool("highlighting_enabled").unwrap_or(true),
+            syntax_dir: settings
+                .get_string("syntax_dir")
+                .ok()
+                .filter(|dir| !dir.is_empty())
+                .map(|dir| expand_home(&dir)),
+            show_line_numbers: settings.get_bool("show_line_numbers").unwrap_or(false),
+            show_whitespace: settings.get_bool("show_whitespace").unwrap_or(false),
+            tab_width: settings.get_int("tab_width").unwrap_or(8).max(1) as usize,
+            show_output_line_count: settings.get_bool("show_output_line_count").unwrap_or(true),
+            output_change_highlight: Some(settings.get_int("output_change_highlight_millis").unwrap_or(1000))
+                .filter(|&millis| millis > 0)
+                .map(|millis| Duration::from_millis(millis as u64)),
+            output_change_highlight_color: settings
+                .get_string("output_change_highlight_color")
+                .ok()
+                .and_then(|color| color.parse().ok())
+                .unwrap_or(Color::DarkGray),
+            stderr_display: settings
+                .get_string("stderr_display")
+                .ok()
+                .and_then(|display| display.parse().ok())
+                .unwrap_or(StderrDisplay::Split),
+            mouse_support: settings.get_bool("mouse_support").unwrap_or(false),
+            min_frame_interval: Some(settings.get_int("max_fps").unwrap_or(30))
+                .filter(|&fps| fps > 0)
+                .map(|fps| Duration::from_secs(1) / fps as u32),
+            keep_ansi_on_export: settings.get_bool("keep_ansi_on_export").unwrap_or(false),
+            output_filter_regex: settings.get_bool("output_filter_regex").unwrap_or(false),
+            output_cache_ttl: Some(settings.get_int("output_cache_ttl_secs").unwrap_or(0))
+                .filter(|&secs| secs > 0)
+                .map(|secs| Duration::from_secs(secs as u64)),
+            output_cache_size: settings.get_int("output_cache_size").unwrap_or(50).max(0) as usize,
+            output_history_size: settings.get_int("output_history_size").unwrap_or(5).max(0) as usize,
+            script_shebang: settings
+                .get_string("script_shebang")
+                .unwrap_or_else(|_| "#!/usr/bin/env bash".into()),
+            script_header: settings
+                .get_string("script_header")
+                .unwrap_or_else(|_| "set -euo pipefail".into()),
+            output_line_limit: settings.get_int("output_line_limit").unwrap_or(1000) as usize,
+            max_display_line_len: settings.get_int("max_display_line_len").unwrap_or(1000).max(0) as usize,
+            compact_output: settings.get_bool("compact_output").unwrap_or(false),
+            keep_output_scroll: settings.get_bool("keep_output_scroll").unwrap_or(false),
+            scrolloff: settings.get_int("scrolloff").unwrap_or(2).max(0) as usize,
+            autocomplete_automatic: settings.get_bool("autocomplete_automatic").unwrap_or(false),
+            autocomplete_min_prefix_length: settings.get_int("autocomplete_min_prefix_length").unwrap_or(0) as usize,
+            autocomplete_max_options: settings.get_int("autocomplete_max_options").unwrap_or(100) as usize,
             output_viewers: settings
                 .get("output_viewers")
                 .unwrap_or_else(|_| hashmap! { 'l' => "less".into() }),
+            command_wrapper: settings.get_string("command_wrapper").ok().filter(|x| !x.is_empty()),
+            elevation_command: settings
+                .get_string("elevation_command")
+                .ok()
+                .filter(|x| !x.is_empty())
+                .unwrap_or_else(|| "sudo".into()),
+            stdin_format: StdinFormat {
+                line_ending: settings
+                    .get_string("stdin_line_ending")
+                    .ok()
+                    .and_then(|x| x.parse().ok())
+                    .unwrap_or(LineEnding::Lf),
+                trailing_newline: settings.get_bool("stdin_trailing_newline").unwrap_or(true),
+            },
+            detach_terminal: settings.get_bool("detach_terminal").unwrap_or(false),
+            context_line: settings.get_string("context_line").ok().filter(|x| !x.is_empty()),
+            tmux_target_pane: settings.get_string("tmux_target_pane").unwrap_or_else(|_| "{last}".into()),
+            favorites: settings
+                .get::<Vec<String>>("favorites")
+                .unwrap_or_default()
+                .iter()
+                .take(9)
+                .map(|x| Snippet::parse(x))
+                .collect(),
+            abbreviations: settings.get("abbreviations").unwrap_or_default(),
             help_viewers,
+            open_in_targets,
+            hooks: Hooks::from_table(settings.get::<HashMap<String, String>>("hooks").unwrap_or_default())?,
             snippets,
-        }
+        })
+    }
+}
+
+/// The directory the bookmarks, history and config overrides of the named profile are stored in
+pub fn profile_dir(config_path: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        anyhow::bail!(
+            "Invalid profile name `{}`, it can't be empty, start with a . or contain a /",
+            name
+        );
+    }
+    Ok(config_path.join("profiles").join(name))
+}
+
+/// The path with a leading `~/` replaced by the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(relative), Some(home)) => Path::new(&home).join(relative),
+        _ => PathBuf::from(path),
     }
 }
 
-fn create_default_file(path: &PathBuf) {
-    let mut file = File::create(path).unwrap();
-    file.write_all(DEFAULT_CONFIG.as_bytes()).unwrap();
+fn create_default_file(path: &Path) -> std::io::Result<()> {
+    File::create(path)?.write_all(DEFAULT_CONFIG.as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_file_candidates() {
+        let candidates = config_file_candidates(Path::new("/home/x/.config"), &[PathBuf::from("/etc/xdg")]);
+        assert_eq!(
+            candidates,
+            vec![
+                PathBuf::from("/home/x/.config/pipr/config.toml"),
+                PathBuf::from("/home/x/.config/pipr/pipr.toml"),
+                PathBuf::from("/etc/xdg/pipr/config.toml"),
+                PathBuf::from("/etc/xdg/pipr/pipr.toml"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_malformed_config() {
+        let path = std::env::temp_dir().join(format!("pipr-test-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "history_size = 10\nautoeval_mode_default = = true\n").unwrap();
+        let Err(err) = PiprConfig::load_from_file(&path, None) else {
+            panic!("malformed config was accepted");
+        };
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            format!("{:#}", err).contains("line 2"),
+            "error did not contain the position: {:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_profile_overrides() {
+        let config_path = std::env::temp_dir().join(format!("pipr-test-profile-{}", std::process::id()));
+        let work_dir = profile_dir(&config_path, "work").unwrap();
+        std::fs::create_dir_all(&work_dir).unwrap();
+        std::fs::write(config_path.join("pipr.toml"), "history_size = 10\nisolation = true\n").unwrap();
+        std::fs::write(work_dir.join("pipr.toml"), "isolation = false\n").unwrap();
+
+        let config = PiprConfig::load_from_file(&config_path.join("pipr.toml"), Some(&work_dir.join("pipr.toml"))).unwrap();
+        assert!(!config.isolation);
+        assert_eq!(config.history_size, 10, "settings the profile doesn't override are kept");
+        let missing_profile_file = config_path.join("profiles/other/pipr.toml");
+        assert!(
+            PiprConfig::load_from_file(&config_path.join("pipr.toml"), Some(&missing_profile_file))
+                .unwrap()
+                .isolation
+        );
+        std::fs::remove_dir_all(&config_path).unwrap();
+
+        assert!(profile_dir(&config_path, "../work").is_err());
+        assert!(profile_dir(&config_path, "").is_err());
+    }
+
+    #[test]
+    fn test_default_config_is_valid() {
+        let path = std::env::temp_dir()
+            .join(format!("pipr-test-default-{}", std::process::id()))
+            .join("pipr.toml");
+        let config = PiprConfig::load_from_file(&path, None).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert_eq!(config.history_size, 500);
+    }
+
+    #[test]
+    fn test_key_binding() {
+        let f12 = "F12".parse::<KeyBinding>().unwrap();
+        assert!(f12.matches(KeyCode::F(12), KeyModifiers::NONE));
+        assert!(!f12.matches(KeyCode::F(12), KeyModifiers::CONTROL));
+        let ctrl_x = "ctrl+alt+X".parse::<KeyBinding>().unwrap();
+        assert!(ctrl_x.matches(
+            KeyCode::Char('X'),
+            KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT
+        ));
+        assert!("esc".parse::<KeyBinding>().unwrap().matches(KeyCode::Esc, KeyModifiers::NONE));
+        assert!("F13".parse::<KeyBinding>().is_err());
+        assert!("hyper+x".parse::<KeyBinding>().is_err());
+    }
+
+    #[test]
+    fn test_invalid_history_ignore_pattern() {
+        let path = std::env::temp_dir().join(format!("pipr-test-history-ignore-{}.toml", std::process::id()));
+        std::fs::write(&path, "history_ignore = [\"clear\", \"ls (\"]\n").unwrap();
+        let Err(err) = PiprConfig::load_from_file(&path, None) else {
+            panic!("invalid pattern was accepted");
+        };
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            format!("{:#}", err).contains("\"ls (\""),
+            "error did not name the pattern: {:#}",
+            err
+        );
+    }
 }
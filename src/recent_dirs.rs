@@ -0,0 +1,72 @@
+//! Directories referenced by the commands that were run, offered for inserting into the input.
+use std::path::{Path, PathBuf};
+
+use crate::context_line::display_path;
+use crate::explain::parse_command;
+use crate::util::shell_quote;
+
+/// The existing directories the arguments of the command refer to, with the home directory shortened to `~`.
+/// Relative paths are resolved against `cwd`, which itself is left out, as there is no point in inserting it.
+pub fn referenced_dirs(command: &str, cwd: &Path) -> Vec<String> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let mut dirs = Vec::new();
+    let arguments = parse_command(command)
+        .into_iter()
+        .flat_map(|pipeline| pipeline.stages)
+        .flat_map(|stage| stage.arguments);
+    for argument in arguments {
+        let argument = argument.trim_matches(|c| c == '\'' || c == '"');
+        let path = match (argument.strip_prefix('~'), &home) {
+            (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => home.join(rest.trim_start_matches('/')),
+            _ => cwd.join(argument),
+        };
+        let Ok(path) = path.canonicalize() else {
+            continue;
+        };
+        let dir = display_path(&path);
+        if path.is_dir() && path != cwd && !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+/// Quote the directory for inserting it into a command, if needed. A leading `~/` is left unquoted, so it still expands.
+pub fn quote_dir(dir: &str) -> String {
+    let is_plain = |s: &str| s.chars().all(|c| c.is_alphanumeric() || "/._-+,:@%=".contains(c));
+    match dir.strip_prefix("~/") {
+        _ if dir == "~" || is_plain(dir) => dir.to_string(),
+        Some(rest) if is_plain(rest) => dir.to_string(),
+        Some(rest) => format!("~/{}", shell_quote(rest)),
+        None => shell_quote(dir),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_referenced_dirs() {
+        let cwd = std::env::temp_dir().join(format!("pipr-recent-dirs-{}", std::process::id()));
+        std::fs::create_dir_all(cwd.join("src/app")).unwrap();
+        std::fs::write(cwd.join("file"), "").unwrap();
+        let cwd = cwd.canonicalize().unwrap();
+        let display = |path: &Path| display_path(path);
+
+        assert_eq!(
+            referenced_dirs("ls -l src 'src/app/' file missing | grep -r x . src/app/..", &cwd),
+            vec![display(&cwd.join("src")), display(&cwd.join("src/app"))]
+        );
+        assert!(referenced_dirs("echo --dir=src", &cwd).is_empty());
+        std::fs::remove_dir_all(&cwd).unwrap();
+    }
+
+    #[test]
+    fn test_quote_dir() {
+        assert_eq!(quote_dir("/usr/local/bin"), "/usr/local/bin");
+        assert_eq!(quote_dir("~/my docs"), "~/'my docs'");
+        assert_eq!(quote_dir("/tmp/it's here"), "'/tmp/it'\\''s here'");
+        assert_eq!(quote_dir("~"), "~");
+    }
+}
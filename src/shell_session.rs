@@ -0,0 +1,329 @@
+//! A shell process that is kept alive between commands, so state like the working directory,
+//! shell variables and functions carries over from one command to the next.
+//!
+//! Commands are written into the stdin of the shell, and the end of their output is marked by a sentinel
+//! the shell prints after each command. This needs a POSIX-like shell, which is started from
+//! `eval_environment` without its trailing `-c`.
+use anyhow::{Context, bail};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, unbounded};
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::command_evaluation::{
+    CmdOutput, CommandExecutionRequest, ExecError, ExecutionMode, LineEnding, StderrLine, StdinFormat, StdinSource, shell_argv,
+    unsafe_command_matches,
+};
+use crate::util::shell_quote;
+
+pub struct ShellSession {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Receiver<Vec<u8>>,
+    stderr: Receiver<Vec<u8>>,
+    execution_mode: ExecutionMode,
+    command_count: usize,
+}
+
+/// Output of a command run in a session
+#[derive(Debug)]
+pub struct SessionOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit_code: i32,
+}
+
+impl ShellSession {
+    pub fn spawn(shell_command: &[String], mode: ExecutionMode) -> anyhow::Result<ShellSession> {
+        // the session reads its commands from stdin instead of getting them passed via -c
+        let shell = match shell_command.split_last() {
+            Some((last, rest)) if last == "-c" => rest,
+            _ => shell_command,
+        };
+        if shell.is_empty() {
+            bail!("eval_environment is empty");
+        }
+        let argv = shell_argv(shell, mode);
+        let mut child = Command::new(&argv[0])
+            .args(&argv[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // a separate process group, so the whole session including running commands can be killed at once
+            .process_group(0)
+            .spawn()
+            .context("Unable to start the shell session")?;
+        let stdin = child.stdin.take().context("No session stdin available")?;
+        let stdout = forward_output(child.stdout.take().context("No session stdout available")?);
+        let stderr = forward_output(child.stderr.take().context("No session stderr available")?);
+        Ok(ShellSession {
+            child,
+            stdin,
+            stdout,
+            stderr,
+            execution_mode: mode,
+            command_count: 0,
+        })
+    }
+
+    /// Run a command in the session and wait for it to finish.
+    /// Fails if the command timed out or the shell exited, after which the session can't be used anymore.
+    pub fn run(
+        &mut self,
+        cmd: &str,
+        stdin: Option<&StdinSource>,
+        stdin_format: StdinFormat,
+        env: &[(String, String)],
+        timeout: Duration,
+    ) -> anyhow::Result<SessionOutput> {
+        self.command_count += 1;
+        let sentinel = format!("__pipr_done_{}_{}__", std::process::id(), self.command_count);
+        self.stdin
+            .write_all(session_script(cmd, stdin, stdin_format, env, &sentinel).as_bytes())
+            .and_then(|_| self.stdin.flush())
+            .context("The shell session ended")?;
+
+        let deadline = Instant::now() + timeout;
+        // stdout ends with "<sentinel> <exit code> <sentinel>", stderr just with the sentinel
+        let (stdout, exit_code) = read_until_sentinel(&self.stdout, &sentinel, 2, deadline)?;
+        let (stderr, _) = read_until_sentinel(&self.stderr, &sentinel, 1, deadline)?;
+        Ok(SessionOutput {
+            stdout,
+            stderr,
+            exit_code: String::from_utf8_lossy(&exit_code).trim().parse().unwrap_or(-1),
+        })
+    }
+
+    fn kill(&mut self) {
+        // SAFETY: kill only sends a signal, the negative pid addresses the process group of the session
+        unsafe {
+            libc::kill(-(self.child.id() as i32), libc::SIGKILL);
+        }
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+/// The script that is written into the session to run a single command.
+/// The command is passed to eval as a single quoted string,
+/// so syntax errors like unclosed quotes can't swallow the lines following it.
+/// `command eval` keeps the shell alive on syntax errors, which would otherwise end a POSIX shell.
+/// Files and named pipes are redirected into the command by the shell itself.
+/// Stdin lines are passed in a here-document, which always ends the last line with a newline.
+fn session_script(
+    cmd: &str,
+    stdin: Option<&StdinSource>,
+    stdin_format: StdinFormat,
+    env: &[(String, String)],
+    sentinel: &str,
+) -> String {
+    let mut script = String::new();
+    for (key, value) in env {
+        script.push_str(&format!("export {}={}\n", key, shell_quote(value)));
+    }
+    match stdin {
+        Some(StdinSource::Lines(lines)) => {
+            let delimiter = format!("{}_stdin", sentinel);
+            script.push_str(&format!("{{ command eval {}\n}} <<'{}'\n", shell_quote(cmd), delimiter));
+            for line in lines {
+                script.push_str(line);
+                if stdin_format.line_ending == LineEnding::Crlf {
+                    script.push('\r');
+                }
+                script.push('\n');
+            }
+            script.push_str(&delimiter);
+            script.push('\n');
+        }
+        Some(StdinSource::File(path) | StdinSource::Fifo(path)) => script.push_str(&format!(
+            "{{ command eval {}\n}} < {}\n",
+            shell_quote(cmd),
+            shell_quote(&path.to_string_lossy())
+        )),
+        None => script.push_str(&format!("{{ command eval {}\n}} < /dev/null\n", shell_quote(cmd))),
+    }
+    script.push_str(&format!(
+        "__pipr_status=$?\nprintf '%s %d %s' '{0}' \"$__pipr_status\" '{0}'\nprintf '%s' '{0}' >&2\n",
+        sentinel
+    ));
+    script
+}
+
+/// Forward everything read from the stream into a channel, chunk by chunk.
+fn forward_output<R: Read + Send + 'static>(mut reader: R) -> Receiver<Vec<u8>> {
+    let (send, receive) = unbounded();
+    thread::spawn(move || {
+        let mut buffer = [0; 8192];
+        while let Ok(read @ 1..) = reader.read(&mut buffer) {
+            if send.send(buffer[..read].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    receive
+}
+
+/// Collect output until the sentinel was seen `count` times.
+/// Returns the output in front of the first sentinel, and whatever is between the first and the last one.
+fn read_until_sentinel(
+    output: &Receiver<Vec<u8>>,
+    sentinel: &str,
+    count: usize,
+    deadline: Instant,
+) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    let sentinel = sentinel.as_bytes();
+    let mut buffer = Vec::new();
+    loop {
+        let positions = find_all(&buffer, sentinel);
+        if positions.len() >= count {
+            let between = buffer
+                .get(positions[0] + sentinel.len()..positions[count - 1])
+                .unwrap_or_default()
+                .to_vec();
+            buffer.truncate(positions[0]);
+            return Ok((buffer, between));
+        }
+        match output.recv_deadline(deadline) {
+            Ok(chunk) => buffer.extend(chunk),
+            Err(RecvTimeoutError::Timeout) => bail!("Command timed out"),
+            Err(RecvTimeoutError::Disconnected) => bail!("The shell session ended"),
+        }
+    }
+}
+
+fn find_all(haystack: &[u8], needle: &[u8]) -> Vec<usize> {
+    haystack
+        .windows(needle.len())
+        .enumerate()
+        .filter(|(_, window)| *window == needle)
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Start a thread running the requests it receives in a persistent shell session, sending their output to `output`.
+/// The session is started on the first request, and restarted if it ended or a request needs another execution mode.
+pub fn start_session_worker(
+    shell_command: Vec<String>,
+    default_mode: ExecutionMode,
+    timeout: Duration,
+    output: Sender<CmdOutput>,
+) -> Sender<CommandExecutionRequest> {
+    let (request_send, request_receive) = unbounded::<CommandExecutionRequest>();
+    thread::spawn(move || {
+        let mut session: Option<ShellSession> = None;
+        while let Ok(mut request) = request_receive.recv() {
+            // requests that came in while the previous command was running are outdated already
+            while let Ok(newer_request) = request_receive.try_recv() {
+                request = newer_request;
+            }
+            let mode = request.execution_mode.unwrap_or(default_mode);
+            let unsafe_matches = unsafe_command_matches(&request.command);
+            let result = if mode == ExecutionMode::Unsafe && !unsafe_matches.is_empty() && !request.allow_unsafe {
+                CmdOutput::NotOk(ExecError::UnsafeCommand(unsafe_matches).to_string())
+            } else {
+                if session.as_ref().is_some_and(|session| session.execution_mode != mode) {
+                    session = None;
+                }
+                match session.take().map_or_else(|| ShellSession::spawn(&shell_command, mode), Ok) {
+                    Ok(mut running_session) => {
+                        let _ = output.send(CmdOutput::Started(Instant::now()));
+                        match running_session.run(
+                            &request.command,
+                            request.stdin.as_ref(),
+                            request.stdin_format,
+                            &request.env,
+                            timeout,
+                        ) {
+                            Ok(finished) => {
+                                session = Some(running_session);
+                                if finished.exit_code == 0 {
+                                    // both streams are only read once the command finished, so its stderr goes after stdout
+                                    let stderr_lines = String::from_utf8_lossy(&finished.stderr)
+                                        .lines()
+                                        .map(|text| StderrLine {
+                                            stdout_offset: finished.stdout.len(),
+                                            text: text.to_string(),
+                                        })
+                                        .collect();
+                                    CmdOutput::Ok(finished.stdout, stderr_lines)
+                                } else {
+                                    CmdOutput::Failed(finished.exit_code, String::from_utf8_lossy(&finished.stderr).into_owned())
+                                }
+                            }
+                            Err(err) => CmdOutput::NotOk(format!("{}, the shell session was restarted", err)),
+                        }
+                    }
+                    Err(err) => CmdOutput::NotOk(format!("{:#}", err)),
+                }
+            };
+            if output.send(result).is_err() {
+                break;
+            }
+        }
+    });
+    request_send
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(session: &mut ShellSession, cmd: &str, stdin: Option<&StdinSource>) -> (String, i32) {
+        let output = session
+            .run(cmd, stdin, StdinFormat::default(), &[], Duration::from_secs(5))
+            .unwrap();
+        (String::from_utf8_lossy(&output.stdout).into_owned(), output.exit_code)
+    }
+
+    #[test]
+    fn test_state_persists_between_commands() {
+        let mut session = ShellSession::spawn(&["sh".to_string(), "-c".to_string()], ExecutionMode::Unsafe).unwrap();
+        assert_eq!(
+            run(&mut session, "cd / && x=42; f() { echo \"f$1\"; }", None),
+            (String::new(), 0)
+        );
+        assert_eq!(run(&mut session, "pwd; echo $x; f 1", None), ("/\n42\nf1\n".to_string(), 0));
+        assert_eq!(run(&mut session, "printf no-newline", None), ("no-newline".to_string(), 0));
+
+        // a syntax error must not break the following commands
+        let output = session
+            .run("echo 'unclosed", None, StdinFormat::default(), &[], Duration::from_secs(5))
+            .unwrap();
+        assert_ne!(output.exit_code, 0);
+        assert!(!output.stderr.is_empty());
+        assert_eq!(run(&mut session, "false || echo $x", None), ("42\n".to_string(), 0));
+
+        let stdin = StdinSource::Lines(vec!["b".to_string(), "a".to_string()]);
+        assert_eq!(run(&mut session, "sort", Some(&stdin)), ("a\nb\n".to_string(), 0));
+
+        let path = std::env::temp_dir().join(format!("pipr-test-session-stdin-{}", std::process::id()));
+        std::fs::write(&path, "d\nc\n").unwrap();
+        let stdin = StdinSource::File(path.clone());
+        assert_eq!(run(&mut session, "sort", Some(&stdin)), ("c\nd\n".to_string(), 0));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_session_timeout_and_exit() {
+        let mut session = ShellSession::spawn(&["sh".to_string(), "-c".to_string()], ExecutionMode::Unsafe).unwrap();
+        assert!(
+            session
+                .run("sleep 5", None, StdinFormat::default(), &[], Duration::from_millis(200))
+                .is_err()
+        );
+
+        let mut session = ShellSession::spawn(&["sh".to_string()], ExecutionMode::Unsafe).unwrap();
+        assert!(
+            session
+                .run("exit 3", None, StdinFormat::default(), &[], Duration::from_secs(5))
+                .is_err()
+        );
+    }
+}
@@ -1,5 +1,9 @@
 //! Predefined text snippets including cursor positioning information
+use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+use anyhow::{Context, bail};
 
 /// Text snippet with cursor positioning information.
 #[derive(Debug, Clone, PartialEq)]
@@ -32,13 +36,50 @@ impl Display for Snippet {
     }
 }
 
+/// Load a snippet library, a TOML file with one `key = "snippet"` entry per snippet, like the `[snippets]` table.
+pub fn load_snippets_file(path: &Path) -> anyhow::Result<HashMap<char, Snippet>> {
+    let snippets = config::Config::builder()
+        .add_source(config::File::from(path).format(config::FileFormat::Toml))
+        .build()
+        .and_then(|settings| settings.try_deserialize::<HashMap<String, String>>())
+        .with_context(|| format!("Invalid snippets file {}", path.display()))?;
+    snippets
+        .iter()
+        .map(|(key, snippet)| {
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(key), None) => Ok((key, Snippet::parse(snippet))),
+                _ => bail!(
+                    "Snippet keys have to be a single character, got `{}` in {}",
+                    key,
+                    path.display()
+                ),
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
-    use super::Snippet;
+    use super::*;
 
     #[test]
     fn test_parsing() {
         assert_eq!(Snippet::parse("ab||c"), Snippet::new("abc".into(), 2));
         assert_eq!(Snippet::parse("abc"), Snippet::new("abc".into(), 3));
     }
+
+    #[test]
+    fn test_load_snippets_file() {
+        let path = std::env::temp_dir().join(format!("pipr-test-snippets-{}.toml", std::process::id()));
+        std::fs::write(&path, "j = \" | jq '||'\"\n'w' = \" | wc -l\"\n").unwrap();
+        let snippets = load_snippets_file(&path).unwrap();
+        assert_eq!(snippets.get(&'j'), Some(&Snippet::new(" | jq ''".into(), 7)));
+        assert_eq!(snippets.get(&'w'), Some(&Snippet::new(" | wc -l".into(), 8)));
+
+        std::fs::write(&path, "long_key = \"x\"\n").unwrap();
+        assert!(load_snippets_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(load_snippets_file(&path).is_err());
+    }
 }
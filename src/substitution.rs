@@ -0,0 +1,82 @@
+//! Finding `$(...)` command substitutions in a line, so they can be run on their own.
+use std::ops::Range;
+
+/// Quoting context of the command around a position. Every substitution starts a fresh context.
+#[derive(Debug, Default)]
+struct Context {
+    /// byte index of the `$` of the substitution, `None` for the line itself
+    start: Option<usize>,
+    /// parentheses opened within the substitution that aren't closed yet
+    open_parens: usize,
+    in_single_quotes: bool,
+    in_double_quotes: bool,
+}
+
+/// Byte ranges of all closed `$(...)` substitutions in the line, including the `$(` and `)`.
+/// Arithmetic expansions like `$((1 + 2))` and anything in single quotes are skipped.
+pub fn find_substitutions(line: &str) -> Vec<Range<usize>> {
+    let mut substitutions = Vec::new();
+    let mut contexts = vec![Context::default()];
+    let mut chars = line.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        let context = contexts.last_mut().unwrap();
+        match c {
+            '\\' if !context.in_single_quotes => {
+                chars.next();
+            }
+            '\'' if !context.in_double_quotes => context.in_single_quotes = !context.in_single_quotes,
+            _ if context.in_single_quotes => {}
+            '"' => context.in_double_quotes = !context.in_double_quotes,
+            '$' if line[idx..].starts_with("$(") && !line[idx..].starts_with("$((") => {
+                chars.next();
+                contexts.push(Context {
+                    start: Some(idx),
+                    ..Context::default()
+                });
+            }
+            _ if context.in_double_quotes => {}
+            '(' => context.open_parens += 1,
+            ')' if context.open_parens > 0 => context.open_parens -= 1,
+            ')' => {
+                if let Some(start) = context.start {
+                    substitutions.push(start..idx + 1);
+                    contexts.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+    substitutions
+}
+
+/// The command within the innermost substitution around the byte index `cursor`.
+pub fn substitution_at(line: &str, cursor: usize) -> Option<&str> {
+    find_substitutions(line)
+        .into_iter()
+        .filter(|range| range.contains(&cursor))
+        .min_by_key(|range| range.len())
+        .map(|range| &line[range.start + 2..range.end - 1])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_substitutions() {
+        assert_eq!(find_substitutions("echo $(date) $(whoami)"), vec![5..12, 13..22]);
+        assert_eq!(find_substitutions("echo \"$(ls | wc -l) files\""), vec![6..19]);
+        assert!(find_substitutions("echo '$(date)' \\$(date)").is_empty());
+        assert!(find_substitutions("echo $((1 + 2)) $(echo").is_empty());
+        assert_eq!(find_substitutions("echo $(case x in (x) echo \")\";; esac)"), vec![5..37]);
+    }
+
+    #[test]
+    fn test_substitution_at() {
+        let line = "cat $(find $(pwd) -name '*.rs')";
+        assert_eq!(substitution_at(line, 8), Some("find $(pwd) -name '*.rs'"));
+        assert_eq!(substitution_at(line, 13), Some("pwd"));
+        assert_eq!(substitution_at(line, 30), Some("find $(pwd) -name '*.rs'"));
+        assert_eq!(substitution_at(line, 1), None);
+    }
+}
@@ -0,0 +1,181 @@
+//! Lightweight detection of obvious shell syntax mistakes, like unbalanced quotes or parentheses.
+//! This is not a full shell parser, it just catches the most common typos while editing.
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxIssueKind {
+    UnclosedSingleQuote,
+    UnclosedDoubleQuote,
+    UnclosedParen,
+    UnmatchedParen,
+    TrailingOperator,
+}
+
+impl Display for SyntaxIssueKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxIssueKind::UnclosedSingleQuote => write!(f, "unclosed '"),
+            SyntaxIssueKind::UnclosedDoubleQuote => write!(f, "unclosed \""),
+            SyntaxIssueKind::UnclosedParen => write!(f, "unclosed ("),
+            SyntaxIssueKind::UnmatchedParen => write!(f, "unmatched )"),
+            SyntaxIssueKind::TrailingOperator => write!(f, "trailing operator"),
+        }
+    }
+}
+
+/// A problematic region of the input, given as a byte range within a single line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxIssue {
+    pub kind: SyntaxIssueKind,
+    pub line: usize,
+    pub start_col: usize,
+    pub end_col: usize,
+}
+
+impl SyntaxIssue {
+    fn new(kind: SyntaxIssueKind, line: usize, start_col: usize, end_col: usize) -> SyntaxIssue {
+        SyntaxIssue {
+            kind,
+            line,
+            start_col,
+            end_col,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    SingleQuote,
+    DoubleQuote,
+    Paren,
+}
+
+/// Check the given input lines for obvious syntax problems.
+/// Lines starting with a `#` are ignored, as they are excluded from execution.
+pub fn find_syntax_issues(lines: &[String]) -> Vec<SyntaxIssue> {
+    let mut issues = Vec::new();
+    // stack of currently opened contexts, together with the position they were opened at
+    let mut open_contexts: Vec<(Context, usize, usize)> = Vec::new();
+    let mut last_token: Option<(usize, usize, usize)> = None;
+
+    for (line_nr, line) in lines.iter().enumerate() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut chars = line.char_indices().peekable();
+        let mut prev_char = None;
+        while let Some((idx, c)) = chars.next() {
+            let current = open_contexts.last().map(|(context, ..)| *context);
+            match (current, c) {
+                (Some(Context::SingleQuote), '\'') => {
+                    open_contexts.pop();
+                }
+                (Some(Context::SingleQuote), _) => {}
+                (_, '\\') => {
+                    chars.next();
+                }
+                (Some(Context::DoubleQuote), '"') => {
+                    open_contexts.pop();
+                }
+                (Some(Context::DoubleQuote), '$') if chars.peek().map(|(_, c)| *c) == Some('(') => {
+                    chars.next();
+                    open_contexts.push((Context::Paren, line_nr, idx));
+                }
+                (Some(Context::DoubleQuote), _) => {}
+                (_, '#') if prev_char.is_none_or(char::is_whitespace) => break,
+                (_, '\'') => open_contexts.push((Context::SingleQuote, line_nr, idx)),
+                (_, '"') => open_contexts.push((Context::DoubleQuote, line_nr, idx)),
+                (_, '(') => open_contexts.push((Context::Paren, line_nr, idx)),
+                (Some(Context::Paren), ')') => {
+                    open_contexts.pop();
+                }
+                (None, ')') => issues.push(SyntaxIssue::new(SyntaxIssueKind::UnmatchedParen, line_nr, idx, idx + 1)),
+                _ => {}
+            }
+
+            if open_contexts.is_empty() && !c.is_whitespace() {
+                last_token = match last_token {
+                    Some((token_line, start, end))
+                        if token_line == line_nr
+                            && end == idx
+                            && is_operator_char(c)
+                            && line[start..end].chars().all(is_operator_char) =>
+                    {
+                        Some((line_nr, start, idx + c.len_utf8()))
+                    }
+                    _ => Some((line_nr, idx, idx + c.len_utf8())),
+                };
+            }
+            prev_char = Some(c);
+        }
+    }
+
+    for (context, line_nr, idx) in open_contexts {
+        let kind = match context {
+            Context::SingleQuote => SyntaxIssueKind::UnclosedSingleQuote,
+            Context::DoubleQuote => SyntaxIssueKind::UnclosedDoubleQuote,
+            Context::Paren => SyntaxIssueKind::UnclosedParen,
+        };
+        issues.push(SyntaxIssue::new(kind, line_nr, idx, lines[line_nr].len()));
+    }
+
+    if let Some((line_nr, start, end)) = last_token {
+        let token = &lines[line_nr][start..end];
+        if token == "|" || token == "||" || token == "&&" {
+            issues.push(SyntaxIssue::new(SyntaxIssueKind::TrailingOperator, line_nr, start, end));
+        }
+    }
+
+    issues
+}
+
+fn is_operator_char(c: char) -> bool {
+    c == '|' || c == '&'
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds(line: &str) -> Vec<SyntaxIssueKind> {
+        find_syntax_issues(&[line.to_string()]).into_iter().map(|x| x.kind).collect()
+    }
+
+    #[test]
+    fn test_valid_commands() {
+        assert_eq!(kinds("echo 'a b' \"c $(d)\" | sed 's/(//'"), vec![]);
+        assert_eq!(kinds("echo it\\'s"), vec![]);
+        assert_eq!(kinds("echo $(ls (foo)) # it's fine"), vec![]);
+        assert_eq!(kinds(""), vec![]);
+    }
+
+    #[test]
+    fn test_unbalanced() {
+        assert_eq!(kinds("echo 'abc"), vec![SyntaxIssueKind::UnclosedSingleQuote]);
+        assert_eq!(kinds("echo \"abc"), vec![SyntaxIssueKind::UnclosedDoubleQuote]);
+        assert_eq!(kinds("echo $(ls"), vec![SyntaxIssueKind::UnclosedParen]);
+        assert_eq!(kinds("echo ls)"), vec![SyntaxIssueKind::UnmatchedParen]);
+        assert_eq!(
+            find_syntax_issues(&["echo 'abc".to_string()]),
+            vec![SyntaxIssue::new(SyntaxIssueKind::UnclosedSingleQuote, 0, 5, 9)]
+        );
+    }
+
+    #[test]
+    fn test_trailing_operator() {
+        assert_eq!(kinds("ls |"), vec![SyntaxIssueKind::TrailingOperator]);
+        assert_eq!(kinds("ls && "), vec![SyntaxIssueKind::TrailingOperator]);
+        assert_eq!(kinds("ls | wc"), vec![]);
+        assert_eq!(kinds("ls|"), vec![SyntaxIssueKind::TrailingOperator]);
+        assert_eq!(
+            find_syntax_issues(&["ls ||".to_string(), "# comment".to_string()]),
+            vec![SyntaxIssue::new(SyntaxIssueKind::TrailingOperator, 0, 3, 5)]
+        );
+    }
+
+    #[test]
+    fn test_multiline() {
+        let lines = vec!["echo 'a".to_string(), "b'".to_string()];
+        assert_eq!(find_syntax_issues(&lines), vec![]);
+    }
+}
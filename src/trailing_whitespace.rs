@@ -0,0 +1,151 @@
+//! Trimming trailing whitespace from commands, without touching whitespace the shell would keep.
+
+/// What the end of a line belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineEnd {
+    /// plain command text, where trailing whitespace doesn't change anything
+    Command,
+    /// a quoted string or a heredoc body, continuing on the next line
+    Literal,
+}
+
+/// Remove trailing whitespace from every line and drop trailing empty lines.
+/// Lines ending within a quoted string, heredoc bodies and an escaped trailing space (`\ `) are kept as they are.
+pub fn trim_trailing_whitespace(lines: &[String]) -> Vec<String> {
+    let mut trimmed = Vec::with_capacity(lines.len());
+    let mut line_ends = Vec::with_capacity(lines.len());
+    let mut in_single_quotes = false;
+    let mut in_double_quotes = false;
+    // delimiters of heredocs whose body has not ended yet, together with whether leading tabs are stripped (`<<-`)
+    let mut heredocs: Vec<(String, bool)> = Vec::new();
+    let mut heredoc_body_started = false;
+
+    for line in lines {
+        if heredoc_body_started && let Some((delimiter, strip_tabs)) = heredocs.first() {
+            let candidate = if *strip_tabs {
+                line.trim_start_matches('\t')
+            } else {
+                line.as_str()
+            };
+            if candidate == delimiter {
+                heredocs.remove(0);
+            }
+            heredoc_body_started = !heredocs.is_empty();
+            trimmed.push(line.clone());
+            line_ends.push(LineEnd::Literal);
+            continue;
+        }
+
+        let mut chars = line.char_indices().peekable();
+        let mut prev_char = None;
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '\'' if !in_double_quotes => in_single_quotes = !in_single_quotes,
+                _ if in_single_quotes => {}
+                '\\' => {
+                    chars.next();
+                }
+                '"' => in_double_quotes = !in_double_quotes,
+                _ if in_double_quotes => {}
+                '#' if prev_char.is_none_or(char::is_whitespace) => break,
+                '<' if line[idx..].starts_with("<<") && !line[idx..].starts_with("<<<") => {
+                    chars.next();
+                    let strip_tabs = chars.next_if(|(_, c)| *c == '-').is_some();
+                    while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+                    let mut delimiter = String::new();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| !c.is_whitespace() && !";&|<>()".contains(*c)) {
+                        if !"'\"\\".contains(c) {
+                            delimiter.push(c);
+                        }
+                    }
+                    if !delimiter.is_empty() {
+                        heredocs.push((delimiter, strip_tabs));
+                    }
+                }
+                _ => {}
+            }
+            prev_char = Some(c);
+        }
+        heredoc_body_started = !heredocs.is_empty();
+
+        if in_single_quotes || in_double_quotes {
+            trimmed.push(line.clone());
+            line_ends.push(LineEnd::Literal);
+        } else {
+            let mut end = line.trim_end().len();
+            let escaping_backslashes = line[..end].chars().rev().take_while(|c| *c == '\\').count();
+            if escaping_backslashes % 2 == 1 {
+                end += line[end..].chars().next().map_or(0, char::len_utf8);
+            }
+            trimmed.push(line[..end].to_string());
+            line_ends.push(LineEnd::Command);
+        }
+    }
+
+    while trimmed.last().is_some_and(String::is_empty) && line_ends.last() == Some(&LineEnd::Command) {
+        trimmed.pop();
+        line_ends.pop();
+    }
+    trimmed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_trim_trailing_whitespace() {
+        assert_eq!(
+            trim_trailing_whitespace(&lines(&["ls -l  ", "  | wc -l\t", "", "  "])),
+            lines(&["ls -l", "  | wc -l"])
+        );
+        assert_eq!(
+            trim_trailing_whitespace(&lines(&["echo a\\  ", "echo 'b ' \"c \"  "])),
+            lines(&["echo a\\ ", "echo 'b ' \"c \""])
+        );
+        assert_eq!(
+            trim_trailing_whitespace(&lines(&["echo a  # it's  "])),
+            lines(&["echo a  # it's"])
+        );
+    }
+
+    #[test]
+    fn test_trim_keeps_quoted_trailing_whitespace() {
+        assert_eq!(
+            trim_trailing_whitespace(&lines(&["echo \"a  ", "b\"  "])),
+            lines(&["echo \"a  ", "b\""])
+        );
+        assert_eq!(
+            trim_trailing_whitespace(&lines(&["echo 'a \" ", "  ", "b'"])),
+            lines(&["echo 'a \" ", "  ", "b'"])
+        );
+        assert_eq!(
+            trim_trailing_whitespace(&lines(&["echo \"a  ", ""])),
+            lines(&["echo \"a  ", ""])
+        );
+    }
+
+    #[test]
+    fn test_trim_keeps_heredoc_bodies() {
+        let heredoc = lines(&[
+            "cat <<'EOF' | grep x  ",
+            "keep  ",
+            "",
+            "EOF",
+            "cat <<-END ",
+            "\tkeep ",
+            "\tEND",
+            "",
+            " ",
+        ]);
+        assert_eq!(
+            trim_trailing_whitespace(&heredoc),
+            lines(&["cat <<'EOF' | grep x", "keep  ", "", "EOF", "cat <<-END", "\tkeep ", "\tEND"])
+        );
+        assert_eq!(trim_trailing_whitespace(&lines(&["cat <<< 'x'  "])), lines(&["cat <<< 'x'"]));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::app::cheatsheet_window::CheatsheetState;
+use crate::cheatsheet::{command, description};
+use ratatui::{
+    Frame,
+    layout::{
+        Constraint::{Length, Min, Percentage},
+        Direction, Layout, Rect,
+    },
+    style::{Modifier, Style},
+    text::{Line, Span},
+    widgets::{List, ListItem, ListState, Paragraph},
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::ui::{highlight_shell_lines, make_default_block};
+
+/// Draw the search field, the matching cheatsheet entries and a preview of the selected command.
+///
+/// Returns the area of the list, the index of its first visible entry and the cursor position in the search field.
+pub fn draw_cheatsheet(
+    f: &mut Frame,
+    rect: Rect,
+    highlighting_enabled: bool,
+    scrolloff: usize,
+    state: &CheatsheetState,
+) -> (Rect, usize, (u16, u16)) {
+    let [search_chunk, list_chunk, preview_chunk] = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Length(3), Percentage(70), Min(0)])
+        .areas(rect);
+
+    f.render_widget(
+        Paragraph::new(state.query.as_str()).block(make_default_block("Search cheatsheet", true)),
+        search_chunk,
+    );
+
+    let matching_entries = state.matching_entries();
+    let items = matching_entries
+        .iter()
+        .map(|entry| {
+            ListItem::new(Line::from(vec![
+                Span::raw(command(entry).replace("||", "").replace('\n', " ↵ ")),
+                Span::styled(
+                    format!("  # {}", description(entry)),
+                    Style::default().add_modifier(Modifier::DIM),
+                ),
+            ]))
+        })
+        .collect::<Vec<_>>();
+
+    let mut list_state = ListState::default();
+    list_state.select(state.selected_idx);
+    let list_widget = List::new(items)
+        .block(make_default_block("Cheatsheet", false))
+        .highlight_style(Style::default().add_modifier(Modifier::ITALIC))
+        .highlight_symbol(">>")
+        .scroll_padding(scrolloff);
+    f.render_stateful_widget(list_widget, list_chunk, &mut list_state);
+
+    if let Some(selected_entry) = state.selected_entry() {
+        let command_lines = command(selected_entry)
+            .replace("||", "")
+            .lines()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        let mut preview_lines = if highlighting_enabled {
+            highlight_shell_lines(&command_lines)
+        } else {
+            command_lines.iter().map(|line| Line::raw(line.as_str())).collect()
+        };
+        preview_lines.push(Line::raw(""));
+        preview_lines.push(Line::raw(description(selected_entry)));
+        f.render_widget(
+            Paragraph::new(preview_lines).block(make_default_block("Preview", false)),
+            preview_chunk,
+        );
+    }
+
+    let cursor = (search_chunk.x + 1 + state.query.width() as u16, search_chunk.y + 1);
+    (list_chunk, list_state.offset(), cursor)
+}
@@ -1,16 +1,29 @@
 use crate::app::command_list_window::CommandListState;
+use ansi_to_tui::IntoText;
 use ratatui::{
+    Frame,
     layout::{Constraint, Direction, Layout, Rect},
-    text::Span,
+    text::{Span, Text},
     widgets::{List, ListItem, ListState, Paragraph},
-    Frame,
 };
 
-use crate::ui::make_default_block;
+use crate::pipr_config::PiprConfig;
+use crate::ui::{highlight_shell_lines, make_default_block};
 
 /// Draw the command list UI (used for both bookmarks and history)
-pub fn draw_command_list(f: &mut Frame, rect: Rect, always_show_preview: bool, state: &CommandListState, title: &str) {
-    let show_preview = always_show_preview || state.selected_entry().map(|e| e.lines().len() > 1) == Some(true);
+///
+/// Returns the area of the list and the index of its first visible entry.
+pub fn draw_command_list(
+    f: &mut Frame,
+    rect: Rect,
+    always_show_preview: bool,
+    config: &PiprConfig,
+    state: &CommandListState,
+    title: &str,
+    selected: bool,
+) -> (Rect, usize) {
+    let show_preview =
+        always_show_preview || state.selected_entry().map(|e| e.lines().len() > 1 || e.output().is_some()) == Some(true);
 
     let [list_chunk, preview_chunk] = Layout::default()
         .direction(Direction::Vertical)
@@ -24,30 +37,69 @@ pub fn draw_command_list(f: &mut Frame, rect: Rect, always_show_preview: bool, s
         .areas(rect);
 
     let items = state
-        .list
-        .iter()
-        .map(|entry| entry.as_string().replace("\n", " ↵ "))
+        .displayed_entries()
+        .into_iter()
+        .map(|entry| {
+            let mut item = entry.as_string().replace("\n", " ↵ ");
+            if let Some(mode) = entry.execution_mode() {
+                item.push_str(&format!(" [{}]", mode));
+            }
+            if entry.output().is_some() {
+                item.push_str(" [output]");
+            }
+            item
+        })
         .map(|entry| ListItem::new(Span::raw(entry)))
         .collect::<Vec<_>>();
 
+    let title = match state.sort_order.label() {
+        Some(label) => format!("{} ({})", title, label),
+        None => title.to_string(),
+    };
     let mut list_state = ListState::default();
     list_state.select(state.selected_idx);
 
     use ratatui::style::{Modifier, Style};
 
     let list_widget = List::new(items)
-        .block(make_default_block(title, true))
+        .block(make_default_block(&title, selected))
         .highlight_style(Style::default().add_modifier(Modifier::ITALIC))
-        .highlight_symbol(">>");
+        .highlight_symbol(">>")
+        .scroll_padding(config.scrolloff);
 
     f.render_stateful_widget(list_widget, list_chunk, &mut list_state);
 
-    if show_preview {
-        if let Some(selected_content) = state.selected_entry() {
+    if show_preview && let Some(selected_content) = state.selected_entry() {
+        let preview_text = if config.highlighting_enabled {
+            Text::from(highlight_shell_lines(selected_content.lines()))
+        } else {
+            Text::from(selected_content.as_string())
+        };
+        let output = selected_content.output();
+        // the command gets as much room as it needs, up to half of the preview
+        let [command_chunk, output_chunk] = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(if output.is_some() {
+                [
+                    Constraint::Max(selected_content.lines().len() as u16 + 2),
+                    Constraint::Min(preview_chunk.height / 2),
+                ]
+            } else {
+                [Constraint::Percentage(100), Constraint::Percentage(0)]
+            })
+            .areas(preview_chunk);
+        f.render_widget(
+            Paragraph::new(preview_text).block(make_default_block("Preview", false)),
+            command_chunk,
+        );
+        if let Some(output) = output {
+            let output_text = output.into_text().unwrap_or_else(|_| Text::raw(output.clone()));
             f.render_widget(
-                Paragraph::new(selected_content.as_string().as_str()).block(make_default_block("Preview", false)),
-                preview_chunk,
+                Paragraph::new(output_text).block(make_default_block("Saved output", false)),
+                output_chunk,
             );
         }
     }
+
+    (list_chunk, list_state.offset())
 }
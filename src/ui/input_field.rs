@@ -1,62 +1,144 @@
 use crate::app::App;
+use crate::command_evaluation::{ExecutionMode, StdinSource};
 use itertools::Itertools;
 use ratatui::{
+    Frame,
     layout::Rect,
+    style::{Color, Modifier, Style},
     text::{Line, Span, Text},
     widgets::Paragraph,
-    Frame,
 };
-use syntect::easy::HighlightLines;
-use syntect::util::LinesWithEndings;
 
-use super::SH_SYNTAX;
-use super::SYNTAX_SET;
-use super::THEME;
-use crate::ui::highlight_style_to_ratatui_style;
-use crate::ui::{make_default_block, truncate_with_ellipsis};
+use crate::syntax_check::find_syntax_issues;
+use crate::ui::{expand_tabs, highlight_shell_lines, line_number_gutter_width, prepend_line_numbers};
+use crate::ui::{make_default_block, skip_line_columns, truncate_line_with_ellipsis};
+
+/// Columns taken up by the `...` marking cut off text
+const ELLIPSIS_WIDTH: usize = 3;
+
+/// Width taken up by the line number gutter in the input field, 0 if line numbers are disabled
+pub fn input_gutter_width(app: &App) -> usize {
+    if app.config.show_line_numbers {
+        line_number_gutter_width(app.input_state.content_lines().len())
+    } else {
+        0
+    }
+}
 
 /// Draw the input field for commands
 pub fn draw_input_field(f: &mut Frame, rect: Rect, app: &mut App) {
-    let mut highlighter = HighlightLines::new(*SH_SYNTAX, &THEME);
+    let gutter_width = input_gutter_width(app);
 
-    // Cut off lines at the input field width, adding ...
-    let lines: Vec<String> = app
-        .input_state
-        .content_lines()
-        .iter()
-        .map(|line| truncate_with_ellipsis(line.clone(), rect.width as usize))
-        .collect_vec();
+    let lines = app.input_state.content_lines();
 
-    let joined_lines = lines.join("\n");
     let styled_lines = if app.config.highlighting_enabled {
-        LinesWithEndings::from(joined_lines.as_ref())
-            .map(|line| {
-                let Ok(result) = highlighter.highlight_line(line, &SYNTAX_SET) else {
-                    return vec![Span::raw(line)];
-                };
-                result
-                    .iter()
-                    .map(|(style, part)| Span::styled(*part, highlight_style_to_ratatui_style(style)))
-                    .collect_vec()
-            })
-            .map(Line::from)
-            .collect_vec()
+        highlight_shell_lines(lines)
     } else {
-        lines.iter().map(Span::raw).map(Line::from).collect_vec()
+        lines.iter().map(|line| Line::raw(line.as_str())).collect_vec()
     };
 
+    // mark obvious syntax errors. These are only hints, the command can still be executed.
+    let syntax_issues = find_syntax_issues(app.input_state.content_lines());
+    let issue_style = Style::default().fg(Color::Red).add_modifier(Modifier::UNDERLINED);
+    let mut styled_lines = styled_lines;
+    for issue in &syntax_issues {
+        if let Some(line) = styled_lines.get_mut(issue.line) {
+            *line = style_line_range(std::mem::take(line), issue.start_col, issue.end_col, issue_style);
+        }
+    }
+    let selection_style = Style::default().add_modifier(Modifier::REVERSED);
+    for (line_idx, start, end) in app.input_state.selected_ranges() {
+        if let Some(line) = styled_lines.get_mut(line_idx) {
+            *line = style_line_range(std::mem::take(line), start, end, selection_style);
+        }
+    }
+    // done after the syntax issues and selection were marked, as their positions are byte offsets into the original content
+    let tab_marker = app.show_whitespace.then(|| Style::default().fg(Color::DarkGray));
+    if app.show_whitespace {
+        styled_lines = styled_lines.into_iter().map(show_whitespace).collect();
+    }
+    // expanding tabs moves the following text, so this also has to happen after marking byte offsets
+    styled_lines = styled_lines
+        .into_iter()
+        .map(|line| expand_tabs(line, app.config.tab_width, tab_marker))
+        .collect();
+    // Cut off lines at the input field width, adding ...
+    // The line with the cursor is scrolled horizontally if needed, so the cursor stays visible.
+    let text_width = (rect.width as usize).saturating_sub(gutter_width + 2);
+    let cursor_line = app.input_state.cursor_line;
+    let scroll = input_scroll_offset(
+        app.input_state.displayed_cursor_column(),
+        styled_lines[cursor_line].width(),
+        text_width,
+    );
+    app.layout.input_scroll = (cursor_line, scroll);
+    styled_lines = styled_lines
+        .into_iter()
+        .enumerate()
+        .map(|(idx, line)| {
+            let line = if idx == cursor_line && scroll > 0 {
+                let mut scrolled = skip_line_columns(line, scroll + ELLIPSIS_WIDTH);
+                scrolled.spans.insert(0, Span::raw("..."));
+                scrolled
+            } else {
+                line
+            };
+            truncate_line_with_ellipsis(line, text_width).0
+        })
+        .collect();
+    if gutter_width > 0 {
+        prepend_line_numbers(&mut styled_lines, gutter_width);
+    }
+
     let is_bookmarked = app.bookmarks.entries().contains(&app.input_state.content_to_commandentry());
 
     // Create descriptive title showing current modes
     let input_block_title = format!(
-        "Command{}{}{}{}",
+        "Command{}{}{}{}{}{}{}{}{}{}{}{}{}",
+        match &app.config.profile {
+            Some(profile) => format!(" [Profile: {}]", profile),
+            None => String::new(),
+        },
+        if app.config.incognito { " [Incognito]" } else { "" },
         if is_bookmarked { " [Bookmarked]" } else { "" },
-        if app.autoeval_mode { " [Autoeval]" } else { "" },
+        if app.elevated {
+            format!(" [{}]", app.config.elevation_command)
+        } else {
+            String::new()
+        },
+        if app.autoeval_mode && !app.persistent_session {
+            " [Autoeval]"
+        } else {
+            ""
+        },
+        if app.pty_mode { " [PTY]" } else { "" },
+        if app.persistent_session { " [Session]" } else { "" },
+        match app.execution_mode() {
+            ExecutionMode::Unsafe => " [Unsafe]",
+            ExecutionMode::Isolated if app.entry_execution_mode.is_some() => " [Isolated]",
+            ExecutionMode::Isolated => "",
+        },
+        match app.watch_interval {
+            Some(interval) => format!(" [Watch {}s]", interval.as_secs()),
+            None => String::new(),
+        },
         if app.cached_command_part.is_some() { " [Caching]" } else { "" },
         if app.autoeval_mode && app.paranoid_history_mode {
             " [Paranoid]"
         } else {
             ""
+        },
+        match &app.primed_stdin {
+            Some(StdinSource::Lines(lines)) => match app.primed_stdin_stream {
+                Some(stream) => format!(" [Stdin: {} lines of {}]", lines.len(), stream.name()),
+                None => format!(" [Stdin: {} edited lines]", lines.len()),
+            },
+            Some(StdinSource::File(path) | StdinSource::Fifo(path)) => format!(" [Stdin: {}]", path.display()),
+            None => String::new(),
+        },
+        match syntax_issues.first() {
+            Some(issue) => format!(" [{}]", issue.kind),
+            None => String::new(),
         }
     );
 
@@ -65,3 +147,70 @@ pub fn draw_input_field(f: &mut Frame, rect: Rect, app: &mut App) {
         rect,
     );
 }
+
+/// Number of columns the line with the cursor has to be scrolled by,
+/// so the cursor at `cursor_col` is visible within `width` columns.
+/// The first columns of a scrolled line are replaced with `...`, and the cursor is kept in front of the `...` at the end.
+fn input_scroll_offset(cursor_col: usize, line_width: usize, width: usize) -> usize {
+    let visible_width = if line_width > width {
+        width.saturating_sub(ELLIPSIS_WIDTH)
+    } else {
+        width
+    };
+    // scrolling needs room for both ellipses and the cursor
+    if cursor_col < visible_width || width <= 2 * ELLIPSIS_WIDTH {
+        return 0;
+    }
+    cursor_col + ELLIPSIS_WIDTH + 1 - width
+}
+
+/// Render spaces as `·`. Each marker is exactly as wide as the space it replaces,
+/// so the cursor position stays correct. Tabs are marked when they are expanded.
+fn show_whitespace(line: Line<'_>) -> Line<'static> {
+    let marker_style = Style::default().fg(Color::DarkGray);
+    let mut new_spans = Vec::new();
+    for span in line.spans {
+        let mut text = String::new();
+        for c in span.content.chars() {
+            if c != ' ' {
+                text.push(c);
+                continue;
+            }
+            if !text.is_empty() {
+                new_spans.push(Span::styled(std::mem::take(&mut text), span.style));
+            }
+            new_spans.push(Span::styled("·", span.style.patch(marker_style)));
+        }
+        if !text.is_empty() {
+            new_spans.push(Span::styled(text, span.style));
+        }
+    }
+    Line::from(new_spans)
+}
+
+/// Patch the given style onto the byte range `start..end` of a line, splitting spans where necessary.
+fn style_line_range(line: Line<'_>, start: usize, end: usize, style: Style) -> Line<'static> {
+    let mut new_spans = Vec::new();
+    let mut offset = 0;
+    for span in line.spans {
+        let content = span.content.as_ref();
+        let span_end = offset + content.len();
+        let local_start = start.clamp(offset, span_end) - offset;
+        let local_end = end.clamp(offset, span_end) - offset;
+        if local_start >= local_end || !content.is_char_boundary(local_start) || !content.is_char_boundary(local_end) {
+            new_spans.push(Span::styled(content.to_string(), span.style));
+        } else {
+            for (part, part_style) in [
+                (&content[..local_start], span.style),
+                (&content[local_start..local_end], span.style.patch(style)),
+                (&content[local_end..], span.style),
+            ] {
+                if !part.is_empty() {
+                    new_spans.push(Span::styled(part.to_string(), part_style));
+                }
+            }
+        }
+        offset = span_end;
+    }
+    Line::from(new_spans)
+}
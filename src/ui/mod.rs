@@ -1,37 +1,75 @@
 use crate::app::{App, WindowState};
+use crate::util::truncate_to_width;
 
+use cheatsheet::draw_cheatsheet;
 use command_list::draw_command_list;
 use crossterm::{
+    event::{DisableMouseCapture, EnableMouseCapture},
     execute,
-    terminal::{EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
 use input_field::draw_input_field;
 use outputs::draw_outputs;
 use ratatui::{
+    Terminal,
     backend::Backend,
     style::{Color, Style},
-    text::Span,
+    text::{Line, Span},
     widgets::{Block, Borders},
-    Terminal,
 };
 use std::io::{self, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicBool;
 use syntect::{
+    easy::HighlightLines,
     highlighting::{self, ThemeSet},
     parsing::{SyntaxReference, SyntaxSet},
 };
+use unicode_width::UnicodeWidthStr;
 
+pub mod cheatsheet;
 pub mod command_list;
 pub mod input_field;
 pub mod outputs;
 
 lazy_static::lazy_static! {
     pub static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
-    pub static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
     pub static ref THEME: &'static syntect::highlighting::Theme = THEME_SET.themes.get("base16-ocean.dark").unwrap();
-    pub static ref SH_SYNTAX: &'static SyntaxReference = SYNTAX_SET.find_syntax_by_extension("sh").unwrap();
-    pub static ref PLAINTEXT_SYNTAX: &'static SyntaxReference = SYNTAX_SET.find_syntax_plain_text();
+    pub static ref SH_SYNTAX: &'static SyntaxReference = syntax_set().find_syntax_by_extension("sh").unwrap();
+    pub static ref JSON_SYNTAX: &'static SyntaxReference = syntax_set().find_syntax_by_extension("json").unwrap();
+    pub static ref PLAINTEXT_SYNTAX: &'static SyntaxReference = syntax_set().find_syntax_plain_text();
+}
+
+static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+
+/// The syntaxes used for highlighting: the syntect defaults, plus those loaded by [`load_syntax_dir`].
+pub fn syntax_set() -> &'static SyntaxSet {
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Use the `.sublime-syntax` files in `dir` in addition to the default syntaxes.
+/// Has to be called before anything is highlighted.
+pub fn load_syntax_dir(dir: &Path) -> anyhow::Result<()> {
+    let syntax_set = build_syntax_set(dir)?;
+    SYNTAX_SET
+        .set(syntax_set)
+        .map_err(|_| anyhow::anyhow!("Syntaxes were already loaded"))
+}
+
+/// The default syntaxes merged with the ones in `dir`, which take precedence for the same file extension.
+fn build_syntax_set(dir: &Path) -> anyhow::Result<SyntaxSet> {
+    let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+    builder
+        .add_from_folder(dir, true)
+        // the error's source repeats its message, so it is not kept as context
+        .map_err(|err| anyhow::anyhow!("Failed to load the syntax definitions in {}: {}", dir.display(), err))?;
+    Ok(builder.build())
 }
 
+/// Set while another program runs in the terminal, so pipr doesn't read the input meant for it
+pub static PAUSE_EVENT_READER: AtomicBool = AtomicBool::new(false);
+
 /// Draw the application UI
 ///
 /// This is the main entry point for rendering the UI.
@@ -40,6 +78,12 @@ pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> anyhow
     // Handle command execution that jumps to other programs (like man pages)
     if let Some((stdin_content, mut should_jump_to_other_cmd)) = app.should_jump_to_other_cmd.take() {
         execute!(io::stdout(), LeaveAlternateScreen)?;
+        if app.config.mouse_support {
+            execute!(io::stdout(), DisableMouseCapture)?;
+        }
+        // programs like sudo read their input line by line
+        disable_raw_mode()?;
+        PAUSE_EVENT_READER.store(true, std::sync::atomic::Ordering::SeqCst);
         let mut child = should_jump_to_other_cmd.env("MAN_POSIXLY_CORRECT", "1").spawn()?;
         if let Some(stdin_content) = stdin_content {
             let _ = child
@@ -48,17 +92,23 @@ pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> anyhow
                 .expect("Command given to should_jump_to_other_cmd did not provide stdin pipe")
                 .write_all(stdin_content.as_bytes());
         }
-        child.wait()?;
+        let status = child.wait()?;
+        PAUSE_EVENT_READER.store(false, std::sync::atomic::Ordering::SeqCst);
+        enable_raw_mode()?;
         execute!(io::stdout(), EnterAlternateScreen)?;
+        if app.config.mouse_support {
+            execute!(io::stdout(), EnableMouseCapture)?;
+        }
         let size = terminal.size()?;
         let rect = ratatui::layout::Rect::new(0, 0, size.width, size.height);
         terminal.resize(rect)?; // this will redraw the whole screen
+        app.on_other_cmd_exit(status.success());
     }
 
     let mut input_field_rect = ratatui::layout::Rect::new(0, 0, 0, 0);
     terminal.draw(|f| {
         let root_rect = f.area();
-        let root_rect = ratatui::layout::Rect::new(1, 1, root_rect.width - 2, root_rect.height - 2);
+        let root_rect = ratatui::layout::Rect::new(1, 1, root_rect.width.saturating_sub(2), root_rect.height.saturating_sub(2));
 
         match &app.window_state {
             WindowState::Main => {
@@ -69,7 +119,11 @@ pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> anyhow
                     .direction(Direction::Horizontal)
                     .constraints(
                         [
-                            Percentage(if app.opened_key_select_menu.is_some() { 40 } else { 0 }),
+                            Percentage(match (&app.opened_key_select_menu, &app.bookmark_sidebar) {
+                                (Some(_), _) => 40,
+                                (None, Some(_)) => app.config.bookmark_sidebar_width,
+                                (None, None) => 0,
+                            }),
                             Percentage(100),
                         ]
                         .as_ref(),
@@ -81,7 +135,10 @@ pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> anyhow
                     .direction(Direction::Vertical)
                     .constraints(
                         [
+                            Length(if app.context_line.is_some() { 1 } else { 0 }),
                             Length(2 + app.input_state.content_lines().len() as u16),
+                            Length(if app.script_export_path.is_some() { 3 } else { 0 }),
+                            Length(if app.config.favorites.is_empty() { 0 } else { 1 }),
                             Length(if let Some(state) = &app.autocomplete_state {
                                 (state.options.len().min(5) + 2) as u16
                             } else {
@@ -96,21 +153,80 @@ pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> anyhow
                 // Render key select menu if open
                 if let Some(opened_key_select_menu) = &app.opened_key_select_menu {
                     use ratatui::text::Span;
-                    use ratatui::widgets::{List, ListItem};
+                    use ratatui::widgets::{List, ListItem, Paragraph, Wrap};
+
+                    // long options are cut off, the full text of the highlighted one is shown below the options
+                    let max_width = root_chunks[0].width.saturating_sub(2) as usize;
+                    let selected = opened_key_select_menu
+                        .selected_option()
+                        .map(|(c, text)| format!("{}: {}", c, text));
+                    let preview = selected.filter(|option| option.width() > max_width);
+                    let preview_height = match &preview {
+                        Some(option) => (option.width().div_ceil(max_width.max(1)) + 2).min(root_chunks[0].height as usize / 2),
+                        None => 0,
+                    };
+                    let [menu_chunk, preview_chunk] = Layout::default()
+                        .direction(Direction::Vertical)
+                        .constraints([Min(0), Length(preview_height as u16)])
+                        .areas(root_chunks[0]);
 
-                    let options = opened_key_select_menu
-                        .option_list_strings()
-                        .map(|opt| ListItem::new(Span::raw(opt)))
-                        .collect::<Vec<_>>();
+                    let heading = opened_key_select_menu
+                        .heading
+                        .iter()
+                        .map(|line| ListItem::new(Span::raw(truncate_with_ellipsis(line.clone(), max_width))));
+                    let separator = (!opened_key_select_menu.heading.is_empty()).then(|| ListItem::new(Span::raw("")));
+                    let options = opened_key_select_menu.option_list_strings().enumerate().map(|(idx, option)| {
+                        let item = ListItem::new(Span::raw(truncate_with_ellipsis(option, max_width)));
+                        if opened_key_select_menu.selected_idx == Some(idx) {
+                            item.style(Style::default().fg(Color::Black).bg(Color::White))
+                        } else {
+                            item
+                        }
+                    });
+                    let items = heading.chain(separator).chain(options).collect::<Vec<_>>();
 
-                    f.render_widget(List::new(options).block(make_default_block("Open in", false)), root_chunks[0]);
+                    f.render_widget(
+                        List::new(items).block(make_default_block(opened_key_select_menu.title, false)),
+                        menu_chunk,
+                    );
+                    if let Some(option) = preview {
+                        f.render_widget(
+                            Paragraph::new(option)
+                                .wrap(Wrap { trim: false })
+                                .block(make_default_block("Selected", false)),
+                            preview_chunk,
+                        );
+                    }
+                } else if let Some(sidebar) = &app.bookmark_sidebar {
+                    draw_command_list(
+                        f,
+                        root_chunks[0],
+                        false,
+                        &app.config,
+                        &sidebar.list_state(&app.bookmarks),
+                        "Bookmarks",
+                        sidebar.focused,
+                    );
                 }
 
                 // Save input field rect for cursor positioning
-                input_field_rect = exec_chunks[0];
+                input_field_rect = exec_chunks[1];
 
                 // Draw the main components
                 draw_input_field(f, input_field_rect, app);
+                draw_favorites_bar(f, exec_chunks[3], app);
+                draw_context_line(f, exec_chunks[0], app);
+                let mut export_cursor = None;
+                if let Some(path) = &app.script_export_path {
+                    use ratatui::widgets::Paragraph;
+                    use unicode_width::UnicodeWidthStr;
+
+                    f.render_widget(
+                        Paragraph::new(path.as_str()).block(make_default_block("Export script to", true)),
+                        exec_chunks[2],
+                    );
+                    export_cursor = Some((exec_chunks[2].x + 1 + path.width() as u16, exec_chunks[2].y + 1));
+                }
 
                 // Draw autocomplete suggestions if available
                 if let Some(autocomplete_state) = &app.autocomplete_state {
@@ -128,24 +244,34 @@ pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> anyhow
                             .collect::<Vec<_>>(),
                     )
                     .highlight_style(Style::default().fg(Color::Black).bg(Color::White))
-                    .block(make_default_block("Suggestions", false));
-                    f.render_stateful_widget(list_widget, exec_chunks[1], &mut list_state);
+                    .block(make_default_block("Suggestions", false))
+                    .scroll_padding(app.config.scrolloff);
+                    f.render_stateful_widget(list_widget, exec_chunks[4], &mut list_state);
+                    app.layout.autocomplete = exec_chunks[4];
+                    app.layout.autocomplete_offset = list_state.offset();
                 }
 
                 // Draw command outputs
-                draw_outputs(
-                    f,
-                    exec_chunks[2],
-                    app.input_state.content_str() == app.last_executed_cmd,
-                    app.is_processing_state,
-                    &app.command_output,
-                    &app.command_error,
-                );
+                app.output_width = exec_chunks[5].width.saturating_sub(2);
+                app.layout.output = exec_chunks[5];
+                if std::mem::take(&mut app.resized) {
+                    app.fit_output_scroll();
+                }
+                let filter_cursor = draw_outputs(f, exec_chunks[5], app);
 
                 // Position cursor at current editing position
-                let cursor_x = input_field_rect.x + 1 + app.input_state.displayed_cursor_column() as u16;
+                let gutter_width = input_field::input_gutter_width(app) as u16;
+                app.layout.input_text = ratatui::layout::Rect::new(
+                    input_field_rect.x + 1 + gutter_width,
+                    input_field_rect.y + 1,
+                    input_field_rect.width.saturating_sub(2 + gutter_width),
+                    input_field_rect.height.saturating_sub(2),
+                );
+                let (_, input_scroll) = app.layout.input_scroll;
+                let cursor_x =
+                    input_field_rect.x + 1 + gutter_width + (app.input_state.displayed_cursor_column() - input_scroll) as u16;
                 let cursor_y = input_field_rect.y + 1 + app.input_state.cursor_line as u16;
-                f.set_cursor_position((cursor_x, cursor_y));
+                f.set_cursor_position(export_cursor.or(filter_cursor).unwrap_or((cursor_x, cursor_y)));
             }
             WindowState::TextView(title, text) => {
                 use ratatui::widgets::Paragraph;
@@ -157,26 +283,156 @@ pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> anyhow
             }
             WindowState::BookmarkList(listview_state) => {
                 let always_show_preview = app.config.cmdlist_always_show_preview;
-                draw_command_list(f, root_rect, always_show_preview, listview_state, "Bookmarks");
+                (app.layout.command_list, app.layout.command_list_offset) = draw_command_list(
+                    f,
+                    root_rect,
+                    always_show_preview,
+                    &app.config,
+                    listview_state,
+                    "Bookmarks",
+                    true,
+                );
             }
             WindowState::HistoryList(listview_state) => {
                 let always_show_preview = app.config.cmdlist_always_show_preview;
-                draw_command_list(f, root_rect, always_show_preview, listview_state, "History");
+                let title = match app.history.max_size() {
+                    Some(max_size) => format!("History {}/{}", listview_state.list.len(), max_size),
+                    None => "History".to_string(),
+                };
+                (app.layout.command_list, app.layout.command_list_offset) =
+                    draw_command_list(f, root_rect, always_show_preview, &app.config, listview_state, &title, true);
+            }
+            WindowState::Scratch(scratch) => {
+                use ratatui::widgets::Paragraph;
+
+                let visible_lines = root_rect.height.saturating_sub(2) as usize;
+                let scroll = (scratch.cursor_line + 1).saturating_sub(visible_lines);
+                f.render_widget(
+                    Paragraph::new(scratch.content_lines().join("\n"))
+                        .block(make_default_block("Scratch", true))
+                        .scroll((scroll as u16, 0)),
+                    root_rect,
+                );
+                f.set_cursor_position((
+                    root_rect.x + 1 + scratch.displayed_cursor_column() as u16,
+                    root_rect.y + 1 + (scratch.cursor_line - scroll) as u16,
+                ));
+            }
+            WindowState::Cheatsheet(cheatsheet_state) => {
+                let cursor;
+                (app.layout.command_list, app.layout.command_list_offset, cursor) = draw_cheatsheet(
+                    f,
+                    root_rect,
+                    app.config.highlighting_enabled,
+                    app.config.scrolloff,
+                    cheatsheet_state,
+                );
+                f.set_cursor_position(cursor);
             }
         }
 
-        // Help message always stays in the bottom right
+        // The status bar with the help message always stays in the bottom right
         use ratatui::widgets::Paragraph;
 
+        let mut status = status_bar(app);
+        if status.width() > (root_rect.width / 2) as usize {
+            status = Line::from("Help: F1");
+        }
+        let status_width = status.width() as u16 + 2;
+
+        let left_width = root_rect.width.saturating_sub(status_width + 2);
+        if let Some(notification) = &app.notification {
+            f.render_widget(
+                Paragraph::new(Span::styled(
+                    format!(" {} ", notification),
+                    Style::default().fg(Color::Yellow),
+                )),
+                ratatui::layout::Rect::new(root_rect.x + 1, root_rect.height, left_width, 1),
+            );
+        } else if let Some(hints) = key_hints(app.window_state.key_hints(), left_width as usize) {
+            f.render_widget(
+                Paragraph::new(Span::styled(hints, Style::default().fg(Color::DarkGray))),
+                ratatui::layout::Rect::new(root_rect.x + 1, root_rect.height, left_width, 1),
+            );
+        }
+
         f.render_widget(
-            Paragraph::new("Help: F1"),
-            ratatui::layout::Rect::new(root_rect.width - 10_u16, root_rect.height, 10, 1),
+            Paragraph::new(status),
+            ratatui::layout::Rect::new(
+                root_rect.width.saturating_sub(status_width),
+                root_rect.height,
+                status_width.min(root_rect.width),
+                1,
+            ),
         );
     })?;
 
     Ok(())
 }
 
+/// Draw the configured prompt-like context line
+fn draw_context_line(f: &mut ratatui::Frame, rect: ratatui::layout::Rect, app: &mut App) {
+    use ratatui::widgets::Paragraph;
+
+    let execution_mode = app.execution_mode();
+    if let Some(context_line) = app.context_line.as_mut() {
+        let style = Style::default().fg(Color::DarkGray);
+        f.render_widget(Paragraph::new(Span::styled(context_line.render(execution_mode), style)), rect);
+    }
+}
+
+/// Draw the numbered list of favorites that can be inserted with Alt+number
+fn draw_favorites_bar(f: &mut ratatui::Frame, rect: ratatui::layout::Rect, app: &App) {
+    use ratatui::widgets::Paragraph;
+
+    let number_style = Style::default().fg(Color::Black).bg(Color::Cyan);
+    let spans = app
+        .config
+        .favorites
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, favorite)| {
+            [
+                Span::styled(format!(" {} ", idx + 1), number_style),
+                Span::raw(format!(" {}  ", favorite)),
+            ]
+        })
+        .collect::<Vec<_>>();
+    f.render_widget(Paragraph::new(Line::from(spans)), rect);
+}
+
+/// Highlight the given lines as shell code, keeping the lines separate.
+pub fn highlight_shell_lines(lines: &[String]) -> Vec<Line<'_>> {
+    highlight_lines(lines, &SH_SYNTAX)
+}
+
+/// Highlight the given lines with the given syntax, keeping the lines separate.
+pub fn highlight_lines<'a, S: AsRef<str>>(lines: &'a [S], syntax: &SyntaxReference) -> Vec<Line<'a>> {
+    let mut highlighter = HighlightLines::new(syntax, &THEME);
+    lines
+        .iter()
+        .map(|line| {
+            let line = line.as_ref();
+            // syntect needs the line endings to correctly carry state (like open quotes) over to the next line
+            let line_with_ending = format!("{}\n", line);
+            let Ok(result) = highlighter.highlight_line(&line_with_ending, syntax_set()) else {
+                return Line::from(line);
+            };
+            let mut offset = 0;
+            let spans = result
+                .iter()
+                .filter_map(|(style, part)| {
+                    let start = offset;
+                    offset += part.len();
+                    let end = offset.min(line.len());
+                    (start < end).then(|| Span::styled(&line[start..end], highlight_style_to_ratatui_style(style)))
+                })
+                .collect::<Vec<_>>();
+            Line::from(spans)
+        })
+        .collect()
+}
+
 /// Converts syntect highlighting style to ratatui style
 pub fn highlight_style_to_ratatui_style(style: &highlighting::Style) -> Style {
     let fg = style.foreground;
@@ -184,7 +440,7 @@ pub fn highlight_style_to_ratatui_style(style: &highlighting::Style) -> Style {
 }
 
 /// Creates a default styled block with a title
-pub fn make_default_block(title: &str, selected: bool) -> Block {
+pub fn make_default_block(title: &str, selected: bool) -> Block<'_> {
     let title_style = if selected {
         Style::default().fg(Color::Black).bg(Color::Cyan)
     } else {
@@ -196,6 +452,59 @@ pub fn make_default_block(title: &str, selected: bool) -> Block {
         .borders(Borders::ALL)
 }
 
+/// The key hints that fit into the width, dropping the ones at the end that don't.
+fn key_hints(hints: &[(&str, &str)], max_width: usize) -> Option<String> {
+    let mut line = String::new();
+    for (key, action) in hints {
+        let hint = format!(" {}: {} ", key, action);
+        if line.width() + hint.width() > max_width {
+            break;
+        }
+        line.push_str(&hint);
+    }
+    (!line.is_empty()).then_some(line)
+}
+
+/// The exit code of the last command, whether commands run while typing and their timeout, followed by the help hint.
+fn status_bar(app: &App) -> Line<'static> {
+    let red = Style::default().fg(Color::Red);
+    let mut parts = Vec::new();
+    match app.last_exit_code {
+        // the code of the previous command would be mistaken for the one of the running command
+        _ if app.is_processing_state.is_some() => {}
+        Some(0) => parts.push(Span::styled("exit 0", Style::default().fg(Color::Green))),
+        Some(code) => parts.push(Span::styled(format!("exit {}", code), red)),
+        None if !app.command_error.is_empty() => parts.push(Span::styled("failed", red)),
+        None => {}
+    }
+    let mode = match (app.persistent_session, app.autoeval_mode) {
+        (true, _) => "session",
+        (false, true) => "autoeval",
+        (false, false) => "manual",
+    };
+    parts.push(Span::raw(if app.pty_mode {
+        format!("{} pty", mode)
+    } else {
+        mode.to_string()
+    }));
+    let timeout = app.config.cmd_timeout;
+    parts.push(Span::raw(if timeout.subsec_millis() == 0 {
+        format!("timeout {}s", timeout.as_secs())
+    } else {
+        format!("timeout {}ms", timeout.as_millis())
+    }));
+    parts.push(Span::raw("Help: F1"));
+
+    let mut spans = Vec::new();
+    for (idx, part) in parts.into_iter().enumerate() {
+        if idx > 0 {
+            spans.push(Span::raw(" · "));
+        }
+        spans.push(part);
+    }
+    Line::from(spans)
+}
+
 /// Display an animation indicator, state being the current frame of the 6-frame animation.
 pub fn display_processing_state(state: Option<u8>) -> &'static str {
     match state {
@@ -209,11 +518,150 @@ pub fn display_processing_state(state: Option<u8>) -> &'static str {
     }
 }
 
-/// Truncates a string to a specific length and adds an ellipsis if needed
-pub fn truncate_with_ellipsis(mut line: String, length: usize) -> String {
-    if line.len() > length - 5 {
-        line.truncate(length - 5);
-        line.push_str("...");
+/// Width of a line number gutter for the given amount of lines, including the separating space
+pub fn line_number_gutter_width(line_count: usize) -> usize {
+    line_count.max(1).to_string().len() + 1
+}
+
+/// Prefixes the lines with right-aligned line numbers, filling a gutter of the given width
+pub fn prepend_line_numbers(lines: &mut [Line], gutter_width: usize) {
+    let style = Style::default().fg(Color::DarkGray);
+    for (idx, line) in lines.iter_mut().enumerate() {
+        line.spans.insert(
+            0,
+            Span::styled(format!("{:>width$} ", idx + 1, width = gutter_width - 1), style),
+        );
+    }
+}
+
+/// Replaces tabs with spaces up to the next tab stop. With a marker style, the first column of each tab is shown as `→`.
+pub fn expand_tabs(line: Line<'_>, tab_width: usize, marker_style: Option<Style>) -> Line<'static> {
+    let mut new_spans = Vec::new();
+    let mut column = 0;
+    for span in line.spans {
+        let mut text = String::new();
+        for c in span.content.chars() {
+            if c != '\t' {
+                column += unicode_width::UnicodeWidthChar::width(c).unwrap_or(0);
+                text.push(c);
+                continue;
+            }
+            let advance = crate::util::tab_advance(column, tab_width);
+            column += advance;
+            match marker_style {
+                Some(marker_style) => {
+                    if !text.is_empty() {
+                        new_spans.push(Span::styled(std::mem::take(&mut text), span.style));
+                    }
+                    new_spans.push(Span::styled("→", span.style.patch(marker_style)));
+                    text.push_str(&" ".repeat(advance - 1));
+                }
+                None => text.push_str(&" ".repeat(advance)),
+            }
+        }
+        if !text.is_empty() {
+            new_spans.push(Span::styled(text, span.style));
+        }
+    }
+    let mut new_line = Line::from(new_spans);
+    new_line.style = line.style;
+    new_line.alignment = line.alignment;
+    new_line
+}
+
+/// Truncates a string to at most `max_width` columns, ending it with an ellipsis if it had to be cut
+pub fn truncate_with_ellipsis(line: String, max_width: usize) -> String {
+    if line.width() <= max_width {
+        return line;
+    }
+    format!("{}...", truncate_to_width(&line, max_width.saturating_sub(3)))
+}
+
+/// Drops the first `columns` display columns of a styled line, keeping the styles of its spans.
+/// A wide character that is only partially dropped is replaced by spaces.
+pub fn skip_line_columns(line: Line<'_>, columns: usize) -> Line<'static> {
+    let mut new_line = Line::default().style(line.style);
+    let mut remaining = columns;
+    for span in line.spans {
+        let skipped = truncate_to_width(&span.content, remaining);
+        remaining -= skipped.width();
+        let mut rest = &span.content[skipped.len()..];
+        if remaining > 0
+            && let Some(c) = rest.chars().next()
+        {
+            rest = &rest[c.len_utf8()..];
+            let partial_width = unicode_width::UnicodeWidthChar::width(c)
+                .unwrap_or(0)
+                .saturating_sub(remaining);
+            new_line.spans.push(Span::styled(" ".repeat(partial_width), span.style));
+            remaining = 0;
+        }
+        if !rest.is_empty() {
+            new_line.spans.push(Span::styled(rest.to_string(), span.style));
+        }
+    }
+    new_line
+}
+
+/// Truncates a styled line like [`truncate_with_ellipsis`], keeping the styles of its spans.
+/// Returns the line together with the number of characters that were cut off.
+pub fn truncate_line_with_ellipsis(line: Line<'_>, max_width: usize) -> (Line<'static>, usize) {
+    let mut new_line = Line::default().style(line.style);
+    new_line.alignment = line.alignment;
+    if line.width() <= max_width {
+        new_line.spans = line
+            .spans
+            .into_iter()
+            .map(|span| Span::styled(span.content.into_owned(), span.style))
+            .collect();
+        return (new_line, 0);
+    }
+    let mut remaining_width = max_width.saturating_sub(3);
+    let mut cut_chars = 0;
+    // the ellipsis takes the style of the span it replaces
+    let mut ellipsis_style = None;
+    for span in line.spans {
+        let kept = truncate_to_width(&span.content, remaining_width);
+        remaining_width -= kept.width();
+        if !kept.is_empty() {
+            new_line.spans.push(Span::styled(kept.to_string(), span.style));
+        }
+        if kept.len() < span.content.len() {
+            cut_chars += span.content[kept.len()..].chars().count();
+            ellipsis_style.get_or_insert(span.style);
+        }
+    }
+    new_line.spans.push(Span::styled("...", ellipsis_style.unwrap_or_default()));
+    (new_line, cut_chars)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_syntax_set() {
+        let dir = std::env::temp_dir().join(format!("pipr-syntaxes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let syntax = concat!(
+            "%YAML 1.2\n---\nname: Pipeline\nfile_extensions: [pipeline]\nscope: source.pipeline\n",
+            "contexts:\n  main:\n    - match: '\\|'\n      scope: keyword.operator\n",
+        );
+        std::fs::write(dir.join("pipeline.sublime-syntax"), syntax).unwrap();
+        let syntax_set = build_syntax_set(&dir).unwrap();
+        assert!(syntax_set.find_syntax_by_extension("pipeline").is_some());
+        assert!(syntax_set.find_syntax_by_extension("sh").is_some());
+
+        std::fs::write(dir.join("broken.sublime-syntax"), "name: [").unwrap();
+        assert!(build_syntax_set(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_key_hints() {
+        let hints = [("Enter", "use"), ("Del", "delete")];
+        assert_eq!(key_hints(&hints, 40).as_deref(), Some(" Enter: use  Del: delete "));
+        assert_eq!(key_hints(&hints, 20).as_deref(), Some(" Enter: use "));
+        assert_eq!(key_hints(&hints, 5), None);
     }
-    line
 }
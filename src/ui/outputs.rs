@@ -1,23 +1,175 @@
 use ansi_to_tui::IntoText;
 use ratatui::{
-    layout::{Constraint::Percentage, Direction, Layout, Rect},
-    text::Text,
-    widgets::Paragraph,
     Frame,
+    layout::{
+        Constraint::{Length, Min, Percentage},
+        Direction, Layout, Rect,
+    },
+    style::{Modifier, Style},
+    text::{Line, Span, Text},
+    widgets::Paragraph,
 };
+use std::borrow::Cow;
+use unicode_width::UnicodeWidthStr;
 
-use crate::ui::{display_processing_state, make_default_block};
+use crate::app::{App, PartialRun};
+use crate::command_evaluation::merge_stderr_lines;
+use crate::pipr_config::StderrDisplay;
+use crate::ui::{
+    JSON_SYNTAX, display_processing_state, expand_tabs, highlight_lines, line_number_gutter_width, make_default_block,
+    prepend_line_numbers, truncate_line_with_ellipsis, truncate_with_ellipsis,
+};
+use crate::util::{filter_lines, hex_dump};
 
-/// Draw command output and error sections
-pub fn draw_outputs(f: &mut Frame, rect: Rect, changed: bool, processing_state: Option<u8>, stdout: &str, stderr: &str) {
-    let text = stdout.into_text().unwrap_or_else(|_| Text::raw(stdout));
+/// Draw command output and error sections, and the output filter if there is one.
+/// Returns the cursor position within the filter while it is edited.
+pub fn draw_outputs(f: &mut Frame, rect: Rect, app: &App) -> Option<(u16, u16)> {
+    if let Some(line) = compact_output_line(app) {
+        draw_compact_output(f, rect, app, line);
+        return None;
+    }
+    let changed = app.input_state.content_str() == app.last_executed_cmd;
+    let line_limit = if app.output_expanded {
+        0
+    } else {
+        app.config.output_line_limit
+    };
+    // past outputs are kept as plain text, so they are shown without the views depending on the raw output
+    let past_output = app.shown_past_output();
+    let hex_dump = app
+        .output_hex_view
+        .then(|| hex_dump(&app.command_output_bytes))
+        .filter(|_| past_output.is_none());
+    let merged_output = if app.command_stderr_lines.is_empty() || app.output_hex_view || past_output.is_some() {
+        None
+    } else {
+        match app.config.stderr_display {
+            StderrDisplay::Split => None,
+            StderrDisplay::Merged => Some(merge_stderr_lines(&app.command_output_bytes, &app.command_stderr_lines, "")),
+            StderrDisplay::Tagged => Some(merge_stderr_lines(
+                &app.command_output_bytes,
+                &app.command_stderr_lines,
+                "\x1b[31mE| \x1b[0m",
+            )),
+        }
+    };
+    // stderr lines merged into the output would get lost when pretty-printing it
+    let pretty_json = app
+        .pretty_json_output
+        .as_deref()
+        .filter(|_| app.pretty_json && !app.output_hex_view && merged_output.is_none() && past_output.is_none());
+    let output = hex_dump
+        .as_deref()
+        .or(merged_output.as_deref())
+        .or(pretty_json)
+        .or(past_output.map(|past| past.output.as_str()))
+        .unwrap_or(&app.command_output);
+    // lines of the hex dump don't correspond to lines of the output, so it is never filtered
+    let filtered_output = app
+        .output_filter
+        .as_ref()
+        .filter(|filter| !app.output_hex_view && !filter.pattern.is_empty())
+        .map(|filter| filter_lines(output, &filter.pattern, app.config.output_filter_regex));
+    let matching_lines = match &filtered_output {
+        Some(Ok(filtered)) => Some(filtered.lines().count()),
+        _ => None,
+    };
+    let output = match &filtered_output {
+        Some(Ok(filtered)) => filtered.as_str(),
+        _ => output,
+    };
+    let stdout = limit_output_lines(output, line_limit);
+    let stderr = if past_output.is_some() {
+        ""
+    } else {
+        app.command_error.as_str()
+    };
+
+    let json_lines = pretty_json.map(|_| stdout.lines().collect::<Vec<_>>());
+    let mut text = if app.output_hex_view {
+        Text::raw(stdout.as_ref())
+    } else if let Some(json_lines) = json_lines.as_ref().filter(|_| app.config.highlighting_enabled) {
+        Text::from(highlight_lines(json_lines, &JSON_SYNTAX))
+    } else {
+        stdout.as_ref().into_text().unwrap_or_else(|_| Text::raw(stdout.as_ref()))
+    };
+    if !app.output_hex_view && stdout.contains('\t') {
+        expand_text_tabs(&mut text, app.config.tab_width);
+    }
+    let max_line_len = if app.output_expanded {
+        0
+    } else {
+        app.config.max_display_line_len
+    };
+    truncate_long_lines(&mut text, max_line_len);
+    // lines of a merged, pretty-printed or filtered output don't correspond to the lines that changed
+    if !app.output_hex_view
+        && merged_output.is_none()
+        && pretty_json.is_none()
+        && filtered_output.is_none()
+        && past_output.is_none()
+    {
+        highlight_changed_lines(&mut text, app, line_limit);
+    }
+    // the selection refers to the lines of stdout, which merged stderr lines would shift
+    let selected_line = app.output_selection.as_ref().filter(|_| merged_output.is_none());
+    if let Some(line) = selected_line.and_then(|selection| text.lines.get_mut(selection.line)) {
+        line.style = line.style.patch(Style::default().add_modifier(Modifier::REVERSED));
+    }
+    // the hex dump already shows offsets
+    if app.config.show_line_numbers && !app.output_hex_view {
+        // don't number the marker line for hidden output
+        let numbered_lines = if line_limit == 0 {
+            text.lines.len()
+        } else {
+            text.lines.len().min(line_limit)
+        };
+        prepend_line_numbers(&mut text.lines[..numbered_lines], line_number_gutter_width(numbered_lines));
+    }
 
     let stdout_title = format!(
-        "Output{}{}",
+        "Output{}{}{}{}{}{}{}{}{}{}{}",
+        output_line_count_badge(app, matching_lines),
+        past_output_badge(app),
+        if app.output_from_cache { " [Cached]" } else { "" },
+        match app.partial_run {
+            Some(PartialRun::Stage(stage, stage_count)) => format!(" [Stage {}/{}]", stage, stage_count),
+            Some(PartialRun::Selection) => " [Selection]".to_string(),
+            None => String::new(),
+        },
+        if app.output_follow { " [Follow]" } else { "" },
+        if app.output_hex_view { " [Hex]" } else { "" },
+        if pretty_json.is_some() { " [JSON]" } else { "" },
         if changed { "" } else { " [+]" },
-        display_processing_state(processing_state)
+        display_processing_state(app.is_processing_state),
+        slow_command_hint(app),
+        output_selection_badge(app)
     );
 
+    let [filter_chunk, rect] = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Length(if app.output_filter.is_some() { 3 } else { 0 }), Min(0)])
+        .areas(rect);
+    let mut filter_cursor = None;
+    if let Some(filter) = &app.output_filter {
+        let filter_title = format!(
+            "Filter{}{}",
+            if app.config.output_filter_regex { " (regex)" } else { "" },
+            if matches!(filtered_output, Some(Err(_))) {
+                " [Invalid pattern]"
+            } else {
+                ""
+            }
+        );
+        f.render_widget(
+            Paragraph::new(filter.pattern.as_str()).block(make_default_block(&filter_title, filter.editing)),
+            filter_chunk,
+        );
+        if filter.editing {
+            filter_cursor = Some((filter_chunk.x + 1 + filter.pattern.width() as u16, filter_chunk.y + 1));
+        }
+    }
+
     let [stdout_chunk, stderr_chunk] = Layout::default()
         .direction(Direction::Vertical)
         .constraints(if stderr.is_empty() {
@@ -28,15 +180,164 @@ pub fn draw_outputs(f: &mut Frame, rect: Rect, changed: bool, processing_state:
         .areas(rect);
 
     f.render_widget(
-        Paragraph::new(text).block(make_default_block(&stdout_title, false)),
+        Paragraph::new(text)
+            .block(make_default_block(&stdout_title, false))
+            .scroll((app.output_scroll, 0)),
         stdout_chunk,
     );
 
     if !stderr.is_empty() {
-        let stderr_text = stderr.into_text().unwrap_or_else(|_| Text::raw(stderr));
+        let mut stderr_text = stderr.into_text().unwrap_or_else(|_| Text::raw(stderr));
+        if stderr.contains('\t') {
+            expand_text_tabs(&mut stderr_text, app.config.tab_width);
+        }
+        truncate_long_lines(&mut stderr_text, max_line_len);
         f.render_widget(
             Paragraph::new(stderr_text).block(make_default_block("Stderr", false)),
             stderr_chunk,
         );
     }
+    filter_cursor
+}
+
+/// The output, if it is a single line that is shown without the output pane.
+/// Anything that needs the pane, like stderr, a filter or another view of the output, keeps it.
+fn compact_output_line(app: &App) -> Option<&str> {
+    let plain_view = !app.output_hex_view
+        && app.output_filter.is_none()
+        && app.shown_past_output().is_none()
+        && !(app.pretty_json && app.pretty_json_output.is_some());
+    let single_line =
+        app.command_output_line_count == Some(1) && app.command_error.is_empty() && app.command_stderr_lines.is_empty();
+    if !(app.config.compact_output && plain_view && single_line) {
+        return None;
+    }
+    Some(app.command_output.trim_end_matches('\n'))
+}
+
+fn draw_compact_output(f: &mut Frame, rect: Rect, app: &App, line: &str) {
+    let mut text = line.into_text().unwrap_or_else(|_| Text::raw(line));
+    if line.contains('\t') {
+        expand_text_tabs(&mut text, app.config.tab_width);
+    }
+    let mut spans = vec![Span::styled("» ", Style::default().add_modifier(Modifier::DIM))];
+    spans.extend(text.lines.into_iter().next().map(|line| line.spans).unwrap_or_default());
+    spans.push(Span::raw(display_processing_state(app.is_processing_state)));
+    let [line_rect, _] = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Length(1), Min(0)])
+        .areas(rect);
+    f.render_widget(Paragraph::new(Line::from(spans)), line_rect);
+}
+
+/// Highlight the lines that changed with the last output, until the configured duration passed.
+fn highlight_changed_lines(text: &mut Text<'_>, app: &App, line_limit: usize) {
+    let (Some((changed_lines, changed_at)), Some(duration)) = (&app.changed_output_lines, app.config.output_change_highlight)
+    else {
+        return;
+    };
+    if changed_at.elapsed() >= duration {
+        return;
+    }
+    let style = Style::default().bg(app.config.output_change_highlight_color);
+    // the marker line for hidden output isn't part of the output
+    for &idx in changed_lines.iter().filter(|&&idx| line_limit == 0 || idx < line_limit) {
+        if let Some(line) = text.lines.get_mut(idx) {
+            line.style = line.style.patch(style);
+        }
+    }
+}
+
+fn expand_text_tabs(text: &mut Text<'_>, tab_width: usize) {
+    for line in text.lines.iter_mut() {
+        *line = expand_tabs(std::mem::take(line), tab_width, None);
+    }
+}
+
+/// Cut off lines that are longer than `max_len` columns, noting how many characters were hidden.
+/// A limit of 0 disables the limit.
+fn truncate_long_lines(text: &mut Text<'_>, max_len: usize) {
+    if max_len == 0 {
+        return;
+    }
+    let marker_style = Style::default().add_modifier(Modifier::DIM);
+    for line in text.lines.iter_mut() {
+        if line.width() <= max_len {
+            continue;
+        }
+        let (mut truncated, cut_chars) = truncate_line_with_ellipsis(std::mem::take(line), max_len);
+        truncated.push_span(Span::styled(
+            format!(" [+{} chars, press F8 to expand]", cut_chars),
+            marker_style,
+        ));
+        *line = truncated;
+    }
+}
+
+/// Number of lines of the last successful output, if enabled. With a filter, the number of matching lines is shown too.
+fn output_line_count_badge(app: &App, matching_lines: Option<usize>) -> String {
+    let line_count = match app.shown_past_output() {
+        Some(past_output) => Some(past_output.output.lines().count()),
+        None => app.command_output_line_count,
+    };
+    match (line_count, matching_lines) {
+        (Some(count), Some(matching)) if app.config.show_output_line_count => format!(" ({} of {} lines)", matching, count),
+        (Some(1), None) if app.config.show_output_line_count => " (1 line)".to_string(),
+        (Some(count), None) if app.config.show_output_line_count => format!(" ({} lines)", count),
+        _ => String::new(),
+    }
+}
+
+/// Which of the past outputs is shown, and of what command.
+fn past_output_badge(app: &App) -> String {
+    let (Some(idx), Some(past_output)) = (app.shown_past_output, app.shown_past_output()) else {
+        return String::new();
+    };
+    let command = past_output.command.lines().next().unwrap_or_default();
+    format!(" [Earlier {}/{}: {}]", idx + 1, app.past_outputs.len(), command)
+}
+
+/// Elapsed time of a command that has been running for longer than the configured threshold, together with its timeout,
+/// or the number of running commands while replaced or timed out ones haven't exited yet.
+fn slow_command_hint(app: &App) -> String {
+    let running_commands = app.execution_handler.running_command_count();
+    if running_commands > 1 {
+        return format!(" [{} commands running]", running_commands);
+    }
+    match (app.is_processing_state, app.command_started, app.config.slow_command_warn) {
+        (Some(_), Some(started), Some(threshold)) if started.elapsed() >= threshold => format!(
+            " [still running: {}s / {}s timeout]",
+            started.elapsed().as_secs(),
+            app.config.cmd_timeout.as_secs()
+        ),
+        _ => String::new(),
+    }
+}
+
+/// The text that Return would insert into the input, while selecting it from the output.
+fn output_selection_badge(app: &App) -> String {
+    match app.selected_output_text() {
+        Some(text) => format!(" [Insert: {}]", truncate_with_ellipsis(text, 40)),
+        None if app.output_selection.is_some() => " [Insert: nothing]".to_string(),
+        None => String::new(),
+    }
+}
+
+/// Cut the output down to its first `limit` lines, adding a marker that shows how many lines were hidden.
+/// A limit of 0 disables the limit.
+fn limit_output_lines(output: &str, limit: usize) -> Cow<'_, str> {
+    if limit == 0 {
+        return Cow::Borrowed(output);
+    }
+    match output.match_indices('\n').nth(limit - 1) {
+        Some((cut_idx, _)) if cut_idx + 1 < output.len() => {
+            let hidden_lines = output[cut_idx + 1..].lines().count();
+            Cow::Owned(format!(
+                "{}\n[... {} more lines, press F8 to expand]",
+                &output[..cut_idx],
+                hidden_lines
+            ))
+        }
+        _ => Cow::Borrowed(output),
+    }
 }
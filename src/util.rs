@@ -1,3 +1,124 @@
+use std::collections::HashMap;
+
+/// number of bytes shown per line of a hex dump
+const HEX_DUMP_BYTES_PER_LINE: usize = 16;
+
+/// Format bytes like `hexdump -C`: an offset, the bytes in hex and their printable ASCII characters.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(HEX_DUMP_BYTES_PER_LINE)
+        .enumerate()
+        .map(|(idx, chunk)| {
+            let hex = (0..HEX_DUMP_BYTES_PER_LINE)
+                .map(|i| match chunk.get(i) {
+                    Some(b) => format!("{:02x}", b),
+                    None => "  ".to_string(),
+                })
+                .collect::<Vec<_>>();
+            let ascii = chunk
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect::<String>();
+            format!(
+                "{:08x}  {}  {}  |{}|",
+                idx * HEX_DUMP_BYTES_PER_LINE,
+                hex[..8].join(" "),
+                hex[8..].join(" "),
+                ascii
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Number of columns a tab at the given column takes up, reaching up to the next tab stop.
+pub fn tab_advance(column: usize, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    tab_width - column % tab_width
+}
+
+/// Number of columns the text takes up when displayed, with tabs expanded to the next tab stop.
+pub fn display_width(text: &str, tab_width: usize) -> usize {
+    text.chars().fold(0, |column, c| match c {
+        '\t' => column + tab_advance(column, tab_width),
+        _ => column + unicode_width::UnicodeWidthChar::width(c).unwrap_or(0),
+    })
+}
+
+/// The longest start of the text that takes up at most `max_width` columns, never splitting a character.
+pub fn truncate_to_width(text: &str, max_width: usize) -> &str {
+    let mut width = 0;
+    for (idx, c) in text.char_indices() {
+        width += unicode_width::UnicodeWidthChar::width(c).unwrap_or(0);
+        if width > max_width {
+            return &text[..idx];
+        }
+    }
+    text
+}
+
+/// A runnable shell script running the command, starting with the shebang and header lines unless they are empty.
+pub fn shell_script(command: &str, shebang: &str, header: &str) -> String {
+    let mut script = String::new();
+    for line in [shebang, header] {
+        if !line.is_empty() {
+            script.push_str(line);
+            script.push('\n');
+        }
+    }
+    if !script.is_empty() {
+        script.push('\n');
+    }
+    script.push_str(command);
+    script.push('\n');
+    script
+}
+
+/// Indices of the lines of `new` that aren't in `old`. Lines that were only moved around don't count as changed.
+pub fn changed_lines(old: &str, new: &str) -> Vec<usize> {
+    let mut old_lines: HashMap<&str, usize> = HashMap::new();
+    for line in old.lines() {
+        *old_lines.entry(line).or_default() += 1;
+    }
+    new.lines()
+        .enumerate()
+        .filter(|(_, line)| match old_lines.get_mut(line) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        })
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// The output pretty-printed, if it is a single JSON object or array. Keys keep their order.
+pub fn pretty_json(output: &str) -> Option<String> {
+    let trimmed = output.trim();
+    if !(trimmed.starts_with('{') || trimmed.starts_with('[')) {
+        return None;
+    }
+    let value: serde_json::Value = serde_json::from_str(trimmed).ok()?;
+    serde_json::to_string_pretty(&value).ok().map(|pretty| pretty + "\n")
+}
+
+/// Keep only the lines containing `pattern`, which is either taken literally or as a regular expression.
+/// Color codes are ignored when matching, but kept in the result.
+pub fn filter_lines(text: &str, pattern: &str, is_regex: bool) -> Result<String, regex::Error> {
+    let pattern = if is_regex {
+        pattern.to_string()
+    } else {
+        regex::escape(pattern)
+    };
+    let regex = regex::Regex::new(&pattern)?;
+    Ok(text
+        .lines()
+        .filter(|line| regex.is_match(&strip_ansi(line)))
+        .map(|line| format!("{}\n", line))
+        .collect())
+}
+
 #[cfg(test)]
 mod stringext_test {
     use super::*;
@@ -22,6 +143,126 @@ mod stringext_test {
         assert_eq!("abc".get_full_char_at(1), Some("b"));
         assert_eq!("aääc".get_full_char_at(1), Some("ä"));
     }
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(
+            hex_dump(b"Hello, world!\n\x00\xffabc"),
+            "00000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 ff  |Hello, world!...|\n\
+             00000010  61 62 63                                          |abc|"
+        );
+        assert_eq!(hex_dump(b""), "");
+    }
+
+    #[test]
+    fn test_truncate_to_width() {
+        assert_eq!(truncate_to_width("abc", 5), "abc");
+        assert_eq!(truncate_to_width("abc", 2), "ab");
+        assert_eq!(truncate_to_width("äöü", 2), "äö");
+        assert_eq!(truncate_to_width("日本語", 5), "日本");
+        assert_eq!(truncate_to_width("abc", 0), "");
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("abc", 8), 3);
+        assert_eq!(display_width("a\tb", 8), 9);
+        assert_eq!(display_width("abcd\tb\t", 4), 12);
+        assert_eq!(display_width("\t\t", 2), 4);
+        assert_eq!(display_width("ä\t", 0), 2, "a tab width of 0 is treated as 1");
+    }
+
+    #[test]
+    fn test_shell_script() {
+        assert_eq!(
+            shell_script("ls |\nwc -l", "#!/bin/sh", "set -eu"),
+            "#!/bin/sh\nset -eu\n\nls |\nwc -l\n"
+        );
+        assert_eq!(shell_script("ls", "#!/bin/sh", ""), "#!/bin/sh\n\nls\n");
+        assert_eq!(shell_script("ls", "", ""), "ls\n");
+    }
+
+    #[test]
+    fn test_changed_lines() {
+        assert_eq!(changed_lines("a\nb\nc\n", "a\nx\nb\nc\n"), vec![1]);
+        assert_eq!(changed_lines("a\nb\n", "b\na\n"), Vec::<usize>::new());
+        assert_eq!(changed_lines("a\n", "a\na\n"), vec![1]);
+        assert_eq!(changed_lines("", "a\nb"), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_pretty_json() {
+        assert_eq!(
+            pretty_json("{\"b\":1,\"a\":[true]}\n").unwrap(),
+            "{\n  \"b\": 1,\n  \"a\": [\n    true\n  ]\n}\n"
+        );
+        assert_eq!(pretty_json("[1, 2"), None);
+        assert_eq!(pretty_json("{} {}"), None);
+        assert_eq!(pretty_json("42"), None);
+    }
+
+    #[test]
+    fn test_filter_lines() {
+        let text = "foo.rs\n\x1b[31mbar\x1b[0m.rs\nfoors\n";
+        assert_eq!(filter_lines(text, "o.r", false).unwrap(), "foo.rs\n");
+        assert_eq!(filter_lines(text, "o.r", true).unwrap(), "foo.rs\nfoors\n");
+        assert_eq!(filter_lines(text, "^bar", true).unwrap(), "\x1b[31mbar\x1b[0m.rs\n");
+        assert!(filter_lines(text, "(", true).is_err());
+        assert_eq!(filter_lines(text, "(", false).unwrap(), "");
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("abc"), "'abc'");
+        assert_eq!(shell_quote("my file.txt"), "'my file.txt'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote("$HOME/*"), "'$HOME/*'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(strip_ansi("\x1b[1;31mred\x1b[0m text"), "red text");
+        assert_eq!(strip_ansi("\x1b]8;;http://x\x07link\x1b]8;;\x1b\\"), "link");
+        assert_eq!(strip_ansi("a\x1b(Bb"), "ab");
+        assert_eq!(strip_ansi("äöü plain"), "äöü plain");
+    }
+}
+
+/// Quote a string so that a POSIX shell parses it as a single literal argument.
+/// Uses single quotes, closing and reopening them around every embedded `'`.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Remove ANSI escape sequences (colors, cursor movement, hyperlinks, ...) from a string.
+pub fn strip_ansi(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters until a final byte in the range @ to ~
+            Some('[') => while chars.next().is_some_and(|c| !('@'..='~').contains(&c)) {},
+            // OSC: terminated by BEL or ESC \
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            // character set selection, followed by one more character
+            Some('(') | Some(')') => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    result
 }
 
 pub trait StringExt {